@@ -0,0 +1,40 @@
+// the shader cache key has to change whenever the slang compiler does, so the
+// locked versions of the slang crates and the sdk they link are embedded
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-env-changed=SLANG_DIR");
+
+    // cargo writes the lock file before build scripts run
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let mut versions = lock
+        .split("[[package]]")
+        .filter_map(|package| {
+            let field = |key: &str| {
+                package.lines().find_map(|line| {
+                    line.strip_prefix(key)?
+                        .trim()
+                        .strip_prefix("= ")?
+                        .strip_prefix('"')?
+                        .strip_suffix('"')
+                })
+            };
+            let name = field("name")?;
+            name.starts_with("shader-slang")
+                .then(|| format!("{name}-{}", field("version").unwrap_or("unknown")))
+        })
+        .collect::<Vec<_>>();
+    if versions.is_empty() {
+        println!(
+            "cargo:warning=Cargo.lock has no shader-slang entry, the shader cache key can't track it"
+        );
+        versions.push("shader-slang-unknown".to_string());
+    }
+    if let Ok(slang_dir) = std::env::var("SLANG_DIR") {
+        versions.push(slang_dir);
+    }
+
+    println!(
+        "cargo:rustc-env=SLANG_COMPILER_VERSION={}",
+        versions.join(",")
+    );
+}
@@ -2,25 +2,47 @@ use anyhow::Result;
 use bonfire::vulkan::{
     RenderBackend, RenderBackendConfig,
     command_ring_buffer::CommandRingBuffer,
-    device::FRAMES_IN_FLIGHT,
-    pipeline::{self, RasterPipeline, RasterPipelineDesc, ShaderDesc},
-    shader_compiler::ShaderCompiler,
+    device::{DeviceFeature, QueueType, SemaphoreSubmit, SubmitDesc, SyncMode},
+    gpu_profiler::GpuProfiler,
+    instance::ValidationConfig,
+    pipeline::{self, BlendDesc, RasterPipeline, RasterPipelineDesc, ShaderDesc},
+    profiling,
+    rendering::{self, LoadOp},
+    shader_compiler::{ShaderCompiler, ShaderCompilerOptions},
+    shader_watcher::ShaderWatcher,
+    swapchain::{PresentModePreference, SwapchainStatus},
+    timeline_semaphore::TimelineSemaphore,
 };
-use log::{info, warn};
+use log::{info, trace, warn};
 use vk_sync::{AccessType, ImageLayout};
 use winit::{
     application::ApplicationHandler,
     event::WindowEvent,
     event_loop::{ActiveEventLoop, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowId},
 };
 
 use ash::vk;
 
+#[cfg(feature = "egui")]
+struct DebugUi {
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: bonfire::vulkan::egui_renderer::EguiRenderer,
+}
+
 struct Renderer {
     render_backend: RenderBackend,
     command_ring_buffer: CommandRingBuffer,
     triangle_pipeline: RasterPipeline,
+    shader_compiler: ShaderCompiler,
+    shader_watcher: ShaderWatcher,
+    gpu_profiler: GpuProfiler,
+    #[cfg(feature = "egui")]
+    debug_ui: DebugUi,
+    capture_requested: bool,
+    clear_color: [f32; 4],
 }
 
 #[derive(Default)]
@@ -38,43 +60,110 @@ impl ApplicationHandler for App {
 
         let render_config = RenderBackendConfig {
             validation_layers: true,
-            vsync: true,
+            validation: ValidationConfig::default(),
+            present_mode: PresentModePreference::AutoVsync,
+            hdr: false,
+            pipeline_cache_path: Some("pipeline_cache.bin".into()),
+            frames_in_flight: 2,
+            sync_mode: SyncMode::Timeline,
+            required_features: Vec::new(),
+            requested_features: vec![
+                DeviceFeature::GeometryShader,
+                DeviceFeature::TessellationShader,
+                DeviceFeature::FillModeNonSolid,
+                DeviceFeature::WideLines,
+                DeviceFeature::MultiDrawIndirect,
+                DeviceFeature::TextureCompressionBc,
+            ],
         };
 
         let render_backend =
             RenderBackend::new(&window, &render_config).expect("Failed to create render backend");
 
         let command_ring_buffer = CommandRingBuffer::builder(render_backend.device.clone())
-            .num_pools(FRAMES_IN_FLIGHT)
+            .num_pools(render_backend.device.frames_in_flight())
             .primary_buffers_per_pool(1)
             .build()
             .expect("Failed to build command buffer manager");
 
-        let triangle_vert_shader = ShaderCompiler::compile_slang("triangle/triangle_vert.slang")
-            .expect("Failed to compile vert shader");
+        let shader_compiler = ShaderCompiler::new().with_cache_dir("shader_cache");
+        let shader_options = ShaderCompilerOptions::default();
+
+        let triangle_vert_shader = shader_compiler
+            .compile_slang("triangle/triangle_vert.slang", "main", &shader_options)
+            .unwrap_or_else(|e| panic!("Failed to compile vert shader: {e:#}"));
         let triangle_vert = ShaderDesc::new(triangle_vert_shader, pipeline::ShaderStage::Vertex);
-        let triangle_frag_shader = ShaderCompiler::compile_slang("triangle/triangle_frag.slang")
-            .expect("Failed to compile frag shader");
+        let triangle_frag_shader = shader_compiler
+            .compile_slang("triangle/triangle_frag.slang", "main", &shader_options)
+            .unwrap_or_else(|e| panic!("Failed to compile frag shader: {e:#}"));
         let triangle_frag = ShaderDesc::new(triangle_frag_shader, pipeline::ShaderStage::Fragment);
 
+        let swapchain_format = render_backend.swapchain.as_ref().unwrap().format();
         let triangle_pipeline_desc = RasterPipelineDesc {
             shaders: vec![triangle_vert, triangle_frag],
-            color_attachments: vec![vk::Format::B8G8R8A8_SRGB],
+            color_attachments: vec![swapchain_format],
+            blend: vec![BlendDesc::alpha()],
+            ..Default::default()
         };
 
         let triangle_pipeline =
             pipeline::create_raster_pipeline(render_backend.device.clone(), triangle_pipeline_desc)
                 .unwrap();
+        render_backend
+            .device
+            .set_debug_name(triangle_pipeline.pipeline, "triangle");
+
+        let shader_watcher = ShaderWatcher::new(shader_compiler.search_paths())
+            .expect("Failed to create shader watcher");
+
+        let gpu_profiler = GpuProfiler::new(render_backend.device.clone(), 16)
+            .expect("Failed to create gpu profiler");
+
+        #[cfg(feature = "egui")]
+        let debug_ui = {
+            let context = egui::Context::default();
+            let state = egui_winit::State::new(
+                context.clone(),
+                egui::ViewportId::ROOT,
+                &window,
+                None,
+                None,
+                None,
+            );
+            let renderer = bonfire::vulkan::egui_renderer::EguiRenderer::new(
+                render_backend.device.clone(),
+                &shader_compiler,
+                swapchain_format,
+            )
+            .expect("Failed to create egui renderer");
+            DebugUi {
+                context,
+                state,
+                renderer,
+            }
+        };
 
         self.window = Some(window);
         self.renderer = Some(Renderer {
             render_backend,
             command_ring_buffer,
             triangle_pipeline,
+            shader_compiler,
+            shader_watcher,
+            gpu_profiler,
+            #[cfg(feature = "egui")]
+            debug_ui,
+            capture_requested: false,
+            clear_color: [0.02, 0.02, 0.03, 1.0],
         });
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        #[cfg(feature = "egui")]
+        if let (Some(window), Some(renderer)) = (self.window.as_ref(), self.renderer.as_mut()) {
+            let _ = renderer.debug_ui.state.on_window_event(window, &event);
+        }
+
         match event {
             WindowEvent::CloseRequested => {
                 info!("The close button was pressed; stopping");
@@ -82,221 +171,296 @@ impl ApplicationHandler for App {
             }
             WindowEvent::RedrawRequested => {
                 let renderer = self.renderer.as_mut().unwrap();
+
+                // any change reloads everything since shaders can import each other
+                if !renderer.shader_watcher.changed_files().is_empty()
+                    && let Err(e) = renderer.triangle_pipeline.reload(&renderer.shader_compiler)
+                {
+                    warn!("Keeping previous triangle pipeline: {e:#}");
+                }
+
                 let render_backend = &mut renderer.render_backend;
-                let vk_device = &render_backend.device.raw;
-                let swapchain = &mut render_backend.swapchain;
+                let swapchain = render_backend.swapchain.as_mut().unwrap();
+
+                // the next resize rebuilds the swapchain and requests a redraw
+                if swapchain.is_minimized() {
+                    return;
+                }
 
                 render_backend.device.begin_frame().expect("begin frame");
 
-                let swapchain_image = swapchain.acquire_next_image().expect("acquire next image");
+                match renderer.gpu_profiler.collect() {
+                    Ok(zones) => zones.iter().for_each(|zone| {
+                        trace!("GPU {}: {:.3}ms", zone.name, zone.duration_ms);
+                    }),
+                    Err(e) => warn!("Failed to collect gpu timings: {e:#}"),
+                }
+
+                let Some(swapchain_image) =
+                    swapchain.acquire_next_image().expect("acquire next image")
+                else {
+                    let window = self.window.as_ref().unwrap();
+                    render_backend
+                        .resize(window_extent(window))
+                        .expect("Failed to resize render backend");
+                    window.request_redraw();
+                    return;
+                };
 
                 renderer
                     .command_ring_buffer
                     .reset_pool(0)
                     .expect("failed to reset command pool");
 
-                let command_buffer = renderer.command_ring_buffer.get_next_primary_buffer(0);
+                let mut command_buffer = renderer.command_ring_buffer.next_primary(0);
+                command_buffer.begin().expect("begin command buffer");
 
-                unsafe {
-                    vk_device
-                        .reset_command_buffer(
-                            command_buffer,
-                            vk::CommandBufferResetFlags::default(),
-                        )
-                        .expect("reset command buffer");
+                let color_range = vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: vk::REMAINING_MIP_LEVELS,
+                    base_array_layer: 0,
+                    layer_count: vk::REMAINING_ARRAY_LAYERS,
                 };
-
-                let begin_info = vk::CommandBufferBeginInfo::default()
-                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-                unsafe {
-                    vk_device
-                        .begin_command_buffer(command_buffer, &begin_info)
-                        .expect("begin command buffer");
-                    let image_barrier = vk_sync::ImageBarrier {
+                command_buffer.pipeline_barrier(
+                    &[],
+                    &[vk_sync::ImageBarrier {
                         previous_accesses: &[AccessType::Nothing],
                         next_accesses: &[AccessType::ColorAttachmentWrite],
-                        previous_layout: ImageLayout::General,
+                        previous_layout: ImageLayout::Optimal,
                         next_layout: ImageLayout::Optimal,
                         discard_contents: true,
                         src_queue_family_index: render_backend.device.graphics_queue.family,
                         dst_queue_family_index: render_backend.device.graphics_queue.family,
                         image: swapchain_image.image,
-                        range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: vk::REMAINING_MIP_LEVELS,
-                            base_array_layer: 0,
-                            layer_count: vk::REMAINING_ARRAY_LAYERS,
-                        },
-                    };
-
-                    vk_sync::cmd::pipeline_barrier(
-                        &render_backend.device.raw,
-                        command_buffer,
-                        None,
-                        &[],
-                        &[image_barrier],
-                    );
-                }
+                        range: color_range,
+                    }],
+                );
+
+                renderer.gpu_profiler.begin_frame(command_buffer.raw);
+                renderer
+                    .gpu_profiler
+                    .begin_zone(command_buffer.raw, "triangle");
 
                 // draw
-                unsafe {
-                    vk_device.cmd_bind_pipeline(
-                        command_buffer,
+                let triangle_scope = render_backend.device.debug_scope(
+                    command_buffer.raw,
+                    "Triangle",
+                    [1.0, 0.5, 0.0, 1.0],
+                );
+                {
+                    command_buffer.bind_pipeline(
                         vk::PipelineBindPoint::GRAPHICS,
                         renderer.triangle_pipeline.pipeline,
                     );
 
                     let extent = swapchain.get_extent();
-                    let height = extent.height;
-                    let width = extent.width;
-                    vk_device.cmd_set_viewport(
-                        command_buffer,
-                        0,
-                        &[vk::Viewport {
-                            x: 0.0,
-                            y: height as _,
-                            width: width as _,
-                            height: -(height as f32),
-                            min_depth: 0.0,
-                            max_depth: 1.0,
-                        }],
+                    render_backend
+                        .device
+                        .cmd_set_viewport_flipped(command_buffer.raw, extent);
+                    command_buffer.set_scissor(vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent,
+                    });
+
+                    let color_attachment = rendering::color_attachment(
+                        swapchain_image.image_view,
+                        LoadOp::clear_color(renderer.clear_color),
                     );
-                    vk_device.cmd_set_scissor(
-                        command_buffer,
-                        0,
-                        &[vk::Rect2D {
-                            offset: vk::Offset2D { x: 0, y: 0 },
-                            extent: vk::Extent2D {
-                                width: width as _,
-                                height: height as _,
-                            },
-                        }],
+
+                    let rendering_info = rendering::rendering_info(
+                        extent,
+                        std::slice::from_ref(&color_attachment),
+                        None,
                     );
 
-                    let color_attachment = vk::RenderingAttachmentInfo::default()
-                        .image_view(swapchain_image.image_view)
-                        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                        .load_op(vk::AttachmentLoadOp::CLEAR)
-                        .store_op(vk::AttachmentStoreOp::STORE)
-                        .clear_value(vk::ClearValue::default());
-
-                    let rendering_info = vk::RenderingInfo::default()
-                        .layer_count(1)
-                        .render_area(
-                            vk::Rect2D::default()
-                                .offset(vk::Offset2D { x: 0, y: 0 })
-                                .extent(extent),
+                    let _rendering = command_buffer.begin_rendering(&rendering_info);
+                    command_buffer.draw(3, 1, 0, 0);
+                }
+                drop(triangle_scope);
+
+                renderer.gpu_profiler.end_zone(command_buffer.raw);
+
+                #[cfg(feature = "egui")]
+                {
+                    let window = self.window.as_ref().unwrap();
+                    let debug_ui = &mut renderer.debug_ui;
+                    let input = debug_ui.state.take_egui_input(window);
+                    let zones = renderer.gpu_profiler.results();
+                    let output = debug_ui.context.run(input, |ctx| {
+                        egui::Window::new("Stats").show(ctx, |ui| {
+                            for zone in zones {
+                                ui.label(format!("{}: {:.3}ms", zone.name, zone.duration_ms));
+                            }
+                        });
+                    });
+                    debug_ui
+                        .state
+                        .handle_platform_output(window, output.platform_output);
+                    let primitives = debug_ui
+                        .context
+                        .tessellate(output.shapes, output.pixels_per_point);
+                    debug_ui
+                        .renderer
+                        .paint(
+                            command_buffer.raw,
+                            swapchain_image.image_view,
+                            swapchain.get_extent(),
+                            output.pixels_per_point,
+                            &primitives,
+                            &output.textures_delta,
                         )
-                        .color_attachments(std::slice::from_ref(&color_attachment));
-
-                    vk_device.cmd_begin_rendering(command_buffer, &rendering_info);
-
-                    vk_device.cmd_draw(command_buffer, 3, 1, 0, 0);
-
-                    vk_device.cmd_end_rendering(command_buffer);
-                };
-
-                let image_barrier = vk_sync::ImageBarrier {
-                    previous_accesses: &[AccessType::ColorAttachmentWrite],
-                    next_accesses: &[AccessType::Present],
-                    previous_layout: ImageLayout::General,
-                    next_layout: ImageLayout::Optimal,
-                    discard_contents: true,
-                    src_queue_family_index: render_backend.device.graphics_queue.family,
-                    dst_queue_family_index: render_backend.device.graphics_queue.family,
-                    image: swapchain_image.image,
-                    range: vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: vk::REMAINING_MIP_LEVELS,
-                        base_array_layer: 0,
-                        layer_count: vk::REMAINING_ARRAY_LAYERS,
-                    },
-                };
+                        .expect("Failed to paint egui");
+                }
 
-                vk_sync::cmd::pipeline_barrier(
-                    &render_backend.device.raw,
-                    command_buffer,
-                    None,
+                // the contents have to survive the transition to present
+                command_buffer.pipeline_barrier(
                     &[],
-                    &[image_barrier],
+                    &[vk_sync::ImageBarrier {
+                        previous_accesses: &[AccessType::ColorAttachmentWrite],
+                        next_accesses: &[AccessType::Present],
+                        previous_layout: ImageLayout::Optimal,
+                        next_layout: ImageLayout::Optimal,
+                        discard_contents: false,
+                        src_queue_family_index: render_backend.device.graphics_queue.family,
+                        dst_queue_family_index: render_backend.device.graphics_queue.family,
+                        image: swapchain_image.image,
+                        range: color_range,
+                    }],
                 );
 
-                unsafe {
-                    vk_device
-                        .end_command_buffer(command_buffer)
-                        .expect("end command buffer");
-                }
-
-                let mut wait_semaphores = vec![
-                    vk::SemaphoreSubmitInfo::default()
-                        .semaphore(swapchain_image.sync.acquire_semaphore)
-                        .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT),
-                ];
-                if render_backend.device.absolute_frame_index() >= FRAMES_IN_FLIGHT {
+                command_buffer.end().expect("end command buffer");
+
+                // the acquire semaphore is only valid for this frame's submit,
+                // see SwapchainSync
+                let mut wait_semaphores = vec![SemaphoreSubmit::binary(
+                    swapchain_image.sync.acquire_semaphore,
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                )];
+                let device = &render_backend.device;
+                let absolute_frame_index = device.absolute_frame_index();
+                let timeline = device.sync_mode() == SyncMode::Timeline;
+                if timeline
+                    && let Some(wait_value) = TimelineSemaphore::wait_value_for_frame(
+                        absolute_frame_index,
+                        device.frames_in_flight(),
+                    )
+                {
                     wait_semaphores.push(
-                        vk::SemaphoreSubmitInfo::default()
-                            .semaphore(render_backend.device.graphics_timeline_semaphore)
-                            .value(
-                                (render_backend.device.absolute_frame_index() - FRAMES_IN_FLIGHT
-                                    + 1) as u64,
-                            )
-                            .stage_mask(vk::PipelineStageFlags2::TOP_OF_PIPE),
+                        device
+                            .graphics_timeline
+                            .wait_info(wait_value, vk::PipelineStageFlags2::TOP_OF_PIPE),
                     );
                 }
 
-                let signal_semaphores = [
-                    vk::SemaphoreSubmitInfo::default()
-                        .semaphore(swapchain_image.sync.present_semaphore)
-                        .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT),
-                    vk::SemaphoreSubmitInfo::default()
-                        .semaphore(render_backend.device.graphics_timeline_semaphore)
-                        .value((render_backend.device.absolute_frame_index() + 1) as u64)
-                        .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT),
-                ];
-
-                let command_buffer_submit_info =
-                    vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer);
-
-                let submit_info = vk::SubmitInfo2::default()
-                    .wait_semaphore_infos(&wait_semaphores)
-                    .signal_semaphore_infos(&signal_semaphores)
-                    .command_buffer_infos(std::slice::from_ref(&command_buffer_submit_info));
-
-                unsafe {
-                    render_backend
-                        .device
-                        .raw
-                        .queue_submit2(
-                            render_backend.device.graphics_queue.raw,
-                            std::slice::from_ref(&submit_info),
-                            vk::Fence::null(),
-                        )
-                        .expect("queue_submit2");
-                };
+                let mut signal_semaphores = vec![SemaphoreSubmit::binary(
+                    swapchain_image.sync.present_semaphore,
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                )];
+                if timeline {
+                    signal_semaphores.push(device.graphics_timeline.signal_info(
+                        TimelineSemaphore::signal_value_for_frame(absolute_frame_index),
+                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    ));
+                }
+
+                let submit_zone = profiling::zone("submit");
+                let fence = device.frame_fence().expect("reset frame fence");
+                device
+                    .submit(&SubmitDesc {
+                        queue: QueueType::Graphics,
+                        wait: &wait_semaphores,
+                        signal: &signal_semaphores,
+                        command_buffers: std::slice::from_ref(&command_buffer.raw),
+                        fence,
+                    })
+                    .expect("Failed to submit frame");
+                drop(submit_zone);
+
+                if std::mem::take(&mut renderer.capture_requested) {
+                    match render_backend
+                        .capture_frame(&swapchain_image)
+                        .and_then(|screenshot| screenshot.save_png("screenshot.png"))
+                    {
+                        Ok(()) => info!("Saved screenshot.png"),
+                        Err(e) => warn!("Failed to capture frame: {e:#}"),
+                    }
+                }
 
-                render_backend.swapchain.present_image(swapchain_image);
+                let status = render_backend
+                    .swapchain
+                    .as_mut()
+                    .unwrap()
+                    .present_image(swapchain_image)
+                    .expect("present image");
 
                 render_backend.device.finish_frame();
 
+                if status == SwapchainStatus::NeedsRebuild {
+                    render_backend
+                        .resize(window_extent(self.window.as_ref().unwrap()))
+                        .expect("Failed to resize render backend");
+                }
+
                 self.window.as_ref().unwrap().request_redraw();
             }
-            WindowEvent::Resized(new_size) => {
-                warn!("Resize requested: {}x{}", new_size.width, new_size.height);
-                self.renderer
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.physical_key == PhysicalKey::Code(KeyCode::KeyV) =>
+            {
+                let swapchain = self
+                    .renderer
                     .as_mut()
                     .unwrap()
                     .render_backend
                     .swapchain
-                    .rebuild()
+                    .as_mut()
+                    .unwrap();
+                let vsync = !swapchain.desc.present_mode.is_vsync();
+                info!("Vsync {}", if vsync { "on" } else { "off" });
+                swapchain
+                    .set_vsync(vsync)
                     .expect("Failed to rebuild swapchain");
             }
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.physical_key == PhysicalKey::Code(KeyCode::F12) =>
+            {
+                self.renderer.as_mut().unwrap().capture_requested = true;
+            }
+            WindowEvent::Resized(new_size) => {
+                warn!("Resize requested: {}x{}", new_size.width, new_size.height);
+                let render_backend = &mut self.renderer.as_mut().unwrap().render_backend;
+                let was_minimized = render_backend.swapchain.as_ref().unwrap().is_minimized();
+                let extent = render_backend
+                    .resize(vk::Extent2D {
+                        width: new_size.width,
+                        height: new_size.height,
+                    })
+                    .expect("Failed to resize render backend");
+                // nothing in the example depends on the window size yet
+                if let Some(extent) = extent {
+                    info!("Resized to {}x{}", extent.width, extent.height);
+                    if was_minimized {
+                        self.window.as_ref().unwrap().request_redraw();
+                    }
+                }
+            }
             _ => (),
         }
     }
 }
 
+fn window_extent(window: &Window) -> vk::Extent2D {
+    let size = window.inner_size();
+    vk::Extent2D {
+        width: size.width,
+        height: size.height,
+    }
+}
+
 fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use std::sync::Arc;
+
+use super::buffer::Buffer;
+use super::device::Device;
+use super::pipeline::MAX_BINDLESS_DESCRIPTORS;
+
+// a single update-after-bind set with one variable sized array at binding 0,
+// either combined image samplers or storage buffers. laid out the same way
+// create_pipeline_layout reflects an unbounded array so the set can be bound
+// to those pipelines
+pub struct DescriptorHeap {
+    pub layout: vk::DescriptorSetLayout,
+    pub set: vk::DescriptorSet,
+    pool: vk::DescriptorPool,
+    descriptor_type: vk::DescriptorType,
+
+    capacity: u32,
+    next_index: u32,
+    free_indices: Vec<u32>,
+
+    device: Arc<Device>,
+}
+
+impl DescriptorHeap {
+    // for Sampler2D textures[]
+    pub fn new(device: Arc<Device>, capacity: u32) -> Result<Self> {
+        Self::with_type(device, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, capacity)
+    }
+
+    // for StructuredBuffer<T> buffers[] and the like
+    pub fn new_storage_buffers(device: Arc<Device>, capacity: u32) -> Result<Self> {
+        Self::with_type(device, vk::DescriptorType::STORAGE_BUFFER, capacity)
+    }
+
+    fn with_type(
+        device: Arc<Device>,
+        descriptor_type: vk::DescriptorType,
+        capacity: u32,
+    ) -> Result<Self> {
+        if capacity > MAX_BINDLESS_DESCRIPTORS {
+            anyhow::bail!(
+                "Descriptor heap capacity {capacity} exceeds the maximum of {MAX_BINDLESS_DESCRIPTORS}"
+            );
+        }
+
+        let binding = vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(MAX_BINDLESS_DESCRIPTORS)
+            .descriptor_type(descriptor_type)
+            .stage_flags(vk::ShaderStageFlags::ALL);
+        let binding_flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND;
+        let mut binding_flags_create_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
+                .binding_flags(std::slice::from_ref(&binding_flags));
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(std::slice::from_ref(&binding))
+            .push_next(&mut binding_flags_create_info);
+        let layout = unsafe {
+            device
+                .raw
+                .create_descriptor_set_layout(&layout_create_info, None)
+                .context("Failed to create descriptor heap layout")?
+        };
+
+        let pool_size = vk::DescriptorPoolSize::default()
+            .ty(descriptor_type)
+            .descriptor_count(capacity);
+        let pool_create_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(std::slice::from_ref(&pool_size));
+        let pool = unsafe {
+            device
+                .raw
+                .create_descriptor_pool(&pool_create_info, None)
+                .context("Failed to create descriptor heap pool")?
+        };
+
+        let mut variable_count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfo::default()
+                .descriptor_counts(std::slice::from_ref(&capacity));
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(std::slice::from_ref(&layout))
+            .push_next(&mut variable_count_info);
+        let set = unsafe {
+            device
+                .raw
+                .allocate_descriptor_sets(&allocate_info)
+                .context("Failed to allocate descriptor heap set")?[0]
+        };
+
+        Ok(Self {
+            layout,
+            set,
+            pool,
+            descriptor_type,
+            capacity,
+            next_index: 0,
+            free_indices: Vec::new(),
+            device,
+        })
+    }
+
+    // returns the index to use in the shader, the image is expected to be in
+    // SHADER_READ_ONLY_OPTIMAL whenever it is sampled
+    pub fn register_image(&mut self, view: vk::ImageView, sampler: vk::Sampler) -> Result<u32> {
+        self.expect_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)?;
+        let index = self.allocate_index()?;
+        self.device.update_combined_image_samplers(
+            self.set,
+            0,
+            index,
+            &[(view, sampler)],
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        Ok(index)
+    }
+
+    // the whole buffer, returns the index to use in the shader
+    pub fn register_storage_buffer(&mut self, buffer: &Buffer) -> Result<u32> {
+        self.expect_type(vk::DescriptorType::STORAGE_BUFFER)?;
+        let index = self.allocate_index()?;
+        self.device
+            .update_storage_buffers(self.set, 0, index, &[(buffer.raw, 0, vk::WHOLE_SIZE)]);
+
+        Ok(index)
+    }
+
+    fn expect_type(&self, descriptor_type: vk::DescriptorType) -> Result<()> {
+        if self.descriptor_type != descriptor_type {
+            anyhow::bail!(
+                "Descriptor heap holds {:?}, not {descriptor_type:?}",
+                self.descriptor_type
+            );
+        }
+        Ok(())
+    }
+
+    fn allocate_index(&mut self) -> Result<u32> {
+        Ok(match self.free_indices.pop() {
+            Some(index) => index,
+            None if self.next_index < self.capacity => {
+                self.next_index += 1;
+                self.next_index - 1
+            }
+            None => anyhow::bail!("Descriptor heap is full ({} descriptors)", self.capacity),
+        })
+    }
+
+    // the slot is reused by the next register_image, so the caller has to make
+    // sure no frame in flight still samples it
+    pub fn free(&mut self, index: u32) {
+        debug_assert!(
+            index < self.next_index,
+            "Freeing unregistered index {index}"
+        );
+        debug_assert!(
+            !self.free_indices.contains(&index),
+            "Double free of index {index}"
+        );
+        self.free_indices.push(index);
+    }
+}
+
+impl Drop for DescriptorHeap {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.raw.destroy_descriptor_pool(self.pool, None);
+            self.device
+                .raw
+                .destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use super::device::Device;
+
+#[derive(Clone)]
+pub struct BufferDesc {
+    pub name: String,
+    pub size: u64,
+    pub usage: vk::BufferUsageFlags,
+    pub memory_location: MemoryLocation,
+    // allow a dedicated allocation when the driver reports it as beneficial
+    pub dedicated: bool,
+}
+
+impl BufferDesc {
+    pub fn new(
+        name: impl Into<String>,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        memory_location: MemoryLocation,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            size,
+            usage,
+            memory_location,
+            dedicated: false,
+        }
+    }
+
+    pub fn dedicated(mut self, dedicated: bool) -> Self {
+        self.dedicated = dedicated;
+        self
+    }
+}
+
+pub struct Buffer {
+    pub raw: vk::Buffer,
+    pub desc: BufferDesc,
+    allocation: Allocation,
+    device: Arc<Device>,
+}
+
+impl Device {
+    pub fn create_buffer(self: &Arc<Self>, desc: BufferDesc) -> Result<Buffer> {
+        let create_info = vk::BufferCreateInfo::default()
+            .size(desc.size)
+            .usage(desc.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let raw = unsafe { self.raw.create_buffer(&create_info, None) }
+            .with_context(|| format!("Failed to create buffer {}", desc.name))?;
+        self.set_debug_name(raw, &desc.name);
+
+        let requirements = self.buffer_memory_requirements(raw);
+        let allocation_scheme = if requirements.requires_dedicated
+            || (desc.dedicated && requirements.prefers_dedicated)
+        {
+            AllocationScheme::DedicatedBuffer(raw)
+        } else {
+            AllocationScheme::GpuAllocatorManaged
+        };
+
+        let allocation = self.allocate(&AllocationCreateDesc {
+            name: &desc.name,
+            requirements: requirements.requirements,
+            location: desc.memory_location,
+            linear: true,
+            allocation_scheme,
+        })?;
+
+        unsafe {
+            self.raw
+                .bind_buffer_memory(raw, allocation.memory(), allocation.offset())
+                .context("Failed to bind buffer memory")?
+        };
+
+        Ok(Buffer {
+            raw,
+            desc,
+            allocation,
+            device: self.clone(),
+        })
+    }
+}
+
+impl Buffer {
+    pub fn size(&self) -> u64 {
+        self.desc.size
+    }
+
+    // None unless the buffer is host visible
+    pub fn mapped_ptr(&self) -> Option<NonNull<c_void>> {
+        self.allocation.mapped_ptr()
+    }
+
+    pub fn write_slice<T: bytemuck::Pod>(&mut self, data: &[T], offset: usize) -> Result<()> {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+
+        let mapped = self
+            .allocation
+            .mapped_slice_mut()
+            .with_context(|| format!("Buffer {} is not host visible", self.desc.name))?;
+        let dst = offset
+            .checked_add(bytes.len())
+            .and_then(|end| mapped.get_mut(offset..end))
+            .with_context(|| {
+                format!(
+                    "Write of {} bytes at offset {offset} is out of bounds for buffer {}",
+                    bytes.len(),
+                    self.desc.name
+                )
+            })?;
+        dst.copy_from_slice(bytes);
+
+        Ok(())
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { self.device.raw.destroy_buffer(self.raw, None) };
+        let _ = self.device.free(std::mem::take(&mut self.allocation));
+    }
+}
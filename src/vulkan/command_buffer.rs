@@ -0,0 +1,256 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use std::sync::Arc;
+
+use super::device::Device;
+use super::rendering::RenderingScope;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RecordingState {
+    // freshly allocated or reset with its pool
+    Initial,
+    Recording,
+    // ended and ready to be submitted
+    Executable,
+}
+
+// the attachments of the rendering instance a secondary buffer executes in,
+// has to match the RenderingInfo the primary buffer began rendering with
+#[derive(Clone)]
+pub struct RenderingInheritance {
+    pub color_attachments: Vec<vk::Format>,
+    pub depth_attachment: Option<vk::Format>,
+    // only for passes that bind a stencil attachment, usually the depth format
+    pub stencil_attachment: Option<vk::Format>,
+    pub sample_count: vk::SampleCountFlags,
+    pub view_mask: u32,
+}
+
+impl Default for RenderingInheritance {
+    fn default() -> Self {
+        Self {
+            color_attachments: Vec::new(),
+            depth_attachment: None,
+            stencil_attachment: None,
+            sample_count: vk::SampleCountFlags::TYPE_1,
+            view_mask: 0,
+        }
+    }
+}
+
+// a command buffer handed out by CommandRingBuffer, commands assert that it
+// is recording. raw is still exposed for apis that take a vk::CommandBuffer
+pub struct CommandBuffer {
+    pub raw: vk::CommandBuffer,
+    level: vk::CommandBufferLevel,
+    state: RecordingState,
+    device: Arc<Device>,
+}
+
+impl CommandBuffer {
+    // the buffer has to be in the initial state, e.g. right after its pool was reset
+    pub(crate) fn new(
+        device: Arc<Device>,
+        raw: vk::CommandBuffer,
+        level: vk::CommandBufferLevel,
+    ) -> Self {
+        Self {
+            raw,
+            level,
+            state: RecordingState::Initial,
+            device,
+        }
+    }
+
+    pub fn state(&self) -> RecordingState {
+        self.state
+    }
+
+    pub fn level(&self) -> vk::CommandBufferLevel {
+        self.level
+    }
+
+    pub fn begin(&mut self) -> Result<()> {
+        assert_eq!(
+            self.level,
+            vk::CommandBufferLevel::PRIMARY,
+            "Secondary command buffers are begun with begin_secondary"
+        );
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.begin_with(&begin_info)
+    }
+
+    // for secondary buffers executed inside a rendering instance that the
+    // primary began with RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS.
+    // draws can be recorded without beginning rendering in this buffer
+    pub fn begin_secondary(&mut self, inheritance: &RenderingInheritance) -> Result<()> {
+        assert_eq!(
+            self.level,
+            vk::CommandBufferLevel::SECONDARY,
+            "Primary command buffers are begun with begin"
+        );
+        let mut rendering_inheritance = vk::CommandBufferInheritanceRenderingInfo::default()
+            .view_mask(inheritance.view_mask)
+            .color_attachment_formats(&inheritance.color_attachments)
+            .depth_attachment_format(
+                inheritance
+                    .depth_attachment
+                    .unwrap_or(vk::Format::UNDEFINED),
+            )
+            .stencil_attachment_format(
+                inheritance
+                    .stencil_attachment
+                    .unwrap_or(vk::Format::UNDEFINED),
+            )
+            .rasterization_samples(inheritance.sample_count);
+        let inheritance_info =
+            vk::CommandBufferInheritanceInfo::default().push_next(&mut rendering_inheritance);
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            )
+            .inheritance_info(&inheritance_info);
+        self.begin_with(&begin_info)
+    }
+
+    fn begin_with(&mut self, begin_info: &vk::CommandBufferBeginInfo<'_>) -> Result<()> {
+        assert_eq!(
+            self.state,
+            RecordingState::Initial,
+            "Command buffer has to be reset before it can be recorded again"
+        );
+        unsafe {
+            self.device
+                .raw
+                .begin_command_buffer(self.raw, begin_info)
+                .context("Failed to begin command buffer")?
+        };
+        self.state = RecordingState::Recording;
+        Ok(())
+    }
+
+    pub fn end(&mut self) -> Result<()> {
+        self.assert_recording();
+        unsafe {
+            self.device
+                .raw
+                .end_command_buffer(self.raw)
+                .context("Failed to end command buffer")?
+        };
+        self.state = RecordingState::Executable;
+        Ok(())
+    }
+
+    fn assert_recording(&self) {
+        assert_eq!(
+            self.state,
+            RecordingState::Recording,
+            "Command buffer is not recording"
+        );
+    }
+
+    pub fn bind_pipeline(&self, bind_point: vk::PipelineBindPoint, pipeline: vk::Pipeline) {
+        self.assert_recording();
+        unsafe {
+            self.device
+                .raw
+                .cmd_bind_pipeline(self.raw, bind_point, pipeline)
+        };
+    }
+
+    pub fn set_viewport(&self, viewport: vk::Viewport) {
+        self.assert_recording();
+        unsafe {
+            self.device
+                .raw
+                .cmd_set_viewport(self.raw, 0, std::slice::from_ref(&viewport))
+        };
+    }
+
+    pub fn set_scissor(&self, scissor: vk::Rect2D) {
+        self.assert_recording();
+        unsafe {
+            self.device
+                .raw
+                .cmd_set_scissor(self.raw, 0, std::slice::from_ref(&scissor))
+        };
+    }
+
+    pub fn draw(
+        &self,
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    ) {
+        self.assert_recording();
+        unsafe {
+            self.device.raw.cmd_draw(
+                self.raw,
+                vertex_count,
+                instance_count,
+                first_vertex,
+                first_instance,
+            )
+        };
+    }
+
+    pub fn dispatch(&self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        self.assert_recording();
+        unsafe {
+            self.device
+                .raw
+                .cmd_dispatch(self.raw, group_count_x, group_count_y, group_count_z)
+        };
+    }
+
+    pub fn begin_rendering(&self, rendering_info: &vk::RenderingInfo<'_>) -> RenderingScope<'_> {
+        self.assert_recording();
+        RenderingScope::new(&self.device, self.raw, rendering_info)
+    }
+
+    // the secondary buffers have to be ended, inside a rendering instance they
+    // have to have been begun with a matching RenderingInheritance
+    pub fn execute_commands(&self, secondary_buffers: &[&CommandBuffer]) {
+        self.assert_recording();
+        assert_eq!(
+            self.level,
+            vk::CommandBufferLevel::PRIMARY,
+            "Only primary command buffers can execute commands"
+        );
+        let raw_buffers = secondary_buffers
+            .iter()
+            .map(|secondary_buffer| {
+                assert_eq!(
+                    secondary_buffer.level,
+                    vk::CommandBufferLevel::SECONDARY,
+                    "Only secondary command buffers can be executed"
+                );
+                assert_eq!(
+                    secondary_buffer.state,
+                    RecordingState::Executable,
+                    "Secondary command buffer has to be ended before it is executed"
+                );
+                secondary_buffer.raw
+            })
+            .collect::<Vec<_>>();
+        unsafe { self.device.raw.cmd_execute_commands(self.raw, &raw_buffers) };
+    }
+
+    pub fn pipeline_barrier(
+        &self,
+        buffer_barriers: &[vk_sync::BufferBarrier<'_>],
+        image_barriers: &[vk_sync::ImageBarrier<'_>],
+    ) {
+        self.assert_recording();
+        vk_sync::cmd::pipeline_barrier(
+            &self.device.raw,
+            self.raw,
+            None,
+            buffer_barriers,
+            image_barriers,
+        );
+    }
+}
@@ -3,13 +3,17 @@ use std::sync::Arc;
 
 use ash::vk;
 
-use super::device;
+use super::command_buffer::{CommandBuffer, RenderingInheritance};
+use super::device::{self, SemaphoreSubmit};
+use super::timeline_semaphore::TimelineSemaphore;
 
 pub struct CommandRingBufferBuilder {
     num_threads: usize,
     primary_buffers_per_pool: usize,
     secondary_buffers_per_pool: usize,
     queue: device::Queue,
+    // Some for rings whose slots are driven by their own timeline
+    transfer_slots: Option<usize>,
     device: Arc<device::Device>,
 }
 
@@ -20,6 +24,7 @@ impl CommandRingBufferBuilder {
             primary_buffers_per_pool: 1,
             secondary_buffers_per_pool: 0,
             queue: device.graphics_queue,
+            transfer_slots: None,
             device,
         }
     }
@@ -43,7 +48,11 @@ impl CommandRingBufferBuilder {
             .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
             .queue_family_index(self.queue.family);
 
-        let num_pools = self.num_threads * device::FRAMES_IN_FLIGHT;
+        let slot_count = self
+            .transfer_slots
+            .unwrap_or_else(|| self.device.frames_in_flight());
+        assert!(slot_count > 0, "Must have at least 1 slot");
+        let num_pools = self.num_threads * slot_count;
 
         let mut command_pools = Vec::with_capacity(num_pools);
         for _ in 0..num_pools {
@@ -83,11 +92,46 @@ impl CommandRingBufferBuilder {
             }
         }
 
+        for (index, &command_buffer) in primary_buffers.iter().enumerate() {
+            let pool = index / self.primary_buffers_per_pool;
+            let buffer = index % self.primary_buffers_per_pool;
+            self.device
+                .set_debug_name(command_buffer, &format!("primary {buffer} of pool {pool}"));
+        }
+        if self.secondary_buffers_per_pool > 0 {
+            for (index, &command_buffer) in secondary_buffers.iter().enumerate() {
+                let pool = index / self.secondary_buffers_per_pool;
+                let buffer = index % self.secondary_buffers_per_pool;
+                self.device.set_debug_name(
+                    command_buffer,
+                    &format!("secondary {buffer} of pool {pool}"),
+                );
+            }
+        }
+
         let used_primary_buffers = vec![0; num_pools];
         let used_secondary_buffers = vec![0; num_pools];
 
+        let timeline = match self.transfer_slots {
+            Some(slot_count) => Some(SlotTimeline {
+                semaphore: TimelineSemaphore::new(&self.device.raw)?,
+                // the first advance_slot moves to slot 0
+                slot: slot_count - 1,
+                slot_values: vec![0; slot_count],
+                last_value: 0,
+            }),
+            None => None,
+        };
+        if let Some(timeline) = &timeline {
+            self.device
+                .set_debug_name(timeline.semaphore.raw, "command ring timeline");
+        }
+
         Ok(CommandRingBuffer {
             device: self.device,
+            num_threads: self.num_threads,
+            slot_count,
+            timeline,
             command_pools,
             primary_buffers_per_pool: self.primary_buffers_per_pool as u8,
             used_primary_offset: used_primary_buffers,
@@ -98,11 +142,22 @@ impl CommandRingBufferBuilder {
         })
     }
 
+    // pools are created for this queue's family, use device.compute_queue
+    // for command buffers passed to Device::submit_compute
     pub fn queue(mut self, queue: device::Queue) -> Self {
         self.queue = queue;
         self
     }
 
+    // pools on the transfer queue that are recycled once the ring's own
+    // timeline shows their last submission finished, instead of with the
+    // graphics frame. lets uploads stay pending for longer than a frame
+    pub fn transfer(mut self, slot_count: usize) -> Self {
+        self.queue = self.device.transfer_queue;
+        self.transfer_slots = Some(slot_count);
+        self
+    }
+
     pub fn num_pools(mut self, pool_count: usize) -> Self {
         self.num_threads = pool_count;
         self
@@ -119,8 +174,27 @@ impl CommandRingBufferBuilder {
     }
 }
 
+// which slot of a transfer ring is recorded and when its pools can be reused
+struct SlotTimeline {
+    semaphore: TimelineSemaphore,
+    slot: usize,
+    // the value the last submission recorded in each slot signals
+    slot_values: Vec<u64>,
+    last_value: u64,
+}
+
+// every thread index owns one pool per slot. a pool and the buffers allocated
+// from it may only be used by one thread at a time, so two threads recording
+// at once have to use different thread indices.
+// by default there is a slot per frame in flight and the current slot is
+// Device::frame_index(), so pools are safe to reset after begin_frame. rings
+// built with transfer() ignore the frame index, the current slot only changes
+// with advance_slot, which waits for the slot's previous submission
 pub struct CommandRingBuffer {
     device: Arc<device::Device>,
+    num_threads: usize,
+    slot_count: usize,
+    timeline: Option<SlotTimeline>,
 
     command_pools: Vec<vk::CommandPool>,
 
@@ -140,7 +214,7 @@ impl CommandRingBuffer {
     }
 
     pub fn reset_pool(&mut self, thread_index: usize) -> Result<()> {
-        let pool_index = Self::pool_from_indices(self.device.frame_index(), thread_index);
+        let pool_index = self.pool_from_indices(self.slot_index(), thread_index);
         unsafe {
             self.device.raw.reset_command_pool(
                 self.command_pools[pool_index],
@@ -155,7 +229,7 @@ impl CommandRingBuffer {
     }
 
     pub fn get_next_primary_buffer(&mut self, thread_index: usize) -> vk::CommandBuffer {
-        let pool_index = Self::pool_from_indices(self.device.frame_index(), thread_index);
+        let pool_index = self.pool_from_indices(self.slot_index(), thread_index);
         assert!(
             self.used_primary_offset[pool_index] < self.primary_buffers_per_pool,
             "Out of primary command buffer"
@@ -168,8 +242,15 @@ impl CommandRingBuffer {
         cmd
     }
 
+    // same as get_next_primary_buffer but tracks the recording state,
+    // the pool has to have been reset this frame
+    pub fn next_primary(&mut self, thread_index: usize) -> CommandBuffer {
+        let raw = self.get_next_primary_buffer(thread_index);
+        CommandBuffer::new(self.device.clone(), raw, vk::CommandBufferLevel::PRIMARY)
+    }
+
     pub fn get_next_secondary_buffer(&mut self, thread_index: usize) -> vk::CommandBuffer {
-        let pool_index = Self::pool_from_indices(self.device.frame_index(), thread_index);
+        let pool_index = self.pool_from_indices(self.slot_index(), thread_index);
         assert!(
             self.used_secondary_offset[pool_index] < self.secondary_buffers_per_pool,
             "Out of secondary command buffer"
@@ -182,11 +263,120 @@ impl CommandRingBuffer {
         cmd
     }
 
-    fn pool_from_indices(frame_index: usize, thread_index: usize) -> usize {
-        thread_index * device::FRAMES_IN_FLIGHT + frame_index
+    // recorded with CommandBuffer::begin_secondary
+    pub fn next_secondary(&mut self, thread_index: usize) -> CommandBuffer {
+        let raw = self.get_next_secondary_buffer(thread_index);
+        CommandBuffer::new(self.device.clone(), raw, vk::CommandBufferLevel::SECONDARY)
+    }
+
+    // records one secondary buffer per thread index in 0..thread_count, each
+    // on its own thread and from its own pool, and returns them in thread
+    // index order to be run with CommandBuffer::execute_commands. the pools
+    // have to have been reset this frame, and the primary buffer has to begin
+    // rendering with RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+    pub fn record_parallel<F>(
+        &mut self,
+        thread_count: usize,
+        inheritance: &RenderingInheritance,
+        record: F,
+    ) -> Result<Vec<CommandBuffer>>
+    where
+        F: Fn(usize, &CommandBuffer) -> Result<()> + Sync,
+    {
+        if thread_count > self.num_threads {
+            anyhow::bail!(
+                "Can't record on {thread_count} threads, the ring buffer has {} pools per frame",
+                self.num_threads
+            );
+        }
+
+        // allocating stays on this thread, the workers only record
+        let secondary_buffers = (0..thread_count)
+            .map(|thread_index| self.next_secondary(thread_index))
+            .collect::<Vec<_>>();
+
+        std::thread::scope(|scope| {
+            let workers = secondary_buffers
+                .into_iter()
+                .enumerate()
+                .map(|(thread_index, mut command_buffer)| {
+                    let record = &record;
+                    scope.spawn(move || -> Result<CommandBuffer> {
+                        command_buffer.begin_secondary(inheritance)?;
+                        record(thread_index, &command_buffer)?;
+                        command_buffer.end()?;
+                        Ok(command_buffer)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Recording thread panicked"))
+                .collect()
+        })
+    }
+
+    // for transfer rings, call before recording each batch. waits until the
+    // next slot's previous submission is done and resets all of its pools.
+    // every batch has to be submitted with signal_info, otherwise the wait
+    // when the slot comes around again never finishes
+    pub fn advance_slot(&mut self) -> Result<()> {
+        let timeline = self
+            .timeline
+            .as_mut()
+            .expect("Only rings built with transfer() advance their slots");
+        timeline.slot = (timeline.slot + 1) % self.slot_count;
+        timeline
+            .semaphore
+            .wait(timeline.slot_values[timeline.slot])?;
+        timeline.last_value += 1;
+        timeline.slot_values[timeline.slot] = timeline.last_value;
+
+        for thread_index in 0..self.num_threads {
+            self.reset_pool(thread_index)?;
+        }
+        Ok(())
+    }
+
+    // the signal for the submission of the current slot of a transfer ring,
+    // graphics work using the transferred data can wait on the same value
+    pub fn signal_info(&self, stage_mask: vk::PipelineStageFlags2) -> SemaphoreSubmit {
+        let timeline = self
+            .timeline
+            .as_ref()
+            .expect("Only rings built with transfer() have a timeline");
+        timeline
+            .semaphore
+            .signal_info(timeline.slot_values[timeline.slot], stage_mask)
+    }
+
+    fn slot_index(&self) -> usize {
+        match &self.timeline {
+            Some(timeline) => timeline.slot,
+            None => self.device.frame_index(),
+        }
+    }
+
+    fn pool_from_indices(&self, slot_index: usize, thread_index: usize) -> usize {
+        pool_index(slot_index, thread_index, self.slot_count, self.num_threads)
     }
 }
 
+// pools are laid out thread major, an out of range index would alias a pool
+// another thread or slot is recording into
+fn pool_index(slot: usize, thread: usize, slot_count: usize, num_threads: usize) -> usize {
+    assert!(
+        thread < num_threads,
+        "Thread index {thread} is out of range, the ring buffer has {num_threads} pools per frame"
+    );
+    assert!(
+        slot < slot_count,
+        "Slot index {slot} is out of range, the ring buffer has {slot_count} slots"
+    );
+    thread * slot_count + slot
+}
+
 impl Drop for CommandRingBuffer {
     fn drop(&mut self) {
         unsafe {
@@ -194,5 +384,78 @@ impl Drop for CommandRingBuffer {
                 self.device.raw.destroy_command_pool(pool, None);
             }
         }
+        if let Some(timeline) = &self.timeline {
+            timeline.semaphore.destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pool_index;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    #[test]
+    fn pool_indices_are_distinct() {
+        for slot_count in 1..=4 {
+            for num_threads in 1..=8 {
+                let mut indices = (0..slot_count)
+                    .flat_map(|slot| {
+                        (0..num_threads)
+                            .map(move |thread| pool_index(slot, thread, slot_count, num_threads))
+                    })
+                    .collect::<Vec<_>>();
+                assert!(
+                    indices
+                        .iter()
+                        .all(|&index| index < slot_count * num_threads)
+                );
+                indices.sort_unstable();
+                indices.dedup();
+                assert_eq!(indices.len(), slot_count * num_threads);
+            }
+        }
+    }
+
+    // every slot records on all threads at once, like record_parallel does
+    // for the current slot, and each worker claims the pool it was given
+    #[test]
+    fn parallel_workers_use_distinct_pools() {
+        let slot_count = 3;
+        let num_threads = 8;
+        let used_pools = Mutex::new(HashSet::new());
+
+        std::thread::scope(|scope| {
+            for slot in 0..slot_count {
+                for thread_index in 0..num_threads {
+                    let used_pools = &used_pools;
+                    scope.spawn(move || {
+                        let pool = pool_index(slot, thread_index, slot_count, num_threads);
+                        assert!(
+                            used_pools.lock().unwrap().insert(pool),
+                            "Pool {pool} was used by two threads"
+                        );
+                    });
+                }
+            }
+        });
+
+        assert_eq!(
+            used_pools.into_inner().unwrap().len(),
+            slot_count * num_threads
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Thread index 4 is out of range")]
+    fn out_of_range_thread_panics() {
+        pool_index(0, 4, 2, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Slot index 2 is out of range")]
+    fn out_of_range_slot_panics() {
+        pool_index(2, 0, 2, 4);
     }
 }
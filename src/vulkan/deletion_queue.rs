@@ -0,0 +1,36 @@
+use std::collections::VecDeque;
+
+use super::device::Device;
+
+type DestroyFn = Box<dyn FnOnce(&Device) + Send>;
+
+// destroy closures tagged with the frame that enqueued them, a closure runs
+// once every frame up to and including that one has finished on the gpu
+#[derive(Default)]
+pub struct DeletionQueue {
+    entries: VecDeque<(usize, DestroyFn)>,
+}
+
+impl DeletionQueue {
+    pub fn push(&mut self, frame_index: usize, destroy: impl FnOnce(&Device) + Send + 'static) {
+        self.entries.push_back((frame_index, Box::new(destroy)));
+    }
+
+    // takes every entry from frames up to completed_frame, entries are
+    // enqueued in frame order so the front is always the oldest
+    pub fn take_completed(&mut self, completed_frame: usize) -> Vec<DestroyFn> {
+        let count = self
+            .entries
+            .iter()
+            .position(|(frame_index, _)| *frame_index > completed_frame)
+            .unwrap_or(self.entries.len());
+        self.entries
+            .drain(..count)
+            .map(|(_, destroy)| destroy)
+            .collect()
+    }
+
+    pub fn take_all(&mut self) -> Vec<DestroyFn> {
+        self.entries.drain(..).map(|(_, destroy)| destroy).collect()
+    }
+}
@@ -0,0 +1,233 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use std::sync::Arc;
+
+use super::buffer::Buffer;
+use super::device::Device;
+
+pub struct DescriptorPool {
+    pub raw: vk::DescriptorPool,
+    device: Arc<Device>,
+}
+
+impl Device {
+    pub fn create_descriptor_pool(
+        self: &Arc<Self>,
+        sizes: &[vk::DescriptorPoolSize],
+        max_sets: u32,
+    ) -> Result<DescriptorPool> {
+        // update after bind so sets from reflected bindless layouts can live here too,
+        // free descriptor set so long lived pools can recycle individual sets
+        let create_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(
+                vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
+                    | vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            )
+            .max_sets(max_sets)
+            .pool_sizes(sizes);
+
+        let raw = unsafe {
+            self.raw
+                .create_descriptor_pool(&create_info, None)
+                .context("Failed to create descriptor pool")?
+        };
+
+        Ok(DescriptorPool {
+            raw,
+            device: self.clone(),
+        })
+    }
+
+    pub fn update_uniform_buffer(&self, set: vk::DescriptorSet, binding: u32, buffer: &Buffer) {
+        let buffer_info = vk::DescriptorBufferInfo::default()
+            .buffer(buffer.raw)
+            .offset(0)
+            .range(vk::WHOLE_SIZE);
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(std::slice::from_ref(&buffer_info));
+
+        unsafe {
+            self.raw
+                .update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+    }
+
+    // range can be vk::WHOLE_SIZE, offset has to be a multiple of
+    // minStorageBufferOffsetAlignment
+    pub fn update_storage_buffer(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        buffer: &Buffer,
+        offset: u64,
+        range: u64,
+    ) {
+        self.update_storage_buffers(set, binding, 0, &[(buffer.raw, offset, range)]);
+    }
+
+    // writes consecutive array elements starting at first_element, each
+    // entry is a buffer with its offset and range
+    pub fn update_storage_buffers(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        first_element: u32,
+        buffers: &[(vk::Buffer, u64, u64)],
+    ) {
+        if buffers.is_empty() {
+            return;
+        }
+
+        let buffer_infos = buffers
+            .iter()
+            .map(|&(buffer, offset, range)| {
+                vk::DescriptorBufferInfo::default()
+                    .buffer(buffer)
+                    .offset(offset)
+                    .range(range)
+            })
+            .collect::<Vec<_>>();
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .dst_array_element(first_element)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_infos);
+
+        unsafe {
+            self.raw
+                .update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+    }
+
+    // binds the whole view, so Image::view of an array image covers every layer.
+    // for arrays of descriptors see update_combined_image_samplers
+    pub fn update_combined_image_sampler(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout,
+    ) {
+        self.update_combined_image_samplers(set, binding, 0, &[(image_view, sampler)], layout);
+    }
+
+    // for SAMPLER bindings, e.g. a comparison sampler used with a separate depth texture
+    pub fn update_sampler(&self, set: vk::DescriptorSet, binding: u32, sampler: vk::Sampler) {
+        let image_info = vk::DescriptorImageInfo::default().sampler(sampler);
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(std::slice::from_ref(&image_info));
+
+        unsafe {
+            self.raw
+                .update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+    }
+
+    // for INPUT_ATTACHMENT bindings read with dynamic rendering local read,
+    // the view has to be the local_read_attachment of the pass
+    pub fn update_input_attachment(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        image_view: vk::ImageView,
+    ) {
+        let image_info = vk::DescriptorImageInfo::default()
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::RENDERING_LOCAL_READ_KHR);
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+            .image_info(std::slice::from_ref(&image_info));
+
+        unsafe {
+            self.raw
+                .update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+    }
+
+    // for SAMPLED_IMAGE bindings, paired with a sampler from update_sampler
+    pub fn update_sampled_image(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        image_view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) {
+        let image_info = vk::DescriptorImageInfo::default()
+            .image_view(image_view)
+            .image_layout(layout);
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(std::slice::from_ref(&image_info));
+
+        unsafe {
+            self.raw
+                .update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+    }
+
+    // writes consecutive array elements starting at first_element, every
+    // image has to be in the same layout when it is sampled
+    pub fn update_combined_image_samplers(
+        &self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        first_element: u32,
+        images: &[(vk::ImageView, vk::Sampler)],
+        layout: vk::ImageLayout,
+    ) {
+        if images.is_empty() {
+            return;
+        }
+
+        let image_infos = images
+            .iter()
+            .map(|&(image_view, sampler)| {
+                vk::DescriptorImageInfo::default()
+                    .sampler(sampler)
+                    .image_view(image_view)
+                    .image_layout(layout)
+            })
+            .collect::<Vec<_>>();
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .dst_array_element(first_element)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos);
+
+        unsafe {
+            self.raw
+                .update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+    }
+}
+
+impl DescriptorPool {
+    // every set allocated from the pool becomes invalid
+    pub fn reset(&self) -> Result<()> {
+        unsafe {
+            self.device
+                .raw
+                .reset_descriptor_pool(self.raw, vk::DescriptorPoolResetFlags::empty())?
+        };
+        Ok(())
+    }
+}
+
+impl Drop for DescriptorPool {
+    fn drop(&mut self) {
+        unsafe { self.device.raw.destroy_descriptor_pool(self.raw, None) };
+    }
+}
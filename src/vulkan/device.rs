@@ -1,17 +1,164 @@
-use super::instance::Instance;
+use super::deletion_queue::DeletionQueue;
+use super::instance::{Instance, MIN_API_VERSION};
 use super::physical_device::PhysicalDevice;
+use super::pipeline_cache::PipelineCache;
+use super::resources::{BufferHandle, ImageHandle, ResourceManager};
+use super::timeline_semaphore::TimelineSemaphore;
 use anyhow::{Context, Result};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, Allocator, AllocatorCreateDesc};
+use log::{info, warn};
 use std::cell::UnsafeCell;
-use std::ffi::CStr;
-use std::sync::Arc;
+use std::ffi::{CStr, CString};
+use std::mem::ManuallyDrop;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use ash::vk;
 
-pub const FRAMES_IN_FLIGHT: usize = 2;
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+// how begin_frame waits for the frame that last used the current slot
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum SyncMode {
+    // the frame's submit signals graphics_timeline, see TimelineSemaphore
+    #[default]
+    Timeline,
+    // the frame's submit signals frame_fence(), for tools that handle binary
+    // fences better or when the timeline values are suspect
+    Fences,
+}
+
+// optional core features, only the ones requested from DeviceBuilder are enabled
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DeviceFeature {
+    FullDrawIndexUint32,
+    IndependentBlend,
+    GeometryShader,
+    TessellationShader,
+    DualSrcBlend,
+    LogicOp,
+    MultiDrawIndirect,
+    DrawIndirectFirstInstance,
+    DepthClamp,
+    DepthBiasClamp,
+    FillModeNonSolid,
+    DepthBounds,
+    WideLines,
+    MultiViewport,
+    SamplerAnisotropy,
+    TextureCompressionBc,
+    PipelineStatisticsQuery,
+    ShaderFloat64,
+    ShaderInt64,
+    ShaderInt16,
+    ShaderUniformBufferArrayDynamicIndexing,
+    ShaderSampledImageArrayDynamicIndexing,
+    ShaderStorageBufferArrayDynamicIndexing,
+    ShaderStorageImageArrayDynamicIndexing,
+}
+
+// enabled on every device, the bindless heap indexes its arrays with push constants
+const ALWAYS_ENABLED_FEATURES: [DeviceFeature; 4] = [
+    DeviceFeature::ShaderUniformBufferArrayDynamicIndexing,
+    DeviceFeature::ShaderSampledImageArrayDynamicIndexing,
+    DeviceFeature::ShaderStorageBufferArrayDynamicIndexing,
+    DeviceFeature::ShaderStorageImageArrayDynamicIndexing,
+];
+
+impl DeviceFeature {
+    // the name used by the vulkan spec
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceFeature::FullDrawIndexUint32 => "fullDrawIndexUint32",
+            DeviceFeature::IndependentBlend => "independentBlend",
+            DeviceFeature::GeometryShader => "geometryShader",
+            DeviceFeature::TessellationShader => "tessellationShader",
+            DeviceFeature::DualSrcBlend => "dualSrcBlend",
+            DeviceFeature::LogicOp => "logicOp",
+            DeviceFeature::MultiDrawIndirect => "multiDrawIndirect",
+            DeviceFeature::DrawIndirectFirstInstance => "drawIndirectFirstInstance",
+            DeviceFeature::DepthClamp => "depthClamp",
+            DeviceFeature::DepthBiasClamp => "depthBiasClamp",
+            DeviceFeature::FillModeNonSolid => "fillModeNonSolid",
+            DeviceFeature::DepthBounds => "depthBounds",
+            DeviceFeature::WideLines => "wideLines",
+            DeviceFeature::MultiViewport => "multiViewport",
+            DeviceFeature::SamplerAnisotropy => "samplerAnisotropy",
+            DeviceFeature::TextureCompressionBc => "textureCompressionBC",
+            DeviceFeature::PipelineStatisticsQuery => "pipelineStatisticsQuery",
+            DeviceFeature::ShaderFloat64 => "shaderFloat64",
+            DeviceFeature::ShaderInt64 => "shaderInt64",
+            DeviceFeature::ShaderInt16 => "shaderInt16",
+            DeviceFeature::ShaderUniformBufferArrayDynamicIndexing => {
+                "shaderUniformBufferArrayDynamicIndexing"
+            }
+            DeviceFeature::ShaderSampledImageArrayDynamicIndexing => {
+                "shaderSampledImageArrayDynamicIndexing"
+            }
+            DeviceFeature::ShaderStorageBufferArrayDynamicIndexing => {
+                "shaderStorageBufferArrayDynamicIndexing"
+            }
+            DeviceFeature::ShaderStorageImageArrayDynamicIndexing => {
+                "shaderStorageImageArrayDynamicIndexing"
+            }
+        }
+    }
+
+    fn field(self, features: &mut vk::PhysicalDeviceFeatures) -> &mut vk::Bool32 {
+        match self {
+            DeviceFeature::FullDrawIndexUint32 => &mut features.full_draw_index_uint32,
+            DeviceFeature::IndependentBlend => &mut features.independent_blend,
+            DeviceFeature::GeometryShader => &mut features.geometry_shader,
+            DeviceFeature::TessellationShader => &mut features.tessellation_shader,
+            DeviceFeature::DualSrcBlend => &mut features.dual_src_blend,
+            DeviceFeature::LogicOp => &mut features.logic_op,
+            DeviceFeature::MultiDrawIndirect => &mut features.multi_draw_indirect,
+            DeviceFeature::DrawIndirectFirstInstance => &mut features.draw_indirect_first_instance,
+            DeviceFeature::DepthClamp => &mut features.depth_clamp,
+            DeviceFeature::DepthBiasClamp => &mut features.depth_bias_clamp,
+            DeviceFeature::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            DeviceFeature::DepthBounds => &mut features.depth_bounds,
+            DeviceFeature::WideLines => &mut features.wide_lines,
+            DeviceFeature::MultiViewport => &mut features.multi_viewport,
+            DeviceFeature::SamplerAnisotropy => &mut features.sampler_anisotropy,
+            DeviceFeature::TextureCompressionBc => &mut features.texture_compression_bc,
+            DeviceFeature::PipelineStatisticsQuery => &mut features.pipeline_statistics_query,
+            DeviceFeature::ShaderFloat64 => &mut features.shader_float64,
+            DeviceFeature::ShaderInt64 => &mut features.shader_int64,
+            DeviceFeature::ShaderInt16 => &mut features.shader_int16,
+            DeviceFeature::ShaderUniformBufferArrayDynamicIndexing => {
+                &mut features.shader_uniform_buffer_array_dynamic_indexing
+            }
+            DeviceFeature::ShaderSampledImageArrayDynamicIndexing => {
+                &mut features.shader_sampled_image_array_dynamic_indexing
+            }
+            DeviceFeature::ShaderStorageBufferArrayDynamicIndexing => {
+                &mut features.shader_storage_buffer_array_dynamic_indexing
+            }
+            DeviceFeature::ShaderStorageImageArrayDynamicIndexing => {
+                &mut features.shader_storage_image_array_dynamic_indexing
+            }
+        }
+    }
+
+    fn is_set(self, features: &vk::PhysicalDeviceFeatures) -> bool {
+        let mut features = *features;
+        *self.field(&mut features) == vk::TRUE
+    }
+}
 
 pub struct DeviceBuilder {
     instance: Arc<Instance>,
     physical_device: Arc<PhysicalDevice>,
+    mesh_shader: bool,
+    pipeline_cache_path: Option<PathBuf>,
+    frames_in_flight: usize,
+    swapchain: bool,
+    sync_mode: SyncMode,
+    required_extensions: Vec<&'static CStr>,
+    optional_extensions: Vec<&'static CStr>,
+    required_features: Vec<DeviceFeature>,
+    requested_features: Vec<DeviceFeature>,
 }
 
 pub struct Device {
@@ -23,31 +170,227 @@ pub struct Device {
     pub compute_queue: Queue,
     pub transfer_queue: Queue,
 
-    pub graphics_timeline_semaphore: vk::Semaphore,
+    pub graphics_timeline: TimelineSemaphore,
+    sync_mode: SyncMode,
+    // one per frame in flight, empty in timeline mode
+    frame_fences: Vec<vk::Fence>,
     absolute_frame_index: UnsafeCell<usize>,
+    frames_in_flight: usize,
+    deletion_queue: Mutex<DeletionQueue>,
+    resources: Mutex<ResourceManager>,
+
+    pub pipeline_cache: PipelineCache,
+    // dropped manually before the device is destroyed
+    allocator: ManuallyDrop<Mutex<Allocator>>,
+
+    pub mesh_shader: Option<ash::ext::mesh_shader::Device>,
+    pub task_shader_supported: bool,
+    pub multiview: MultiviewSupport,
+    // the core features that were requested and are supported
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    // every device extension that was enabled, see is_extension_enabled
+    enabled_extensions: Vec<&'static CStr>,
+    // enabled whenever VK_KHR_dynamic_rendering_local_read and its feature are
+    // supported, lets fragment shaders read color attachments of the same pass
+    pub dynamic_rendering_local_read: bool,
+    // enabled whenever VK_KHR_draw_indirect_count is supported
+    pub draw_indirect_count: Option<ash::khr::draw_indirect_count::Device>,
+    // only loaded below vulkan 1.3, the core entry points are used otherwise
+    synchronization2: Option<ash::khr::synchronization2::Device>,
+    dynamic_rendering: Option<ash::khr::dynamic_rendering::Device>,
+
+    // only loaded together with the validation layers
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+}
+
+#[must_use]
+pub struct DebugScope<'a> {
+    device: &'a Device,
+    command_buffer: vk::CommandBuffer,
+}
+
+impl Drop for DebugScope<'_> {
+    fn drop(&mut self) {
+        self.device.cmd_end_label(self.command_buffer);
+    }
 }
 
 // technically not thread safe with interior mutability but
 // the functions that modify those fields should only be
-// called in the main thread
+// called in the main thread. creating pipelines, shader modules
+// and layouts only uses raw and the pipeline cache, which is
+// what lets PipelineCompiler create them on worker threads
 unsafe impl Send for Device {}
 unsafe impl Sync for Device {}
 
+// multiview is core and always enabled, the stages that may be used with it
+// depend on the device
+#[derive(Copy, Clone, Debug)]
+pub struct MultiviewSupport {
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    pub max_view_count: u32,
+}
+
+#[derive(Copy, Clone)]
+pub struct MemoryRequirements {
+    pub requirements: vk::MemoryRequirements,
+    pub prefers_dedicated: bool,
+    pub requires_dedicated: bool,
+}
+
 #[derive(Copy, Clone)]
 pub struct Queue {
     pub raw: vk::Queue,
     pub family: u32,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum QueueType {
+    #[default]
+    Graphics,
+    Compute,
+    Transfer,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SemaphoreSubmit {
+    pub semaphore: vk::Semaphore,
+    // for waits the stages that wait, for signals the stages that have to finish first
+    pub stage_mask: vk::PipelineStageFlags2,
+    // None for binary semaphores
+    pub value: Option<u64>,
+}
+
+impl SemaphoreSubmit {
+    pub fn binary(semaphore: vk::Semaphore, stage_mask: vk::PipelineStageFlags2) -> Self {
+        Self {
+            semaphore,
+            stage_mask,
+            value: None,
+        }
+    }
+
+    pub fn timeline(
+        semaphore: vk::Semaphore,
+        value: u64,
+        stage_mask: vk::PipelineStageFlags2,
+    ) -> Self {
+        Self {
+            semaphore,
+            stage_mask,
+            value: Some(value),
+        }
+    }
+
+    fn to_vk(self) -> vk::SemaphoreSubmitInfo<'static> {
+        vk::SemaphoreSubmitInfo::default()
+            .semaphore(self.semaphore)
+            .value(self.value.unwrap_or(0))
+            .stage_mask(self.stage_mask)
+    }
+}
+
+#[derive(Default)]
+pub struct SubmitDesc<'a> {
+    pub queue: QueueType,
+    pub wait: &'a [SemaphoreSubmit],
+    pub signal: &'a [SemaphoreSubmit],
+    pub command_buffers: &'a [vk::CommandBuffer],
+    // signaled once everything in the submission has completed, may be null
+    pub fence: vk::Fence,
+}
+
 impl DeviceBuilder {
     pub fn new(instance: Arc<Instance>, physical_device: Arc<PhysicalDevice>) -> Self {
         Self {
             instance,
             physical_device,
+            mesh_shader: false,
+            pipeline_cache_path: None,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            swapchain: true,
+            sync_mode: SyncMode::default(),
+            required_extensions: Vec::new(),
+            optional_extensions: Vec::new(),
+            required_features: Vec::new(),
+            // used by SamplerDesc::with_anisotropy, RasterPipelineDesc::logic_op and
+            // differing BlendDescs, all cheap and widely supported
+            requested_features: vec![
+                DeviceFeature::SamplerAnisotropy,
+                DeviceFeature::LogicOp,
+                DeviceFeature::IndependentBlend,
+            ],
         }
     }
 
+    // build fails if the feature is not supported
+    pub fn require_feature(mut self, feature: DeviceFeature) -> Self {
+        self.required_features.push(feature);
+        self
+    }
+
+    pub fn require_features(mut self, features: &[DeviceFeature]) -> Self {
+        self.required_features.extend_from_slice(features);
+        self
+    }
+
+    // enabled when supported, check with Device::is_feature_enabled
+    pub fn request_feature(mut self, feature: DeviceFeature) -> Self {
+        self.requested_features.push(feature);
+        self
+    }
+
+    pub fn request_features(mut self, features: &[DeviceFeature]) -> Self {
+        self.requested_features.extend_from_slice(features);
+        self
+    }
+
+    // in addition to the extensions the device always needs, build fails if
+    // any of them is not supported
+    pub fn required_extensions(mut self, extensions: &[&'static CStr]) -> Self {
+        self.required_extensions.extend_from_slice(extensions);
+        self
+    }
+
+    // enabled when supported, check with Device::is_extension_enabled
+    pub fn optional_extensions(mut self, extensions: &[&'static CStr]) -> Self {
+        self.optional_extensions.extend_from_slice(extensions);
+        self
+    }
+
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    pub fn frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    pub fn pipeline_cache_path(mut self, path: Option<PathBuf>) -> Self {
+        self.pipeline_cache_path = path;
+        self
+    }
+
+    // headless devices don't need to present
+    pub fn swapchain(mut self, should_enable: bool) -> Self {
+        self.swapchain = should_enable;
+        self
+    }
+
+    pub fn mesh_shader(mut self, should_enable: bool) -> Self {
+        self.mesh_shader = should_enable;
+        self
+    }
+
     pub fn build(self) -> Result<Device> {
+        assert!(
+            self.frames_in_flight > 0,
+            "Must have at least 1 frame in flight"
+        );
+
         let queue_family_properties = unsafe {
             self.instance
                 .raw
@@ -80,8 +423,11 @@ impl DeviceBuilder {
             }
         }
 
-        let graphics_queue_family_index =
-            graphics_queue_family_index.context("Failed to find graphics queue")?;
+        let graphics_queue_family_index = self
+            .physical_device
+            .graphics_present_queue_family_index
+            .or(graphics_queue_family_index)
+            .context("Failed to find graphics queue")?;
         let compute_queue_family_index =
             compute_queue_family_index.unwrap_or(graphics_queue_family_index);
         let transfer_queue_family_index =
@@ -112,32 +458,140 @@ impl DeviceBuilder {
                 .collect()
         };
 
-        let required_extensions = vec![
-            ash::khr::swapchain::NAME,
+        // the instance may ask for a lower version than the device supports
+        let api_version = self
+            .instance
+            .api_version
+            .min(self.physical_device.properties.api_version);
+        if api_version < MIN_API_VERSION {
+            anyhow::bail!(
+                "The device supports Vulkan {}.{} but at least 1.2 is needed",
+                vk::api_version_major(api_version),
+                vk::api_version_minor(api_version)
+            );
+        }
+        let core_1_3 = api_version >= vk::API_VERSION_1_3;
+
+        let mut enabled_extensions = vec![
             ash::khr::timeline_semaphore::NAME,
             ash::ext::descriptor_indexing::NAME,
             ash::khr::synchronization2::NAME,
         ];
+        if !core_1_3 {
+            enabled_extensions.push(ash::khr::dynamic_rendering::NAME);
+        }
+        if self.swapchain {
+            enabled_extensions.push(ash::khr::swapchain::NAME);
+        }
+        enabled_extensions.extend_from_slice(&self.required_extensions);
+
+        let missing_extensions = enabled_extensions
+            .iter()
+            .filter(|ext| !supported_extensions.contains(*ext))
+            .map(|ext| ext.to_string_lossy())
+            .collect::<Vec<_>>();
+        if !missing_extensions.is_empty() {
+            anyhow::bail!(
+                "Device extensions not supported: {}",
+                missing_extensions.join(", ")
+            );
+        }
 
-        for ext in &required_extensions {
-            if !supported_extensions.contains(ext) {
-                anyhow::bail!("Device extension not supported: {}", ext.to_string_lossy());
+        for &ext in &self.optional_extensions {
+            if supported_extensions.contains(&ext) {
+                enabled_extensions.push(ext);
+            } else {
+                info!(
+                    "Optional device extension not supported: {}",
+                    ext.to_string_lossy()
+                );
             }
         }
 
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+        if self.mesh_shader {
+            if supported_extensions.contains(&ash::ext::mesh_shader::NAME) {
+                let mut features2 =
+                    vk::PhysicalDeviceFeatures2::default().push_next(&mut mesh_shader_features);
+                unsafe {
+                    self.instance
+                        .raw
+                        .get_physical_device_features2(self.physical_device.raw, &mut features2);
+                }
+            } else {
+                warn!("Mesh shaders requested but VK_EXT_mesh_shader is not supported");
+            }
+        }
+        let mesh_shader_supported = mesh_shader_features.mesh_shader == vk::TRUE;
+        if self.mesh_shader
+            && !mesh_shader_supported
+            && supported_extensions.contains(&ash::ext::mesh_shader::NAME)
+        {
+            warn!("Mesh shaders requested but the meshShader feature is not supported");
+        }
+        let task_shader_supported =
+            mesh_shader_supported && mesh_shader_features.task_shader == vk::TRUE;
+        // only enable the mesh/task stages, the other mesh shader features
+        // depend on features that are not enabled here
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default()
+            .mesh_shader(mesh_shader_supported)
+            .task_shader(task_shader_supported);
+        if mesh_shader_supported {
+            enabled_extensions.push(ash::ext::mesh_shader::NAME);
+        }
+        let mut local_read_features =
+            vk::PhysicalDeviceDynamicRenderingLocalReadFeaturesKHR::default();
+        if supported_extensions.contains(&ash::khr::dynamic_rendering_local_read::NAME) {
+            let mut features2 =
+                vk::PhysicalDeviceFeatures2::default().push_next(&mut local_read_features);
+            unsafe {
+                self.instance
+                    .raw
+                    .get_physical_device_features2(self.physical_device.raw, &mut features2);
+            }
+        }
+        let local_read_supported = local_read_features.dynamic_rendering_local_read == vk::TRUE;
+        let mut local_read_features =
+            vk::PhysicalDeviceDynamicRenderingLocalReadFeaturesKHR::default()
+                .dynamic_rendering_local_read(local_read_supported);
+        if local_read_supported {
+            enabled_extensions.push(ash::khr::dynamic_rendering_local_read::NAME);
+        }
+        let draw_indirect_count_supported =
+            supported_extensions.contains(&ash::khr::draw_indirect_count::NAME);
+        if draw_indirect_count_supported {
+            enabled_extensions.push(ash::khr::draw_indirect_count::NAME);
+        }
+        // has to be enabled whenever it is exposed, e.g. by MoltenVK
+        let portability_subset_supported =
+            supported_extensions.contains(&ash::khr::portability_subset::NAME);
+        if portability_subset_supported {
+            enabled_extensions.push(ash::khr::portability_subset::NAME);
+        }
+
         let mut timeline_sem = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
         let mut desc_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
         let mut sync2 = vk::PhysicalDeviceSynchronization2Features::default();
         let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut multiview = vk::PhysicalDeviceMultiviewFeatures::default();
+        let mut portability_subset = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
 
-        let required_extensions: Vec<*const i8> =
-            required_extensions.iter().map(|ext| ext.as_ptr()).collect();
+        // the builder's lists may repeat extensions that are enabled anyway
+        enabled_extensions.sort();
+        enabled_extensions.dedup();
+        let enabled_extension_names: Vec<*const i8> =
+            enabled_extensions.iter().map(|ext| ext.as_ptr()).collect();
 
         let mut features2 = vk::PhysicalDeviceFeatures2::default()
             .push_next(&mut timeline_sem)
             .push_next(&mut desc_indexing)
             .push_next(&mut sync2)
-            .push_next(&mut dynamic_rendering);
+            .push_next(&mut dynamic_rendering)
+            .push_next(&mut multiview);
+        // enables every feature of the subset the implementation supports
+        if portability_subset_supported {
+            features2 = features2.push_next(&mut portability_subset);
+        }
 
         unsafe {
             self.instance
@@ -145,9 +599,126 @@ impl DeviceBuilder {
                 .get_physical_device_features2(self.physical_device.raw, &mut features2);
         }
 
+        let missing_features = [
+            (timeline_sem.timeline_semaphore, "timelineSemaphore"),
+            (
+                desc_indexing.shader_uniform_texel_buffer_array_dynamic_indexing,
+                "shaderUniformTexelBufferArrayDynamicIndexing",
+            ),
+            (
+                desc_indexing.shader_storage_texel_buffer_array_dynamic_indexing,
+                "shaderStorageTexelBufferArrayDynamicIndexing",
+            ),
+            (
+                desc_indexing.shader_sampled_image_array_non_uniform_indexing,
+                "shaderSampledImageArrayNonUniformIndexing",
+            ),
+            (
+                desc_indexing.shader_storage_image_array_non_uniform_indexing,
+                "shaderStorageImageArrayNonUniformIndexing",
+            ),
+            (
+                desc_indexing.shader_uniform_texel_buffer_array_non_uniform_indexing,
+                "shaderUniformTexelBufferArrayNonUniformIndexing",
+            ),
+            (
+                desc_indexing.shader_storage_texel_buffer_array_non_uniform_indexing,
+                "shaderStorageTexelBufferArrayNonUniformIndexing",
+            ),
+            (
+                desc_indexing.descriptor_binding_sampled_image_update_after_bind,
+                "descriptorBindingSampledImageUpdateAfterBind",
+            ),
+            // unbounded storage buffer arrays get the same flags as bindless images
+            (
+                desc_indexing.descriptor_binding_storage_buffer_update_after_bind,
+                "descriptorBindingStorageBufferUpdateAfterBind",
+            ),
+            (
+                desc_indexing.descriptor_binding_update_unused_while_pending,
+                "descriptorBindingUpdateUnusedWhilePending",
+            ),
+            (
+                desc_indexing.descriptor_binding_partially_bound,
+                "descriptorBindingPartiallyBound",
+            ),
+            (
+                desc_indexing.descriptor_binding_variable_descriptor_count,
+                "descriptorBindingVariableDescriptorCount",
+            ),
+            (
+                desc_indexing.runtime_descriptor_array,
+                "runtimeDescriptorArray",
+            ),
+            (sync2.synchronization2, "synchronization2"),
+            (dynamic_rendering.dynamic_rendering, "dynamicRendering"),
+            (multiview.multiview, "multiview"),
+        ]
+        .into_iter()
+        .filter(|(supported, _)| *supported != vk::TRUE)
+        .map(|(_, name)| name)
+        .collect::<Vec<_>>();
+        if !missing_features.is_empty() {
+            anyhow::bail!(
+                "Device features not supported: {}",
+                missing_features.join(", ")
+            );
+        }
+
+        let mut multiview_properties = vk::PhysicalDeviceMultiviewProperties::default();
+        let mut properties2 =
+            vk::PhysicalDeviceProperties2::default().push_next(&mut multiview_properties);
+        unsafe {
+            self.instance
+                .raw
+                .get_physical_device_properties2(self.physical_device.raw, &mut properties2);
+        }
+        let multiview_support = MultiviewSupport {
+            geometry_shader: multiview.multiview_geometry_shader == vk::TRUE,
+            tessellation_shader: multiview.multiview_tessellation_shader == vk::TRUE,
+            max_view_count: multiview_properties.max_multiview_view_count,
+        };
+
+        // the query filled in every supported core feature, only keep the requested ones
+        let supported_features = features2.features;
+        let required_features = ALWAYS_ENABLED_FEATURES
+            .iter()
+            .chain(&self.required_features)
+            .collect::<Vec<_>>();
+        let missing_features = required_features
+            .iter()
+            .filter(|feature| !feature.is_set(&supported_features))
+            .map(|feature| feature.name())
+            .collect::<Vec<_>>();
+        if !missing_features.is_empty() {
+            anyhow::bail!(
+                "Required device features not supported: {}",
+                missing_features.join(", ")
+            );
+        }
+        let mut enabled_features = vk::PhysicalDeviceFeatures::default();
+        for &feature in required_features
+            .into_iter()
+            .chain(&self.requested_features)
+        {
+            if feature.is_set(&supported_features) {
+                *feature.field(&mut enabled_features) = vk::TRUE;
+            } else {
+                info!("Requested device feature not supported: {}", feature.name());
+            }
+        }
+        features2.features = enabled_features;
+
+        if mesh_shader_supported {
+            features2 = features2.push_next(&mut mesh_shader_features);
+        }
+        if local_read_supported {
+            features2 = features2.push_next(&mut local_read_features);
+        }
+
         let create_info = vk::DeviceCreateInfo::default()
             .queue_create_infos(&queue_create_info)
-            .enabled_extension_names(&required_extensions)
+            .enabled_extension_names(&enabled_extension_names)
             .push_next(&mut features2);
 
         let raw_device = unsafe {
@@ -157,20 +728,6 @@ impl DeviceBuilder {
                 .context("Failed to create logical device")?
         };
 
-        assert!(timeline_sem.timeline_semaphore == vk::TRUE);
-        assert!(desc_indexing.shader_uniform_texel_buffer_array_dynamic_indexing == vk::TRUE);
-        assert!(desc_indexing.shader_storage_texel_buffer_array_dynamic_indexing == vk::TRUE);
-        assert!(desc_indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE);
-        assert!(desc_indexing.shader_storage_image_array_non_uniform_indexing == vk::TRUE);
-        assert!(desc_indexing.shader_uniform_texel_buffer_array_non_uniform_indexing == vk::TRUE);
-        assert!(desc_indexing.shader_storage_texel_buffer_array_non_uniform_indexing == vk::TRUE);
-        assert!(desc_indexing.descriptor_binding_sampled_image_update_after_bind == vk::TRUE);
-        assert!(desc_indexing.descriptor_binding_update_unused_while_pending == vk::TRUE);
-        assert!(desc_indexing.descriptor_binding_partially_bound == vk::TRUE);
-        assert!(desc_indexing.descriptor_binding_variable_descriptor_count == vk::TRUE);
-        assert!(desc_indexing.runtime_descriptor_array == vk::TRUE);
-        assert!(dynamic_rendering.dynamic_rendering == vk::TRUE);
-
         let graphics_queue = Queue {
             raw: unsafe { raw_device.get_device_queue(graphics_queue_family_index, 0) },
             family: graphics_queue_family_index,
@@ -184,16 +741,54 @@ impl DeviceBuilder {
             family: transfer_queue_family_index,
         };
 
-        let mut timeline_semaphore_type_create_info = vk::SemaphoreTypeCreateInfo::default()
-            .semaphore_type(vk::SemaphoreType::TIMELINE)
-            .initial_value(0);
-        let timeline_semaphore_create_info =
-            vk::SemaphoreCreateInfo::default().push_next(&mut timeline_semaphore_type_create_info);
+        let graphics_timeline = TimelineSemaphore::new(&raw_device)?;
 
-        let graphics_timeline_semaphore =
-            unsafe { raw_device.create_semaphore(&timeline_semaphore_create_info, None) }?;
+        // signaled so the first wait on every slot returns right away
+        let frame_fences = match self.sync_mode {
+            SyncMode::Timeline => Vec::new(),
+            SyncMode::Fences => {
+                let fence_create_info =
+                    vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+                (0..self.frames_in_flight)
+                    .map(|_| unsafe { raw_device.create_fence(&fence_create_info, None) })
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Failed to create frame fences")?
+            }
+        };
 
-        Ok(Device {
+        let allocator = Allocator::new(&AllocatorCreateDesc {
+            instance: self.instance.raw.clone(),
+            device: raw_device.clone(),
+            physical_device: self.physical_device.raw,
+            debug_settings: Default::default(),
+            buffer_device_address: false,
+            allocation_sizes: Default::default(),
+        })
+        .context("Failed to create gpu allocator")?;
+
+        let pipeline_cache = PipelineCache::new(
+            &raw_device,
+            &self.physical_device.properties,
+            self.pipeline_cache_path.as_deref(),
+        )?;
+
+        let mesh_shader = mesh_shader_supported
+            .then(|| ash::ext::mesh_shader::Device::new(&self.instance.raw, &raw_device));
+
+        let draw_indirect_count = draw_indirect_count_supported
+            .then(|| ash::khr::draw_indirect_count::Device::new(&self.instance.raw, &raw_device));
+        let synchronization2 = (!core_1_3)
+            .then(|| ash::khr::synchronization2::Device::new(&self.instance.raw, &raw_device));
+        let dynamic_rendering = (!core_1_3)
+            .then(|| ash::khr::dynamic_rendering::Device::new(&self.instance.raw, &raw_device));
+
+        let debug_utils = self
+            .instance
+            .debug_utils_loader
+            .is_some()
+            .then(|| ash::ext::debug_utils::Device::new(&self.instance.raw, &raw_device));
+
+        let device = Device {
             raw: raw_device,
             physical_device: self.physical_device,
             instance: self.instance,
@@ -202,37 +797,485 @@ impl DeviceBuilder {
             compute_queue,
             transfer_queue,
 
-            graphics_timeline_semaphore,
+            graphics_timeline,
+            sync_mode: self.sync_mode,
+            frame_fences,
             absolute_frame_index: UnsafeCell::new(0),
-        })
+            frames_in_flight: self.frames_in_flight,
+            deletion_queue: Mutex::new(DeletionQueue::default()),
+            resources: Mutex::new(ResourceManager::default()),
+
+            pipeline_cache,
+            allocator: ManuallyDrop::new(Mutex::new(allocator)),
+
+            mesh_shader,
+            task_shader_supported,
+            multiview: multiview_support,
+            enabled_features,
+            enabled_extensions,
+            dynamic_rendering_local_read: local_read_supported,
+            draw_indirect_count,
+            synchronization2,
+            dynamic_rendering,
+
+            debug_utils,
+        };
+
+        device.set_debug_name(device.graphics_timeline.raw, "graphics timeline");
+        for (frame, fence) in device.frame_fences.iter().enumerate() {
+            device.set_debug_name(*fence, &format!("frame fence {frame}"));
+        }
+
+        Ok(device)
     }
 }
 
 impl Device {
+    // shows up in validation messages and captures instead of the raw handle
+    pub fn set_debug_name<T: vk::Handle>(&self, handle: T, name: &str) {
+        let Some(debug_utils) = &self.debug_utils else {
+            return;
+        };
+        let Ok(name) = CString::new(name) else {
+            return;
+        };
+
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+        if let Err(e) = unsafe { debug_utils.set_debug_utils_object_name(&name_info) } {
+            warn!("Failed to set debug name {name:?}: {e}");
+        }
+    }
+
+    pub fn cmd_begin_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        let Some(debug_utils) = &self.debug_utils else {
+            return;
+        };
+        let Ok(name) = CString::new(name) else {
+            return;
+        };
+
+        let label = vk::DebugUtilsLabelEXT::default()
+            .label_name(&name)
+            .color(color);
+        unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+
+    pub fn cmd_end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+
+    // labels everything recorded until the returned scope is dropped
+    pub fn debug_scope(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+        color: [f32; 4],
+    ) -> DebugScope<'_> {
+        self.cmd_begin_label(command_buffer, name, color);
+        DebugScope {
+            device: self,
+            command_buffer,
+        }
+    }
+
     pub fn absolute_frame_index(&self) -> usize {
         unsafe { *self.absolute_frame_index.get() }
     }
 
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
     pub fn frame_index(&self) -> usize {
-        self.absolute_frame_index() % FRAMES_IN_FLIGHT
+        self.absolute_frame_index() % self.frames_in_flight
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    pub fn is_feature_enabled(&self, feature: DeviceFeature) -> bool {
+        feature.is_set(&self.enabled_features)
+    }
+
+    // bit i renders view i into layer i of every attachment, 0 disables multiview
+    pub fn validate_view_mask(&self, view_mask: u32) -> Result<()> {
+        let view_count = 32 - view_mask.leading_zeros();
+        if view_count > self.multiview.max_view_count {
+            anyhow::bail!(
+                "View mask {view_mask:#b} needs {view_count} views but maxMultiviewViewCount is {}",
+                self.multiview.max_view_count
+            );
+        }
+        Ok(())
+    }
+
+    // e.g. ash::khr::draw_indirect_count::NAME
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.enabled_extensions.contains(&name)
+    }
+
+    // the fence the current frame's graphics submit has to signal in fences
+    // mode, null in timeline mode. it is reset here, so only call this right
+    // before that submit
+    pub fn frame_fence(&self) -> Result<vk::Fence> {
+        let Some(&fence) = self.frame_fences.get(self.frame_index()) else {
+            return Ok(vk::Fence::null());
+        };
+        unsafe { self.raw.reset_fences(std::slice::from_ref(&fence))? };
+        Ok(fence)
     }
 
     pub fn begin_frame(&self) -> Result<()> {
-        // wait for the frame submitted FRAMES_IN_FLIGHT ago
+        // wait for the frame submitted frames_in_flight ago, waiting again
+        // without a submit in between is fine in both modes
         let absolute_frame_index = self.absolute_frame_index();
-        if absolute_frame_index >= FRAMES_IN_FLIGHT {
-            let wait_value = (absolute_frame_index - FRAMES_IN_FLIGHT + 1) as u64;
-            let wait_info = vk::SemaphoreWaitInfo::default()
-                .semaphores(std::slice::from_ref(&self.graphics_timeline_semaphore))
-                .values(std::slice::from_ref(&wait_value));
+        if let Some(fence) = self.frame_fences.get(self.frame_index()) {
+            unsafe {
+                self.raw
+                    .wait_for_fences(std::slice::from_ref(fence), true, u64::MAX)?
+            };
+        }
+        if let Some(wait_value) =
+            TimelineSemaphore::wait_value_for_frame(absolute_frame_index, self.frames_in_flight)
+        {
+            if self.sync_mode == SyncMode::Timeline {
+                self.graphics_timeline.wait(wait_value)?;
+            }
 
-            unsafe { self.raw.wait_semaphores(&wait_info, u64::MAX)? };
+            let completed = self
+                .deletion_queue
+                .lock()
+                .unwrap()
+                .take_completed(absolute_frame_index - self.frames_in_flight);
+            completed.into_iter().for_each(|destroy| destroy(self));
         }
 
         Ok(())
     }
 
+    // runs every pending destroy right away, the device has to be idle
+    pub fn flush_deferred_destroys(&self) {
+        let pending = self.deletion_queue.lock().unwrap().take_all();
+        pending.into_iter().for_each(|destroy| destroy(self));
+    }
+
+    pub fn resources(&self) -> MutexGuard<'_, ResourceManager> {
+        self.resources.lock().unwrap()
+    }
+
+    // stale handles are ignored and return false
+    pub fn destroy_buffer(&self, handle: BufferHandle) -> bool {
+        let Some(buffer) = self.resources().remove_buffer(handle) else {
+            return false;
+        };
+        self.defer_destroy(move |_| drop(buffer));
+        true
+    }
+
+    pub fn destroy_image(&self, handle: ImageHandle) -> bool {
+        let Some(image) = self.resources().remove_image(handle) else {
+            return false;
+        };
+        self.defer_destroy(move |_| drop(image));
+        true
+    }
+
+    // runs destroy once the frames that may still use the resource have
+    // finished, instead of waiting for the whole device to go idle
+    pub fn defer_destroy(&self, destroy: impl FnOnce(&Device) + Send + 'static) {
+        self.deletion_queue
+            .lock()
+            .unwrap()
+            .push(self.absolute_frame_index(), destroy);
+    }
+
+    pub fn allocate(&self, desc: &AllocationCreateDesc<'_>) -> Result<Allocation> {
+        self.allocator
+            .lock()
+            .unwrap()
+            .allocate(desc)
+            .with_context(|| format!("Failed to allocate memory for {}", desc.name))
+    }
+
+    pub fn free(&self, allocation: Allocation) -> Result<()> {
+        self.allocator
+            .lock()
+            .unwrap()
+            .free(allocation)
+            .context("Failed to free allocation")
+    }
+
+    pub fn buffer_memory_requirements(&self, buffer: vk::Buffer) -> MemoryRequirements {
+        let info = vk::BufferMemoryRequirementsInfo2::default().buffer(buffer);
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+        unsafe {
+            self.raw
+                .get_buffer_memory_requirements2(&info, &mut requirements)
+        };
+        let requirements = requirements.memory_requirements;
+
+        MemoryRequirements {
+            requirements,
+            prefers_dedicated: dedicated.prefers_dedicated_allocation == vk::TRUE,
+            requires_dedicated: dedicated.requires_dedicated_allocation == vk::TRUE,
+        }
+    }
+
+    pub fn image_memory_requirements(&self, image: vk::Image) -> MemoryRequirements {
+        let info = vk::ImageMemoryRequirementsInfo2::default().image(image);
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+        unsafe {
+            self.raw
+                .get_image_memory_requirements2(&info, &mut requirements)
+        };
+        let requirements = requirements.memory_requirements;
+
+        MemoryRequirements {
+            requirements,
+            prefers_dedicated: dedicated.prefers_dedicated_allocation == vk::TRUE,
+            requires_dedicated: dedicated.requires_dedicated_allocation == vk::TRUE,
+        }
+    }
+
+    // highest sample count usable for both color and depth attachments
+    pub fn max_usable_sample_count(&self) -> vk::SampleCountFlags {
+        let limits = &self.physical_device.properties.limits;
+        let counts =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        [
+            vk::SampleCountFlags::TYPE_64,
+            vk::SampleCountFlags::TYPE_32,
+            vk::SampleCountFlags::TYPE_16,
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_2,
+        ]
+        .into_iter()
+        .find(|&count| counts.contains(count))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1)
+    }
+
+    pub fn queue(&self, queue_type: QueueType) -> Queue {
+        match queue_type {
+            QueueType::Graphics => self.graphics_queue,
+            QueueType::Compute => self.compute_queue,
+            QueueType::Transfer => self.transfer_queue,
+        }
+    }
+
+    // a single SubmitInfo2 on the chosen queue
+    pub fn submit(&self, desc: &SubmitDesc<'_>) -> Result<()> {
+        let wait = desc
+            .wait
+            .iter()
+            .map(|semaphore| semaphore.to_vk())
+            .collect::<Vec<_>>();
+        let signal = desc
+            .signal
+            .iter()
+            .map(|semaphore| semaphore.to_vk())
+            .collect::<Vec<_>>();
+        self.submit2(
+            self.queue(desc.queue),
+            desc.command_buffers,
+            &wait,
+            &signal,
+            desc.fence,
+        )
+        .with_context(|| format!("Failed to submit to the {:?} queue", desc.queue))
+    }
+
+    fn submit2(
+        &self,
+        queue: Queue,
+        command_buffers: &[vk::CommandBuffer],
+        wait: &[vk::SemaphoreSubmitInfo<'_>],
+        signal: &[vk::SemaphoreSubmitInfo<'_>],
+        fence: vk::Fence,
+    ) -> Result<()> {
+        let command_buffer_infos = command_buffers
+            .iter()
+            .map(|&command_buffer| {
+                vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)
+            })
+            .collect::<Vec<_>>();
+        let submit_info = vk::SubmitInfo2::default()
+            .wait_semaphore_infos(wait)
+            .signal_semaphore_infos(signal)
+            .command_buffer_infos(&command_buffer_infos);
+
+        self.queue_submit2(queue.raw, std::slice::from_ref(&submit_info), fence)?;
+        Ok(())
+    }
+
+    // the synchronization2 and dynamic rendering commands go through these so
+    // they work below vulkan 1.3 as well
+    pub fn queue_submit2(
+        &self,
+        queue: vk::Queue,
+        submits: &[vk::SubmitInfo2<'_>],
+        fence: vk::Fence,
+    ) -> ash::prelude::VkResult<()> {
+        match &self.synchronization2 {
+            Some(synchronization2) => unsafe {
+                synchronization2.queue_submit2(queue, submits, fence)
+            },
+            None => unsafe { self.raw.queue_submit2(queue, submits, fence) },
+        }
+    }
+
+    pub fn cmd_pipeline_barrier2(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        dependency_info: &vk::DependencyInfo<'_>,
+    ) {
+        match &self.synchronization2 {
+            Some(synchronization2) => unsafe {
+                synchronization2.cmd_pipeline_barrier2(command_buffer, dependency_info)
+            },
+            None => unsafe {
+                self.raw
+                    .cmd_pipeline_barrier2(command_buffer, dependency_info)
+            },
+        }
+    }
+
+    pub fn cmd_write_timestamp2(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        stage: vk::PipelineStageFlags2,
+        query_pool: vk::QueryPool,
+        query: u32,
+    ) {
+        match &self.synchronization2 {
+            Some(synchronization2) => unsafe {
+                synchronization2.cmd_write_timestamp2(command_buffer, stage, query_pool, query)
+            },
+            None => unsafe {
+                self.raw
+                    .cmd_write_timestamp2(command_buffer, stage, query_pool, query)
+            },
+        }
+    }
+
+    pub fn cmd_begin_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        rendering_info: &vk::RenderingInfo<'_>,
+    ) {
+        match &self.dynamic_rendering {
+            Some(dynamic_rendering) => unsafe {
+                dynamic_rendering.cmd_begin_rendering(command_buffer, rendering_info)
+            },
+            None => unsafe { self.raw.cmd_begin_rendering(command_buffer, rendering_info) },
+        }
+    }
+
+    pub fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        match &self.dynamic_rendering {
+            Some(dynamic_rendering) => unsafe {
+                dynamic_rendering.cmd_end_rendering(command_buffer)
+            },
+            None => unsafe { self.raw.cmd_end_rendering(command_buffer) },
+        }
+    }
+
+    // overlaps with graphics, sync with a timeline semaphore. exclusive resources
+    // also need a release/acquire barrier pair when the queue families differ
+    pub fn submit_compute(
+        &self,
+        command_buffers: &[vk::CommandBuffer],
+        wait: &[vk::SemaphoreSubmitInfo<'_>],
+        signal: &[vk::SemaphoreSubmitInfo<'_>],
+    ) -> Result<()> {
+        self.submit2(
+            self.compute_queue,
+            command_buffers,
+            wait,
+            signal,
+            vk::Fence::null(),
+        )
+        .context("Failed to submit compute work")
+    }
+
+    // records into a transient command buffer, submits it to the graphics
+    // queue and blocks until it has completed. for one off work at load time
+    // that needs the graphics queue, e.g. blits
+    pub fn submit_immediate<F>(&self, record: F) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer) -> Result<()>,
+    {
+        let pool_create_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(self.graphics_queue.family);
+        let pool = unsafe { self.raw.create_command_pool(&pool_create_info, None)? };
+        let fence = match unsafe { self.raw.create_fence(&vk::FenceCreateInfo::default(), None) } {
+            Ok(fence) => fence,
+            Err(e) => {
+                unsafe { self.raw.destroy_command_pool(pool, None) };
+                return Err(e.into());
+            }
+        };
+
+        let result = (|| -> Result<()> {
+            let alloc_info = vk::CommandBufferAllocateInfo::default()
+                .command_pool(pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1);
+            let command_buffer = unsafe { self.raw.allocate_command_buffers(&alloc_info)?[0] };
+
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe { self.raw.begin_command_buffer(command_buffer, &begin_info)? };
+            record(command_buffer)?;
+            unsafe { self.raw.end_command_buffer(command_buffer)? };
+
+            self.submit2(self.graphics_queue, &[command_buffer], &[], &[], fence)?;
+            unsafe {
+                self.raw
+                    .wait_for_fences(std::slice::from_ref(&fence), true, u64::MAX)?
+            };
+            Ok(())
+        })();
+
+        unsafe {
+            self.raw.destroy_fence(fence, None);
+            self.raw.destroy_command_pool(pool, None);
+        }
+        result.context("Failed to submit immediate commands")
+    }
+
+    pub fn cmd_draw_mesh_tasks(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) {
+        let mesh_shader = self
+            .mesh_shader
+            .as_ref()
+            .expect("Mesh shaders are not enabled on this device");
+        unsafe {
+            mesh_shader.cmd_draw_mesh_tasks(
+                command_buffer,
+                group_count_x,
+                group_count_y,
+                group_count_z,
+            )
+        };
+    }
+
     pub fn finish_frame(&self) {
+        super::profiling::frame_mark();
         unsafe {
             *self.absolute_frame_index.get() += 1;
         }
@@ -244,8 +1287,19 @@ impl Drop for Device {
         unsafe {
             let _ = self.raw.device_wait_idle();
 
-            self.raw
-                .destroy_semaphore(self.graphics_timeline_semaphore, None);
+            let pending = self.deletion_queue.get_mut().unwrap().take_all();
+            pending.into_iter().for_each(|destroy| destroy(self));
+
+            if let Err(e) = self.pipeline_cache.save(&self.raw) {
+                warn!("{e:#}");
+            }
+            self.pipeline_cache.destroy(&self.raw);
+            ManuallyDrop::drop(&mut self.allocator);
+
+            self.graphics_timeline.destroy();
+            self.frame_fences
+                .iter()
+                .for_each(|fence| self.raw.destroy_fence(*fence, None));
             self.raw.destroy_device(None);
 
             if let Some(debug_messenger) = self.instance.debug_messenger
@@ -0,0 +1,459 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use egui::epaint::{ImageDelta, Primitive, Vertex};
+use egui::{ClippedPrimitive, ImageData, TextureFilter, TextureId, TexturesDelta};
+use gpu_allocator::MemoryLocation;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::buffer::{Buffer, BufferDesc};
+use super::descriptor::DescriptorPool;
+use super::device::Device;
+use super::image::{Image, ImageDesc};
+use super::pipeline::{
+    self, BlendDesc, RasterPipeline, RasterPipelineDesc, ShaderDesc, ShaderStage,
+    VertexAttributeDesc, VertexBindingDesc,
+};
+use super::rendering::{self, LoadOp};
+use super::shader_compiler::{ShaderCompiler, ShaderCompilerOptions};
+use super::upload::Uploader;
+
+// enough for the font atlas and a handful of user textures
+const MAX_TEXTURES: u32 = 256;
+
+struct EguiTexture {
+    image: Image,
+    set: vk::DescriptorSet,
+    // kept so partial updates can be applied without reading the image back
+    pixels: Vec<u8>,
+    size: [usize; 2],
+}
+
+// one pair per frame in flight, a frame's buffers are only rewritten once
+// Device::begin_frame has waited for it
+#[derive(Default)]
+struct FrameBuffers {
+    vertices: Option<Buffer>,
+    indices: Option<Buffer>,
+}
+
+// draws egui output on top of an already rendered color attachment
+pub struct EguiRenderer {
+    device: Arc<Device>,
+    pipeline: RasterPipeline,
+    descriptor_pool: DescriptorPool,
+    uploader: Uploader,
+    linear_sampler: vk::Sampler,
+    nearest_sampler: vk::Sampler,
+    textures: HashMap<TextureId, EguiTexture>,
+    frames: Vec<FrameBuffers>,
+}
+
+impl EguiRenderer {
+    pub fn new(
+        device: Arc<Device>,
+        shader_compiler: &ShaderCompiler,
+        color_format: vk::Format,
+    ) -> Result<Self> {
+        let options = ShaderCompilerOptions::default();
+        let vert = shader_compiler.compile_slang("egui/egui_vert.slang", "main", &options)?;
+        let frag = shader_compiler.compile_slang("egui/egui_frag.slang", "main", &options)?;
+
+        let pipeline = pipeline::create_raster_pipeline(
+            device.clone(),
+            RasterPipelineDesc {
+                shaders: vec![
+                    ShaderDesc::new(vert, ShaderStage::Vertex),
+                    ShaderDesc::new(frag, ShaderStage::Fragment),
+                ],
+                color_attachments: vec![color_format],
+                blend: vec![BlendDesc::premultiplied_alpha()],
+                vertex_bindings: vec![VertexBindingDesc::per_vertex(
+                    0,
+                    std::mem::size_of::<Vertex>() as u32,
+                )],
+                vertex_attributes: vec![
+                    VertexAttributeDesc {
+                        location: 0,
+                        binding: 0,
+                        format: vk::Format::R32G32_SFLOAT,
+                        offset: 0,
+                    },
+                    VertexAttributeDesc {
+                        location: 1,
+                        binding: 0,
+                        format: vk::Format::R32G32_SFLOAT,
+                        offset: 8,
+                    },
+                    VertexAttributeDesc {
+                        location: 2,
+                        binding: 0,
+                        format: vk::Format::R8G8B8A8_UNORM,
+                        offset: 16,
+                    },
+                ],
+                ..Default::default()
+            },
+        )?;
+        device.set_debug_name(pipeline.pipeline, "egui");
+
+        let descriptor_pool = device.create_descriptor_pool(
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: MAX_TEXTURES,
+            }],
+            MAX_TEXTURES,
+        )?;
+
+        let linear_sampler = create_sampler(&device, vk::Filter::LINEAR)?;
+        let nearest_sampler = create_sampler(&device, vk::Filter::NEAREST)?;
+
+        let frames = (0..device.frames_in_flight())
+            .map(|_| FrameBuffers::default())
+            .collect();
+
+        Ok(Self {
+            uploader: Uploader::new(device.clone())?,
+            device,
+            pipeline,
+            descriptor_pool,
+            linear_sampler,
+            nearest_sampler,
+            textures: HashMap::new(),
+            frames,
+        })
+    }
+
+    // target_view has to be in COLOR_ATTACHMENT_OPTIMAL, its contents are kept
+    pub fn paint(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        target_view: vk::ImageView,
+        extent: vk::Extent2D,
+        pixels_per_point: f32,
+        primitives: &[ClippedPrimitive],
+        textures_delta: &TexturesDelta,
+    ) -> Result<()> {
+        for (id, delta) in &textures_delta.set {
+            self.update_texture(*id, delta)
+                .with_context(|| format!("Failed to update egui texture {id:?}"))?;
+        }
+
+        let meshes = primitives
+            .iter()
+            .filter_map(|primitive| match &primitive.primitive {
+                Primitive::Mesh(mesh) if !mesh.indices.is_empty() => {
+                    Some((primitive.clip_rect, mesh))
+                }
+                // paint callbacks need a renderer specific api, not supported
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if !meshes.is_empty() && extent.width > 0 && extent.height > 0 {
+            self.write_geometry(&meshes)?;
+            self.record(
+                command_buffer,
+                target_view,
+                extent,
+                pixels_per_point,
+                &meshes,
+            )?;
+        }
+
+        // freed once every frame that could still sample them has finished
+        for id in &textures_delta.free {
+            if let Some(texture) = self.textures.remove(id) {
+                self.retire_texture(texture);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_texture(&mut self, id: TextureId, delta: &ImageDelta) -> Result<()> {
+        let size = delta.image.size();
+        let pixels: Vec<u8> = match &delta.image {
+            ImageData::Color(image) => image
+                .pixels
+                .iter()
+                .flat_map(|color| color.to_array())
+                .collect(),
+            ImageData::Font(image) => image
+                .srgba_pixels(None)
+                .flat_map(|color| color.to_array())
+                .collect(),
+        };
+
+        let (pixels, size) = match (delta.pos, self.textures.get(&id)) {
+            (Some([x, y]), Some(texture)) => {
+                // patch the cpu copy and reupload the whole texture, egui does
+                // this when new glyphs are added to the font atlas
+                let mut patched = texture.pixels.clone();
+                let row_bytes = size[0] * 4;
+                for row in 0..size[1] {
+                    let dst = ((y + row) * texture.size[0] + x) * 4;
+                    let src = row * row_bytes;
+                    patched[dst..dst + row_bytes].copy_from_slice(&pixels[src..src + row_bytes]);
+                }
+                (patched, texture.size)
+            }
+            (Some(_), None) => anyhow::bail!("Partial update of an unknown texture"),
+            (None, _) => (pixels, size),
+        };
+
+        let extent = vk::Extent2D {
+            width: size[0] as u32,
+            height: size[1] as u32,
+        };
+        let mut image = self.device.create_image(ImageDesc::new_2d(
+            format!("egui texture {id:?}"),
+            extent,
+            vk::Format::R8G8B8A8_SRGB,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        ))?;
+        self.uploader
+            .upload_image(&pixels, &mut image, extent.into())?;
+        self.uploader.flush()?;
+
+        let set = self
+            .pipeline
+            .allocate_descriptor_set(&self.descriptor_pool, 0)?;
+        let sampler = match delta.options.magnification {
+            TextureFilter::Linear => self.linear_sampler,
+            TextureFilter::Nearest => self.nearest_sampler,
+        };
+        self.device.update_combined_image_sampler(
+            set,
+            0,
+            image.view,
+            sampler,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        let texture = EguiTexture {
+            image,
+            set,
+            pixels,
+            size,
+        };
+        if let Some(previous) = self.textures.insert(id, texture) {
+            self.retire_texture(previous);
+        }
+
+        Ok(())
+    }
+
+    fn retire_texture(&self, texture: EguiTexture) {
+        let pool = self.descriptor_pool.raw;
+        let EguiTexture { image, set, .. } = texture;
+        self.device.defer_destroy(move |device| {
+            unsafe {
+                let _ = device
+                    .raw
+                    .free_descriptor_sets(pool, std::slice::from_ref(&set));
+            }
+            drop(image);
+        });
+    }
+
+    // grows to the next power of two so a slowly growing ui doesn't
+    // reallocate every frame
+    fn write_geometry(&mut self, meshes: &[(egui::Rect, &egui::Mesh)]) -> Result<()> {
+        let vertex_count: usize = meshes.iter().map(|(_, mesh)| mesh.vertices.len()).sum();
+        let index_count: usize = meshes.iter().map(|(_, mesh)| mesh.indices.len()).sum();
+
+        let frame = &mut self.frames[self.device.frame_index()];
+        let vertices = ensure_capacity(
+            &self.device,
+            &mut frame.vertices,
+            "egui vertices",
+            (vertex_count * std::mem::size_of::<Vertex>()) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let mut offset = 0;
+        for (_, mesh) in meshes {
+            vertices.write_slice(&mesh.vertices, offset)?;
+            offset += std::mem::size_of_val(mesh.vertices.as_slice());
+        }
+
+        let indices = ensure_capacity(
+            &self.device,
+            &mut frame.indices,
+            "egui indices",
+            (index_count * std::mem::size_of::<u32>()) as u64,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        )?;
+        let mut offset = 0;
+        for (_, mesh) in meshes {
+            indices.write_slice(&mesh.indices, offset)?;
+            offset += std::mem::size_of_val(mesh.indices.as_slice());
+        }
+
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        target_view: vk::ImageView,
+        extent: vk::Extent2D,
+        pixels_per_point: f32,
+        meshes: &[(egui::Rect, &egui::Mesh)],
+    ) -> Result<()> {
+        let frame = &self.frames[self.device.frame_index()];
+        let (Some(vertices), Some(indices)) = (&frame.vertices, &frame.indices) else {
+            return Ok(());
+        };
+
+        let _scope = self
+            .device
+            .debug_scope(command_buffer, "egui", [0.6, 0.9, 0.6, 1.0]);
+
+        let color_attachment = rendering::color_attachment(target_view, LoadOp::Load);
+        let rendering_info =
+            rendering::rendering_info(extent, std::slice::from_ref(&color_attachment), None);
+        let _rendering = self.device.begin_rendering(command_buffer, &rendering_info);
+
+        let screen_size = [
+            extent.width as f32 / pixels_per_point,
+            extent.height as f32 / pixels_per_point,
+        ];
+
+        unsafe {
+            self.device.raw.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            self.device.raw.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                std::slice::from_ref(&vertices.raw),
+                &[0],
+            );
+            self.device.raw.cmd_bind_index_buffer(
+                command_buffer,
+                indices.raw,
+                0,
+                vk::IndexType::UINT32,
+            );
+        }
+        self.device
+            .cmd_set_viewport_unflipped(command_buffer, extent);
+        self.pipeline
+            .push_constants(command_buffer, vk::ShaderStageFlags::VERTEX, &screen_size)?;
+
+        let mut vertex_offset = 0;
+        let mut first_index = 0;
+        for (clip_rect, mesh) in meshes {
+            let mesh_vertex_offset = vertex_offset;
+            let mesh_first_index = first_index;
+            vertex_offset += mesh.vertices.len() as i32;
+            first_index += mesh.indices.len() as u32;
+
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+
+            // clip rects are in points and may extend past the target
+            let min_x = (clip_rect.min.x * pixels_per_point).round().max(0.0) as u32;
+            let min_y = (clip_rect.min.y * pixels_per_point).round().max(0.0) as u32;
+            let max_x =
+                ((clip_rect.max.x * pixels_per_point).round().max(0.0) as u32).min(extent.width);
+            let max_y =
+                ((clip_rect.max.y * pixels_per_point).round().max(0.0) as u32).min(extent.height);
+            if max_x <= min_x || max_y <= min_y {
+                continue;
+            }
+
+            let scissor = vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: min_x as i32,
+                    y: min_y as i32,
+                },
+                extent: vk::Extent2D {
+                    width: max_x - min_x,
+                    height: max_y - min_y,
+                },
+            };
+
+            unsafe {
+                self.device
+                    .raw
+                    .cmd_set_scissor(command_buffer, 0, std::slice::from_ref(&scissor));
+                self.device.raw.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline.layout(),
+                    0,
+                    std::slice::from_ref(&texture.set),
+                    &[],
+                );
+                self.device.raw.cmd_draw_indexed(
+                    command_buffer,
+                    mesh.indices.len() as u32,
+                    1,
+                    mesh_first_index,
+                    mesh_vertex_offset,
+                    0,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn ensure_capacity<'a>(
+    device: &Arc<Device>,
+    buffer: &'a mut Option<Buffer>,
+    name: &str,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+) -> Result<&'a mut Buffer> {
+    // the frame's previous buffer finished on the gpu, it can be dropped right away
+    if buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+        *buffer = Some(device.create_buffer(BufferDesc::new(
+            name,
+            size.next_power_of_two(),
+            usage,
+            MemoryLocation::CpuToGpu,
+        ))?);
+    }
+    Ok(buffer.as_mut().unwrap())
+}
+
+fn create_sampler(device: &Device, filter: vk::Filter) -> Result<vk::Sampler> {
+    let create_info = vk::SamplerCreateInfo::default()
+        .mag_filter(filter)
+        .min_filter(filter)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .max_lod(vk::LOD_CLAMP_NONE);
+
+    let sampler = unsafe {
+        device
+            .raw
+            .create_sampler(&create_info, None)
+            .context("Failed to create egui sampler")?
+    };
+    Ok(sampler)
+}
+
+impl Drop for EguiRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.raw.device_wait_idle();
+        }
+        self.textures.clear();
+        // retired textures free their sets from the pool, which is dropped after this
+        self.device.flush_deferred_destroys();
+        unsafe {
+            self.device.raw.destroy_sampler(self.linear_sampler, None);
+            self.device.raw.destroy_sampler(self.nearest_sampler, None);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::device::Device;
+use super::mesh::{Mesh, VERTEX_BINDING};
+use super::pipeline::{VertexAttributeDesc, VertexBindingDesc};
+use super::upload::Uploader;
+
+// the interleaved layout every loaded primitive uses. attributes missing from
+// the file are zeroed
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GltfVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+impl GltfVertex {
+    pub fn vertex_bindings() -> Vec<VertexBindingDesc> {
+        vec![VertexBindingDesc::per_vertex(
+            VERTEX_BINDING,
+            std::mem::size_of::<Self>() as u32,
+        )]
+    }
+
+    // position at location 0, normal at 1 and uv at 2
+    pub fn vertex_attributes() -> Vec<VertexAttributeDesc> {
+        vec![
+            VertexAttributeDesc {
+                location: 0,
+                binding: VERTEX_BINDING,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: std::mem::offset_of!(Self, position) as u32,
+            },
+            VertexAttributeDesc {
+                location: 1,
+                binding: VERTEX_BINDING,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: std::mem::offset_of!(Self, normal) as u32,
+            },
+            VertexAttributeDesc {
+                location: 2,
+                binding: VERTEX_BINDING,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: std::mem::offset_of!(Self, uv) as u32,
+            },
+        ]
+    }
+}
+
+// one Mesh per triangle primitive, in the order of the meshes in the file.
+// the vertex descs are ready to be put into a RasterPipelineDesc
+pub struct GltfModel {
+    pub meshes: Vec<Mesh>,
+    pub vertex_bindings: Vec<VertexBindingDesc>,
+    pub vertex_attributes: Vec<VertexAttributeDesc>,
+}
+
+impl GltfModel {
+    // reads .gltf files with external or embedded buffers and .glb files.
+    // images are not loaded, the meshes are valid once the uploader is flushed
+    pub fn load<P>(device: &Arc<Device>, uploader: &mut Uploader, path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let ::gltf::Gltf { document, blob } = ::gltf::Gltf::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let buffers = ::gltf::import_buffers(&document, path.parent(), blob)
+            .with_context(|| format!("Failed to load the buffers of {}", path.display()))?;
+
+        let mut meshes = Vec::new();
+        for mesh in document.meshes() {
+            let mesh_name = mesh.name().unwrap_or("mesh");
+            for primitive in mesh.primitives() {
+                let name = format!("{} {mesh_name} {}", path.display(), primitive.index());
+                if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+                    anyhow::bail!(
+                        "{name} uses {:?}, only triangles are supported",
+                        primitive.mode()
+                    );
+                }
+
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()][..]));
+                let mut vertices = reader
+                    .read_positions()
+                    .with_context(|| format!("{name} has no positions"))?
+                    .map(|position| GltfVertex {
+                        position,
+                        ..Default::default()
+                    })
+                    .collect::<Vec<_>>();
+                if let Some(normals) = reader.read_normals() {
+                    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                        vertex.normal = normal;
+                    }
+                }
+                if let Some(uvs) = reader.read_tex_coords(0) {
+                    for (vertex, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                        vertex.uv = uv;
+                    }
+                }
+
+                // non indexed primitives draw their vertices in order
+                let indices = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                    None => (0..vertices.len() as u32).collect(),
+                };
+
+                meshes.push(Mesh::new(device, uploader, &name, &vertices, &indices)?);
+            }
+        }
+
+        Ok(Self {
+            meshes,
+            vertex_bindings: GltfVertex::vertex_bindings(),
+            vertex_attributes: GltfVertex::vertex_attributes(),
+        })
+    }
+}
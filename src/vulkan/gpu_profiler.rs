@@ -0,0 +1,260 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use std::sync::Arc;
+
+use super::device::Device;
+use super::profiling;
+
+#[derive(Clone, Debug)]
+pub struct GpuZone {
+    pub name: String,
+    pub duration_ms: f64,
+}
+
+struct PendingZone {
+    name: String,
+    begin_query: u32,
+    end_query: Option<u32>,
+    span: profiling::GpuSpan,
+}
+
+#[derive(Default)]
+struct FrameQueries {
+    zones: Vec<PendingZone>,
+    open_zones: Vec<usize>,
+    used_queries: u32,
+}
+
+// every frame in flight owns a slice of the query pool, a slice is only read
+// back once the device has waited for the frame that last wrote it
+pub struct GpuProfiler {
+    device: Arc<Device>,
+    query_pool: vk::QueryPool,
+    queries_per_frame: u32,
+    timestamp_period: f64,
+    timestamp_mask: u64,
+
+    frames: Vec<FrameQueries>,
+    results: Vec<GpuZone>,
+
+    tracy_context: profiling::GpuContext,
+}
+
+impl GpuProfiler {
+    pub fn new(device: Arc<Device>, max_zones_per_frame: u32) -> Result<Self> {
+        let limits = &device.physical_device.properties.limits;
+        if limits.timestamp_compute_and_graphics != vk::TRUE {
+            anyhow::bail!("Device doesn't support timestamps on graphics queues");
+        }
+
+        let queue_family_properties = unsafe {
+            device
+                .instance
+                .raw
+                .get_physical_device_queue_family_properties(device.physical_device.raw)
+        };
+        let valid_bits =
+            queue_family_properties[device.graphics_queue.family as usize].timestamp_valid_bits;
+        if valid_bits == 0 {
+            anyhow::bail!("Graphics queue doesn't support timestamps");
+        }
+        let timestamp_mask = if valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1 << valid_bits) - 1
+        };
+
+        let queries_per_frame = max_zones_per_frame * 2;
+        let frames_in_flight = device.frames_in_flight();
+        let query_pool_create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(queries_per_frame * frames_in_flight as u32);
+        let query_pool = unsafe {
+            device
+                .raw
+                .create_query_pool(&query_pool_create_info, None)
+                .context("Failed to create timestamp query pool")?
+        };
+        device.set_debug_name(query_pool, "gpu profiler");
+
+        let timestamp_period = limits.timestamp_period as f64;
+        let tracy_context = if profiling::enabled() {
+            let gpu_timestamp = read_timestamp(&device, query_pool)?;
+            profiling::GpuContext::new(
+                "graphics",
+                (gpu_timestamp & timestamp_mask) as i64,
+                limits.timestamp_period,
+            )
+        } else {
+            profiling::GpuContext::new("graphics", 0, limits.timestamp_period)
+        };
+
+        Ok(Self {
+            device,
+            query_pool,
+            queries_per_frame,
+            timestamp_period,
+            timestamp_mask,
+            frames: (0..frames_in_flight)
+                .map(|_| FrameQueries::default())
+                .collect(),
+            results: Vec::new(),
+            tracy_context,
+        })
+    }
+
+    fn first_query(&self) -> u32 {
+        self.device.frame_index() as u32 * self.queries_per_frame
+    }
+
+    // reads back the zones recorded frames_in_flight ago into this slot, call
+    // after Device::begin_frame so the frame is known to be finished
+    pub fn collect(&mut self) -> Result<&[GpuZone]> {
+        let first_query = self.first_query();
+        let frame = &mut self.frames[self.device.frame_index()];
+        if frame.used_queries == 0 {
+            return Ok(&self.results);
+        }
+        if !frame.open_zones.is_empty() {
+            anyhow::bail!("GPU zones were not ended in the previous frame");
+        }
+
+        let mut timestamps = vec![0u64; frame.used_queries as usize];
+        unsafe {
+            self.device
+                .raw
+                .get_query_pool_results(
+                    self.query_pool,
+                    first_query,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )
+                .context("Failed to read timestamp queries")?
+        };
+
+        self.results.clear();
+        for zone in frame.zones.drain(..) {
+            let Some(end_query) = zone.end_query else {
+                continue;
+            };
+            let begin = timestamps[(zone.begin_query - first_query) as usize] & self.timestamp_mask;
+            let end = timestamps[(end_query - first_query) as usize] & self.timestamp_mask;
+
+            zone.span.upload(begin as i64, end as i64);
+            let ticks = end.wrapping_sub(begin) & self.timestamp_mask;
+            self.results.push(GpuZone {
+                name: zone.name,
+                duration_ms: ticks as f64 * self.timestamp_period / 1_000_000.0,
+            });
+        }
+        frame.used_queries = 0;
+
+        Ok(&self.results)
+    }
+
+    // resets this frame's queries, has to be recorded before any zone
+    pub fn begin_frame(&mut self, command_buffer: vk::CommandBuffer) {
+        let first_query = self.first_query();
+        let frame = &mut self.frames[self.device.frame_index()];
+        frame.zones.clear();
+        frame.open_zones.clear();
+        frame.used_queries = 0;
+
+        unsafe {
+            self.device.raw.cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                first_query,
+                self.queries_per_frame,
+            )
+        };
+    }
+
+    pub fn begin_zone(&mut self, command_buffer: vk::CommandBuffer, name: &str) {
+        let first_query = self.first_query();
+        let frame = &mut self.frames[self.device.frame_index()];
+        assert!(
+            frame.used_queries + 2 <= self.queries_per_frame,
+            "Out of GPU profiler zones"
+        );
+
+        let begin_query = first_query + frame.used_queries;
+        frame.used_queries += 2;
+        self.device.cmd_write_timestamp2(
+            command_buffer,
+            vk::PipelineStageFlags2::TOP_OF_PIPE,
+            self.query_pool,
+            begin_query,
+        );
+
+        frame.open_zones.push(frame.zones.len());
+        frame.zones.push(PendingZone {
+            name: name.to_string(),
+            begin_query,
+            end_query: None,
+            span: self.tracy_context.span(name),
+        });
+    }
+
+    pub fn end_zone(&mut self, command_buffer: vk::CommandBuffer) {
+        let frame = &mut self.frames[self.device.frame_index()];
+        let zone_index = frame
+            .open_zones
+            .pop()
+            .expect("end_zone called without a matching begin_zone");
+        let zone = &mut frame.zones[zone_index];
+
+        // the end query is reserved together with the begin query
+        let end_query = zone.begin_query + 1;
+        self.device.cmd_write_timestamp2(
+            command_buffer,
+            vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+            self.query_pool,
+            end_query,
+        );
+        zone.end_query = Some(end_query);
+        zone.span.end();
+    }
+
+    // zones from the most recent collect
+    pub fn results(&self) -> &[GpuZone] {
+        &self.results
+    }
+}
+
+impl Drop for GpuProfiler {
+    fn drop(&mut self) {
+        unsafe { self.device.raw.destroy_query_pool(self.query_pool, None) };
+    }
+}
+
+// tracy needs a reference timestamp to line the gpu timeline up with the cpu
+fn read_timestamp(device: &Device, query_pool: vk::QueryPool) -> Result<u64> {
+    device
+        .submit_immediate(|command_buffer| {
+            unsafe {
+                device
+                    .raw
+                    .cmd_reset_query_pool(command_buffer, query_pool, 0, 1)
+            };
+            device.cmd_write_timestamp2(
+                command_buffer,
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                query_pool,
+                0,
+            );
+            Ok(())
+        })
+        .context("Failed to read reference timestamp")?;
+
+    let mut timestamp = [0u64];
+    unsafe {
+        device.raw.get_query_pool_results(
+            query_pool,
+            0,
+            &mut timestamp,
+            vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+        )?
+    };
+    Ok(timestamp[0])
+}
@@ -0,0 +1,536 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme};
+use std::sync::Arc;
+use vk_sync::{AccessType, ImageLayout};
+
+use super::device::Device;
+use super::render_graph::is_write;
+use super::texture::mip_level_count;
+
+#[derive(Clone)]
+pub struct ImageDesc {
+    pub name: String,
+    pub extent: vk::Extent3D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    // None picks a view type from the extent and array_layers, CUBE also makes
+    // the image cube compatible
+    pub view_type: Option<vk::ImageViewType>,
+    pub samples: vk::SampleCountFlags,
+    // allow a dedicated allocation when the driver reports it as beneficial
+    pub dedicated: bool,
+}
+
+impl ImageDesc {
+    pub fn new_2d(
+        name: impl Into<String>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            extent: extent.into(),
+            format,
+            usage,
+            mip_levels: 1,
+            array_layers: 1,
+            view_type: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            dedicated: false,
+        }
+    }
+
+    // six square layers in +x, -x, +y, -y, +z, -z order with a cube view
+    pub fn new_cube(
+        name: impl Into<String>,
+        size: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Self {
+        let extent = vk::Extent2D {
+            width: size,
+            height: size,
+        };
+        Self::new_2d(name, extent, format, usage)
+            .array_layers(6)
+            .view_type(vk::ImageViewType::CUBE)
+    }
+
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = array_layers;
+        self
+    }
+
+    pub fn view_type(mut self, view_type: vk::ImageViewType) -> Self {
+        self.view_type = Some(view_type);
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn dedicated(mut self, dedicated: bool) -> Self {
+        self.dedicated = dedicated;
+        self
+    }
+
+    fn image_type(&self) -> vk::ImageType {
+        if self.extent.depth > 1 {
+            vk::ImageType::TYPE_3D
+        } else {
+            vk::ImageType::TYPE_2D
+        }
+    }
+
+    fn resolved_view_type(&self) -> vk::ImageViewType {
+        if let Some(view_type) = self.view_type {
+            view_type
+        } else if self.extent.depth > 1 {
+            vk::ImageViewType::TYPE_3D
+        } else if self.array_layers > 1 {
+            vk::ImageViewType::TYPE_2D_ARRAY
+        } else {
+            vk::ImageViewType::TYPE_2D
+        }
+    }
+
+    fn create_flags(&self) -> vk::ImageCreateFlags {
+        if self.resolved_view_type() == vk::ImageViewType::CUBE {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        }
+    }
+
+    fn validate_view_type(&self) -> Result<()> {
+        let layers_match = match self.resolved_view_type() {
+            vk::ImageViewType::TYPE_2D | vk::ImageViewType::TYPE_3D => self.array_layers == 1,
+            vk::ImageViewType::TYPE_2D_ARRAY => self.extent.depth == 1,
+            vk::ImageViewType::CUBE => {
+                if self.extent.width != self.extent.height {
+                    anyhow::bail!("Cube image {} has to be square", self.name);
+                }
+                self.array_layers == 6 && self.extent.depth == 1
+            }
+            view_type => anyhow::bail!("{view_type:?} views are not supported"),
+        };
+        if !layers_match {
+            anyhow::bail!(
+                "{} has {} layers, which doesn't match its {:?} view",
+                self.name,
+                self.array_layers,
+                self.resolved_view_type()
+            );
+        }
+        Ok(())
+    }
+}
+
+pub fn format_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+pub struct Image {
+    pub raw: vk::Image,
+    pub view: vk::ImageView,
+    pub desc: ImageDesc,
+    // how the whole image was last used, every mip and layer is assumed to share it
+    current_access: AccessType,
+    allocation: Allocation,
+    device: Arc<Device>,
+}
+
+impl Device {
+    pub fn create_image(self: &Arc<Self>, desc: ImageDesc) -> Result<Image> {
+        desc.validate_view_type()?;
+
+        let create_info = vk::ImageCreateInfo::default()
+            .flags(desc.create_flags())
+            .image_type(desc.image_type())
+            .format(desc.format)
+            .extent(desc.extent)
+            .mip_levels(desc.mip_levels)
+            .array_layers(desc.array_layers)
+            .samples(desc.samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(desc.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let raw = unsafe { self.raw.create_image(&create_info, None) }
+            .with_context(|| format!("Failed to create image {}", desc.name))?;
+
+        let requirements = self.image_memory_requirements(raw);
+        let allocation_scheme = if requirements.requires_dedicated
+            || (desc.dedicated && requirements.prefers_dedicated)
+        {
+            AllocationScheme::DedicatedImage(raw)
+        } else {
+            AllocationScheme::GpuAllocatorManaged
+        };
+
+        let allocation = self.allocate(&AllocationCreateDesc {
+            name: &desc.name,
+            requirements: requirements.requirements,
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme,
+        })?;
+
+        unsafe {
+            self.raw
+                .bind_image_memory(raw, allocation.memory(), allocation.offset())
+                .context("Failed to bind image memory")?
+        };
+
+        let view_create_info = vk::ImageViewCreateInfo::default()
+            .image(raw)
+            .view_type(desc.resolved_view_type())
+            .format(desc.format)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(format_aspect_mask(desc.format))
+                    .level_count(desc.mip_levels)
+                    .layer_count(desc.array_layers),
+            );
+
+        let view = unsafe { self.raw.create_image_view(&view_create_info, None) }
+            .with_context(|| format!("Failed to create image view for {}", desc.name))?;
+        self.set_debug_name(raw, &desc.name);
+        self.set_debug_name(view, &desc.name);
+
+        Ok(Image {
+            raw,
+            view,
+            desc,
+            current_access: AccessType::Nothing,
+            allocation,
+            device: self.clone(),
+        })
+    }
+}
+
+impl Image {
+    pub fn format(&self) -> vk::Format {
+        self.desc.format
+    }
+
+    pub fn extent(&self) -> vk::Extent3D {
+        self.desc.extent
+    }
+
+    pub fn current_access(&self) -> AccessType {
+        self.current_access
+    }
+
+    // for transitions recorded outside of transition(), e.g. by a render graph
+    pub fn set_current_access(&mut self, access: AccessType) {
+        self.current_access = access;
+    }
+
+    // for color attachments that fragment shaders of the same pass read as
+    // input attachments. the image stays in RENDERING_LOCAL_READ until
+    // end_local_read, render with local_read_attachment and separate the
+    // writes from the reads with Device::cmd_local_read_barrier
+    pub fn begin_local_read(&mut self, command_buffer: vk::CommandBuffer) {
+        assert!(
+            self.device.dynamic_rendering_local_read,
+            "Local reads need VK_KHR_dynamic_rendering_local_read"
+        );
+        self.transition(command_buffer, AccessType::ColorAttachmentReadWrite);
+        self.local_read_barrier(
+            command_buffer,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::RENDERING_LOCAL_READ_KHR,
+        );
+    }
+
+    // back to the layout transition() expects, the image was last used as a
+    // color attachment
+    pub fn end_local_read(&mut self, command_buffer: vk::CommandBuffer) {
+        self.local_read_barrier(
+            command_buffer,
+            vk::ImageLayout::RENDERING_LOCAL_READ_KHR,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        self.current_access = AccessType::ColorAttachmentReadWrite;
+    }
+
+    fn local_read_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) {
+        let access_mask = vk::AccessFlags2::COLOR_ATTACHMENT_READ
+            | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags2::INPUT_ATTACHMENT_READ;
+        let stage_mask = vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags2::FRAGMENT_SHADER;
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(stage_mask)
+            .src_access_mask(access_mask)
+            .dst_stage_mask(stage_mask)
+            .dst_access_mask(access_mask)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.raw)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(vk::REMAINING_MIP_LEVELS)
+                    .layer_count(vk::REMAINING_ARRAY_LAYERS),
+            );
+        let dependency_info =
+            vk::DependencyInfo::default().image_memory_barriers(std::slice::from_ref(&barrier));
+        self.device
+            .cmd_pipeline_barrier2(command_buffer, &dependency_info);
+    }
+
+    // the previous layout and access come from the tracked state, transitioning
+    // from Nothing discards the contents
+    pub fn transition(&mut self, command_buffer: vk::CommandBuffer, next_access: AccessType) {
+        if next_access == self.current_access && !is_write(next_access) {
+            return;
+        }
+
+        let barrier = vk_sync::ImageBarrier {
+            previous_accesses: std::slice::from_ref(&self.current_access),
+            next_accesses: std::slice::from_ref(&next_access),
+            previous_layout: ImageLayout::Optimal,
+            next_layout: ImageLayout::Optimal,
+            discard_contents: self.current_access == AccessType::Nothing,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: self.raw,
+            range: vk::ImageSubresourceRange {
+                aspect_mask: format_aspect_mask(self.desc.format),
+                base_mip_level: 0,
+                level_count: vk::REMAINING_MIP_LEVELS,
+                base_array_layer: 0,
+                layer_count: vk::REMAINING_ARRAY_LAYERS,
+            },
+        };
+        vk_sync::cmd::pipeline_barrier(&self.device.raw, command_buffer, None, &[], &[barrier]);
+
+        self.current_access = next_access;
+    }
+}
+
+fn mip_barrier<'a>(
+    image: vk::Image,
+    mip_level: u32,
+    layer_count: u32,
+    previous_accesses: &'a [AccessType],
+    next_accesses: &'a [AccessType],
+) -> vk_sync::ImageBarrier<'a> {
+    vk_sync::ImageBarrier {
+        previous_accesses,
+        next_accesses,
+        previous_layout: ImageLayout::Optimal,
+        next_layout: ImageLayout::Optimal,
+        discard_contents: matches!(previous_accesses, [AccessType::Nothing]),
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: mip_level,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count,
+        },
+    }
+}
+
+// mip 0 was last accessed with source_access, e.g. TransferWrite right after
+// a copy, and the contents of the other mips are discarded. leaves every mip
+// ready to be sampled. every array layer gets its own mip chain
+#[allow(clippy::too_many_arguments)]
+pub fn generate_mipmaps(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    mip_levels: u32,
+    layer_count: u32,
+    format: vk::Format,
+    source_access: AccessType,
+) -> Result<()> {
+    let max_mip_levels = mip_level_count(extent);
+    if mip_levels == 0 || mip_levels > max_mip_levels {
+        anyhow::bail!(
+            "Can't generate {mip_levels} mips for a {}x{} image, it has 1 to {max_mip_levels}",
+            extent.width,
+            extent.height
+        );
+    }
+
+    let format_properties = unsafe {
+        device
+            .instance
+            .raw
+            .get_physical_device_format_properties(device.physical_device.raw, format)
+    };
+    let required_features = vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+        | vk::FormatFeatureFlags::BLIT_SRC
+        | vk::FormatFeatureFlags::BLIT_DST;
+    if !format_properties
+        .optimal_tiling_features
+        .contains(required_features)
+    {
+        anyhow::bail!("Format {format:?} does not support linear blits for mip generation");
+    }
+
+    let shader_read = [AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer];
+    let transfer_read = [AccessType::TransferRead];
+    let transfer_write = [AccessType::TransferWrite];
+    let nothing = [AccessType::Nothing];
+    let source_access = [source_access];
+
+    let mut mip_width = extent.width as i32;
+    let mut mip_height = extent.height as i32;
+
+    for mip_level in 1..mip_levels {
+        // every mip after the first was written by the previous blit
+        let previous_access = if mip_level == 1 {
+            &source_access
+        } else {
+            &transfer_write
+        };
+        vk_sync::cmd::pipeline_barrier(
+            &device.raw,
+            command_buffer,
+            None,
+            &[],
+            &[
+                mip_barrier(
+                    image,
+                    mip_level - 1,
+                    layer_count,
+                    previous_access,
+                    &transfer_read,
+                ),
+                mip_barrier(image, mip_level, layer_count, &nothing, &transfer_write),
+            ],
+        );
+
+        let next_width = (mip_width / 2).max(1);
+        let next_height = (mip_height / 2).max(1);
+
+        let blit = vk::ImageBlit::default()
+            .src_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(mip_level - 1)
+                    .layer_count(layer_count),
+            )
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: mip_width,
+                    y: mip_height,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(mip_level)
+                    .layer_count(layer_count),
+            )
+            .dst_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: next_width,
+                    y: next_height,
+                    z: 1,
+                },
+            ]);
+
+        unsafe {
+            device.raw.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                std::slice::from_ref(&blit),
+                vk::Filter::LINEAR,
+            )
+        };
+
+        vk_sync::cmd::pipeline_barrier(
+            &device.raw,
+            command_buffer,
+            None,
+            &[],
+            &[mip_barrier(
+                image,
+                mip_level - 1,
+                layer_count,
+                &transfer_read,
+                &shader_read,
+            )],
+        );
+
+        mip_width = next_width;
+        mip_height = next_height;
+    }
+
+    let last_access = if mip_levels == 1 {
+        &source_access
+    } else {
+        &transfer_write
+    };
+    vk_sync::cmd::pipeline_barrier(
+        &device.raw,
+        command_buffer,
+        None,
+        &[],
+        &[mip_barrier(
+            image,
+            mip_levels - 1,
+            layer_count,
+            last_access,
+            &shader_read,
+        )],
+    );
+
+    Ok(())
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.raw.destroy_image_view(self.view, None);
+            self.device.raw.destroy_image(self.raw, None);
+        }
+        let _ = self.device.free(std::mem::take(&mut self.allocation));
+    }
+}
@@ -1,13 +1,83 @@
 use log::{debug, error, info, trace, warn};
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 
 use anyhow::{Context, Result};
 use ash::vk;
 
-#[derive(Default)]
+// below 1.3 the device enables dynamic rendering and synchronization2 through
+// their KHR extensions
+pub const MIN_API_VERSION: u32 = vk::API_VERSION_1_2;
+
+// extra validation layer features, only used together with the validation layers
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ValidationConfig {
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+    pub synchronization: bool,
+    // can't be combined with gpu assisted validation
+    pub debug_printf: bool,
+}
+
+impl ValidationConfig {
+    fn enabled_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = Vec::new();
+        if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.best_practices {
+            features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        if self.synchronization {
+            features.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if self.debug_printf {
+            if self.gpu_assisted {
+                warn!("Debug printf can't be used with gpu assisted validation, skipping it");
+            } else {
+                features.push(vk::ValidationFeatureEnableEXT::DEBUG_PRINTF);
+            }
+        }
+        features
+    }
+}
+
 pub struct InstanceBuilder {
+    pub application_name: CString,
+    pub application_version: u32,
+    pub engine_name: CString,
+    pub engine_version: u32,
+    pub api_version: u32,
     pub required_extensions: &'static [*const i8],
     pub validation_layers: bool,
+    pub validation: ValidationConfig,
+    pub swapchain_colorspace: bool,
+    pub message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub ignored_message_ids: Vec<i32>,
+}
+
+impl Default for InstanceBuilder {
+    fn default() -> Self {
+        Self {
+            application_name: c"Bonfire".to_owned(),
+            application_version: 0,
+            engine_name: c"No engine".to_owned(),
+            engine_version: 0,
+            api_version: MIN_API_VERSION,
+            required_extensions: &[],
+            validation_layers: false,
+            validation: ValidationConfig::default(),
+            swapchain_colorspace: false,
+            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            ignored_message_ids: Vec::new(),
+        }
+    }
 }
 
 impl InstanceBuilder {
@@ -15,6 +85,24 @@ impl InstanceBuilder {
         Instance::new(self)
     }
 
+    pub fn application(mut self, name: &str, version: u32) -> Self {
+        self.application_name = CString::new(name).expect("Application name contains a nul byte");
+        self.application_version = version;
+        self
+    }
+
+    pub fn engine(mut self, name: &str, version: u32) -> Self {
+        self.engine_name = CString::new(name).expect("Engine name contains a nul byte");
+        self.engine_version = version;
+        self
+    }
+
+    // built with vk::make_api_version, has to be supported by the loader
+    pub fn api_version(mut self, api_version: u32) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
     pub fn required_extensions(mut self, extensions: &'static [*const i8]) -> Self {
         self.required_extensions = extensions;
         self
@@ -24,25 +112,61 @@ impl InstanceBuilder {
         self.validation_layers = should_enable;
         self
     }
+
+    pub fn validation(mut self, validation: ValidationConfig) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub fn message_severity(mut self, severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        self.message_severity = severity;
+        self
+    }
+
+    pub fn message_type(mut self, message_type: vk::DebugUtilsMessageTypeFlagsEXT) -> Self {
+        self.message_type = message_type;
+        self
+    }
+
+    // messageIdNumber as printed by the validation layers, e.g. 0x675dc32e
+    pub fn ignore_message_id(mut self, message_id: i32) -> Self {
+        self.ignored_message_ids.push(message_id);
+        self
+    }
+
+    // needed for hdr color spaces, skipped if the loader doesn't support it
+    pub fn enable_swapchain_colorspace(mut self, should_enable: bool) -> Self {
+        self.swapchain_colorspace = should_enable;
+        self
+    }
 }
 
 pub struct Instance {
     pub entry: ash::Entry,
     pub raw: ash::Instance,
+    pub api_version: u32,
     pub debug_utils_loader: Option<ash::ext::debug_utils::Instance>,
     pub debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
+    // read by the debug callback, has to outlive the messenger
+    _debug_filter: Box<DebugFilter>,
+}
+
+struct DebugFilter {
+    ignored_message_ids: Vec<i32>,
 }
 
 impl Instance {
     fn new(builder: &InstanceBuilder) -> Result<Self> {
         let entry = unsafe { ash::Entry::load().context("Failed to load Entry")? };
 
+        Self::validate_api_version(&entry, builder.api_version)?;
+
         let app_info = vk::ApplicationInfo::default()
-            .application_name(c"Bonfire")
-            .application_version(0)
-            .engine_name(c"No engine")
-            .engine_version(0)
-            .api_version(vk::make_api_version(0, 1, 3, 0));
+            .application_name(&builder.application_name)
+            .application_version(builder.application_version)
+            .engine_name(&builder.engine_name)
+            .engine_version(builder.engine_version)
+            .api_version(builder.api_version);
 
         let layers = Self::layers(builder);
 
@@ -52,14 +176,24 @@ impl Instance {
             vk::InstanceCreateFlags::default()
         };
 
-        let mut enabled_extensions = Self::extensions(builder);
+        let mut enabled_extensions = Self::extensions(&entry, builder)?;
         enabled_extensions.extend_from_slice(builder.required_extensions);
 
-        let create_info = vk::InstanceCreateInfo::default()
+        let validation_features = Self::validation_features(&entry, builder)?;
+        if !validation_features.is_empty() {
+            enabled_extensions.push(ash::ext::validation_features::NAME.as_ptr());
+        }
+        let mut validation_features_info =
+            vk::ValidationFeaturesEXT::default().enabled_validation_features(&validation_features);
+
+        let mut create_info = vk::InstanceCreateInfo::default()
             .application_info(&app_info)
             .enabled_extension_names(&enabled_extensions)
             .enabled_layer_names(&layers)
             .flags(create_flags);
+        if !validation_features.is_empty() {
+            create_info = create_info.push_next(&mut validation_features_info);
+        }
 
         let raw = unsafe {
             entry
@@ -67,19 +201,16 @@ impl Instance {
                 .context("Failed to create instance")?
         };
 
+        let debug_filter = Box::new(DebugFilter {
+            ignored_message_ids: builder.ignored_message_ids.clone(),
+        });
+
         let (debug_utils_loader, debug_messenger) = if builder.validation_layers {
             let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
-                .message_severity(
-                    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                        | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-                )
-                .message_type(
-                    vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                        | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                        | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-                )
-                .pfn_user_callback(Some(vulkan_debug_callback));
+                .message_severity(builder.message_severity)
+                .message_type(builder.message_type)
+                .pfn_user_callback(Some(vulkan_debug_callback))
+                .user_data(&*debug_filter as *const DebugFilter as *mut std::os::raw::c_void);
             let debug_utils_loader = ash::ext::debug_utils::Instance::new(&entry, &raw);
             let debug_messenger = unsafe {
                 debug_utils_loader
@@ -95,19 +226,119 @@ impl Instance {
         Ok(Instance {
             entry,
             raw,
+            api_version: builder.api_version,
             debug_utils_loader,
             debug_messenger,
+            _debug_filter: debug_filter,
         })
     }
 
-    fn extensions(builder: &InstanceBuilder) -> Vec<*const i8> {
+    fn validate_api_version(entry: &ash::Entry, api_version: u32) -> Result<()> {
+        let format_version = |version: u32| {
+            format!(
+                "{}.{}.{}",
+                vk::api_version_major(version),
+                vk::api_version_minor(version),
+                vk::api_version_patch(version)
+            )
+        };
+
+        if api_version < MIN_API_VERSION {
+            anyhow::bail!(
+                "Vulkan {} was requested but at least {} is needed",
+                format_version(api_version),
+                format_version(MIN_API_VERSION)
+            );
+        }
+
+        // None for 1.0 loaders, which don't have vkEnumerateInstanceVersion
+        let loader_version = unsafe {
+            entry
+                .try_enumerate_instance_version()
+                .context("Failed to query the instance version")?
+        }
+        .unwrap_or(vk::API_VERSION_1_0);
+        // the patch version doesn't change what the loader can create
+        let major_minor = |version: u32| {
+            (
+                vk::api_version_major(version),
+                vk::api_version_minor(version),
+            )
+        };
+        if major_minor(api_version) > major_minor(loader_version) {
+            anyhow::bail!(
+                "Vulkan {} was requested but the loader only supports {}",
+                format_version(api_version),
+                format_version(loader_version)
+            );
+        }
+
+        Ok(())
+    }
+
+    fn extensions(entry: &ash::Entry, builder: &InstanceBuilder) -> Result<Vec<*const i8>> {
         let mut extensions = vec![ash::khr::get_physical_device_properties2::NAME.as_ptr()];
 
         if builder.validation_layers {
             extensions.push(ash::ext::debug_utils::NAME.as_ptr());
         }
 
-        extensions
+        // required by the ENUMERATE_PORTABILITY_KHR create flag, without it
+        // MoltenVK devices are not enumerated
+        if cfg!(any(target_os = "macos", target_os = "ios")) {
+            extensions.push(ash::khr::portability_enumeration::NAME.as_ptr());
+        }
+
+        if builder.swapchain_colorspace {
+            let supported_extensions = unsafe {
+                entry
+                    .enumerate_instance_extension_properties(None)
+                    .context("Failed to enumerate instance extensions")?
+            };
+            let supported = supported_extensions.iter().any(|extension| {
+                extension.extension_name_as_c_str() == Ok(ash::ext::swapchain_colorspace::NAME)
+            });
+            if supported {
+                extensions.push(ash::ext::swapchain_colorspace::NAME.as_ptr());
+            } else {
+                warn!("VK_EXT_swapchain_colorspace is not supported");
+            }
+        }
+
+        Ok(extensions)
+    }
+
+    // the validation features extension is provided by the validation layer itself
+    fn validation_features(
+        entry: &ash::Entry,
+        builder: &InstanceBuilder,
+    ) -> Result<Vec<vk::ValidationFeatureEnableEXT>> {
+        if !builder.validation_layers {
+            return Ok(Vec::new());
+        }
+
+        let features = builder.validation.enabled_features();
+        if features.is_empty() {
+            return Ok(features);
+        }
+
+        let layer_extensions = unsafe {
+            entry
+                .enumerate_instance_extension_properties(Some(c"VK_LAYER_KHRONOS_validation"))
+                .context("Failed to enumerate validation layer extensions")?
+        };
+        let supported = layer_extensions.iter().any(|extension| {
+            extension.extension_name_as_c_str() == Ok(ash::ext::validation_features::NAME)
+        });
+        if !supported {
+            warn!(
+                "VK_EXT_validation_features is not supported, ignoring {:?}",
+                builder.validation
+            );
+            return Ok(Vec::new());
+        }
+
+        Ok(features)
     }
 
     fn layers(builder: &InstanceBuilder) -> Vec<*const i8> {
@@ -125,10 +356,17 @@ extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     _message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     unsafe {
         let callback_data = *p_callback_data;
+        let filter = &*(user_data as *const DebugFilter);
+        if filter
+            .ignored_message_ids
+            .contains(&callback_data.message_id_number)
+        {
+            return vk::FALSE;
+        }
         let log_message = CStr::from_ptr(callback_data.p_message).to_str().unwrap();
 
         match message_severity {
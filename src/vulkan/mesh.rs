@@ -0,0 +1,249 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+use super::buffer::{Buffer, BufferDesc};
+use super::device::{Device, DeviceFeature};
+use super::upload::Uploader;
+
+pub trait Index: bytemuck::Pod {
+    const INDEX_TYPE: vk::IndexType;
+}
+
+impl Index for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
+
+impl Index for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
+
+pub const VERTEX_BINDING: u32 = 0;
+// per instance attributes, see VertexBindingDesc::per_instance
+pub const INSTANCE_BINDING: u32 = 1;
+
+// the index type follows the index data
+pub struct Mesh {
+    device: Arc<Device>,
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+}
+
+impl Mesh {
+    // the data is only valid on the graphics queue once the uploader is flushed
+    pub fn new<V: bytemuck::Pod, I: Index>(
+        device: &Arc<Device>,
+        uploader: &mut Uploader,
+        name: &str,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<Self> {
+        if vertices.is_empty() || indices.is_empty() {
+            anyhow::bail!("Mesh {name} has no vertices or indices");
+        }
+
+        let vertex_buffer = device.create_buffer(BufferDesc::new(
+            format!("{name} vertices"),
+            std::mem::size_of_val(vertices) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        ))?;
+        let index_buffer = device.create_buffer(BufferDesc::new(
+            format!("{name} indices"),
+            std::mem::size_of_val(indices) as u64,
+            vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        ))?;
+
+        uploader.upload_buffer(vertices, &vertex_buffer, 0)?;
+        uploader.upload_buffer(indices, &index_buffer, 0)?;
+
+        Ok(Self {
+            device: device.clone(),
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+            index_type: I::INDEX_TYPE,
+        })
+    }
+
+    pub fn bind(&self, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            self.device.raw.cmd_bind_vertex_buffers(
+                command_buffer,
+                VERTEX_BINDING,
+                std::slice::from_ref(&self.vertex_buffer.raw),
+                &[0],
+            );
+            self.device.raw.cmd_bind_index_buffer(
+                command_buffer,
+                self.index_buffer.raw,
+                0,
+                self.index_type,
+            );
+        }
+    }
+
+    pub fn bind_instances(&self, command_buffer: vk::CommandBuffer, instance_buffer: &Buffer) {
+        unsafe {
+            self.device.raw.cmd_bind_vertex_buffers(
+                command_buffer,
+                INSTANCE_BINDING,
+                std::slice::from_ref(&instance_buffer.raw),
+                &[0],
+            )
+        };
+    }
+
+    // expects the mesh and instance buffers to be bound already
+    pub fn draw_indexed_instanced(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        index_count: u32,
+        instance_count: u32,
+    ) {
+        debug_assert!(
+            index_count <= self.index_count,
+            "Drawing {index_count} indices but the mesh has {}",
+            self.index_count
+        );
+        unsafe {
+            self.device
+                .raw
+                .cmd_draw_indexed(command_buffer, index_count, instance_count, 0, 0, 0)
+        };
+    }
+
+    pub fn draw_indexed(&self, command_buffer: vk::CommandBuffer) {
+        self.bind(command_buffer);
+        unsafe {
+            self.device
+                .raw
+                .cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0)
+        };
+    }
+}
+
+impl Device {
+    // buffer holds vk::DrawIndexedIndirectCommand entries, usually written by a compute pass
+    pub fn draw_indexed_indirect(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffer: &Buffer,
+        offset: u64,
+        draw_count: u32,
+        stride: u32,
+    ) -> Result<()> {
+        self.validate_indirect(buffer, offset, draw_count, stride)?;
+        unsafe {
+            self.raw.cmd_draw_indexed_indirect(
+                command_buffer,
+                buffer.raw,
+                offset,
+                draw_count,
+                stride,
+            )
+        };
+        Ok(())
+    }
+
+    // the draw count is read from count_buffer at count_offset, clamped to max_draw_count
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_indexed_indirect_count(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffer: &Buffer,
+        offset: u64,
+        count_buffer: &Buffer,
+        count_offset: u64,
+        max_draw_count: u32,
+        stride: u32,
+    ) -> Result<()> {
+        let draw_indirect_count = self
+            .draw_indirect_count
+            .as_ref()
+            .context("drawIndirectCount is not supported")?;
+        self.validate_indirect(buffer, offset, max_draw_count, stride)?;
+        if !count_buffer
+            .desc
+            .usage
+            .contains(vk::BufferUsageFlags::INDIRECT_BUFFER)
+        {
+            anyhow::bail!(
+                "Count buffer {} is missing INDIRECT_BUFFER usage",
+                count_buffer.desc.name
+            );
+        }
+        if count_offset % 4 != 0
+            || count_offset
+                .checked_add(4)
+                .is_none_or(|end| end > count_buffer.size())
+        {
+            anyhow::bail!(
+                "Invalid draw count offset {count_offset} for buffer {}",
+                count_buffer.desc.name
+            );
+        }
+
+        unsafe {
+            draw_indirect_count.cmd_draw_indexed_indirect_count(
+                command_buffer,
+                buffer.raw,
+                offset,
+                count_buffer.raw,
+                count_offset,
+                max_draw_count,
+                stride,
+            )
+        };
+        Ok(())
+    }
+
+    fn validate_indirect(
+        &self,
+        buffer: &Buffer,
+        offset: u64,
+        draw_count: u32,
+        stride: u32,
+    ) -> Result<()> {
+        let name = &buffer.desc.name;
+        if !buffer
+            .desc
+            .usage
+            .contains(vk::BufferUsageFlags::INDIRECT_BUFFER)
+        {
+            anyhow::bail!("Buffer {name} is missing INDIRECT_BUFFER usage");
+        }
+        if offset % 4 != 0 {
+            anyhow::bail!("Indirect offset {offset} into {name} is not a multiple of 4");
+        }
+        if draw_count > 1 && !self.is_feature_enabled(DeviceFeature::MultiDrawIndirect) {
+            anyhow::bail!("Drawing {draw_count} indirect draws requires multiDrawIndirect");
+        }
+        if draw_count
+            > self
+                .physical_device
+                .properties
+                .limits
+                .max_draw_indirect_count
+        {
+            anyhow::bail!("Indirect draw count {draw_count} exceeds maxDrawIndirectCount");
+        }
+
+        let command_size = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u64;
+        if draw_count > 1 && (stride % 4 != 0 || (stride as u64) < command_size) {
+            anyhow::bail!("Indirect stride {stride} is too small or not a multiple of 4");
+        }
+        if draw_count > 0 {
+            let end = offset.checked_add((draw_count as u64 - 1) * stride as u64 + command_size);
+            if end.is_none_or(|end| end > buffer.size()) {
+                anyhow::bail!("{draw_count} indirect draws overflow buffer {name}");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,25 +1,65 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use ash::vk;
+use gpu_allocator::MemoryLocation;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use std::path::PathBuf;
 use std::sync::Arc;
+use vk_sync::AccessType;
 
+pub mod bindless;
+pub mod buffer;
+pub mod command_buffer;
 pub mod command_ring_buffer;
+pub mod deletion_queue;
+pub mod descriptor;
 pub mod device;
+#[cfg(feature = "egui")]
+pub mod egui_renderer;
+#[cfg(feature = "gltf")]
+pub mod gltf;
+pub mod gpu_profiler;
+pub mod image;
 pub mod instance;
+pub mod mesh;
 pub mod physical_device;
 pub mod pipeline;
+pub mod pipeline_cache;
+pub mod pipeline_compiler;
+pub mod profiling;
+pub mod render_graph;
+pub mod rendering;
+pub mod resources;
+pub mod sampler;
+pub mod screenshot;
 pub mod shader_compiler;
+pub mod shader_watcher;
 pub mod surface;
 pub mod swapchain;
+pub mod texture;
+pub mod timeline_semaphore;
+pub mod uniform_ring;
+pub mod upload;
 
 pub struct RenderBackendConfig {
     pub validation_layers: bool,
-    pub vsync: bool,
+    pub validation: instance::ValidationConfig,
+    pub present_mode: swapchain::PresentModePreference,
+    // falls back to sdr when the surface has no hdr format
+    pub hdr: bool,
+    pub pipeline_cache_path: Option<PathBuf>,
+    pub frames_in_flight: usize,
+    pub sync_mode: device::SyncMode,
+    // core features on top of the ones the device builder always enables,
+    // pipelines and textures that need them check Device::is_feature_enabled
+    pub required_features: Vec<device::DeviceFeature>,
+    pub requested_features: Vec<device::DeviceFeature>,
 }
 
+// headless backends have an offscreen image instead of a surface and swapchain
 pub struct RenderBackend {
-    pub swapchain: swapchain::Swapchain,
-    pub surface: Arc<surface::Surface>,
+    pub swapchain: Option<swapchain::Swapchain>,
+    pub surface: Option<Arc<surface::Surface>>,
+    pub offscreen_image: Option<image::Image>,
     pub device: Arc<device::Device>,
 }
 
@@ -28,6 +68,8 @@ impl RenderBackend {
         window: &(impl HasDisplayHandle + HasWindowHandle),
         config: &RenderBackendConfig,
     ) -> Result<Self> {
+        profiling::start();
+
         let required_window_extensions =
             ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
                 .unwrap();
@@ -35,47 +77,267 @@ impl RenderBackend {
             instance::InstanceBuilder::default()
                 .required_extensions(required_window_extensions)
                 .enable_validation_layers(config.validation_layers)
+                .validation(config.validation)
+                .enable_swapchain_colorspace(config.hdr)
                 .build()?,
         );
 
+        let surface = Arc::new(surface::Surface::new(&instance, window)?);
+
         let physical_device_selector =
-            physical_device::PhysicalDeviceSelector::with_instance(&instance);
+            physical_device::PhysicalDeviceSelector::with_instance(&instance)
+                .with_surface(&surface);
         let physical_device = Arc::new(physical_device_selector.select()?);
 
-        let device_builder = device::DeviceBuilder::new(instance, physical_device);
+        let device_builder = device::DeviceBuilder::new(instance, physical_device)
+            .pipeline_cache_path(config.pipeline_cache_path.clone())
+            .frames_in_flight(config.frames_in_flight)
+            .sync_mode(config.sync_mode)
+            .require_features(&config.required_features)
+            .request_features(&config.requested_features);
         let device = Arc::new(device_builder.build()?);
 
-        let surface = Arc::new(surface::Surface::new(&device, window)?);
-
-        let surface_format = vk::SurfaceFormatKHR {
-            format: vk::Format::B8G8R8A8_SRGB,
-            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-        };
         let supported_surface_formats =
             swapchain::Swapchain::enumerate_surface_formats(&device, &surface)?;
-        if !supported_surface_formats.contains(&surface_format) {
-            anyhow::bail!("Surface format not available");
-        }
+        let surface_format =
+            swapchain::Swapchain::choose_surface_format(&supported_surface_formats, config.hdr)?;
 
         let swapchain_desc = swapchain::SwapchainDesc {
             old_swapchain: None,
             format: surface_format,
-            vsync: config.vsync,
+            present_mode: config.present_mode,
         };
         let swapchain = swapchain::Swapchain::new(&device, &surface, swapchain_desc)?;
 
         Ok(Self {
             device,
-            surface,
-            swapchain,
+            surface: Some(surface),
+            swapchain: Some(swapchain),
+            offscreen_image: None,
+        })
+    }
+
+    pub fn new_headless(config: &RenderBackendConfig, extent: vk::Extent2D) -> Result<Self> {
+        profiling::start();
+
+        let instance = Arc::new(
+            instance::InstanceBuilder::default()
+                .enable_validation_layers(config.validation_layers)
+                .validation(config.validation)
+                .build()?,
+        );
+
+        let physical_device_selector =
+            physical_device::PhysicalDeviceSelector::with_instance(&instance);
+        let physical_device = Arc::new(physical_device_selector.select()?);
+
+        let device_builder = device::DeviceBuilder::new(instance, physical_device)
+            .pipeline_cache_path(config.pipeline_cache_path.clone())
+            .frames_in_flight(config.frames_in_flight)
+            .sync_mode(config.sync_mode)
+            .require_features(&config.required_features)
+            .request_features(&config.requested_features)
+            .swapchain(false);
+        let device = Arc::new(device_builder.build()?);
+
+        let offscreen_image = create_offscreen_image(&device, extent)?;
+
+        Ok(Self {
+            device,
+            surface: None,
+            swapchain: None,
+            offscreen_image: Some(offscreen_image),
         })
     }
+
+    // rebuilds the swapchain, or recreates the offscreen image when headless, and
+    // returns the extent that size dependent resources should be recreated with,
+    // None while the window is minimized
+    pub fn resize(&mut self, new_extent: vk::Extent2D) -> Result<Option<vk::Extent2D>> {
+        if let Some(swapchain) = &mut self.swapchain {
+            // the surface has the final say, new_extent may already be stale
+            if swapchain.rebuild()? == swapchain::SwapchainRebuild::Minimized {
+                return Ok(None);
+            }
+            return Ok(Some(swapchain.get_extent()));
+        }
+
+        if new_extent.width == 0 || new_extent.height == 0 {
+            return Ok(None);
+        }
+        unsafe { self.device.raw.device_wait_idle()? };
+        self.offscreen_image = Some(create_offscreen_image(&self.device, new_extent)?);
+        Ok(Some(new_extent))
+    }
+
+    // copies the offscreen image to the cpu as tightly packed bgra8 rows, the image
+    // has to be last written as a color attachment and is left in that state
+    pub fn read_pixels(&self) -> Result<Vec<u8>> {
+        let image = self
+            .offscreen_image
+            .as_ref()
+            .context("read_pixels requires a headless render backend")?;
+        let extent = image.extent();
+        let size = extent.width as u64 * extent.height as u64 * 4;
+
+        let readback_buffer = self.device.create_buffer(buffer::BufferDesc::new(
+            "readback",
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+        ))?;
+
+        self.device
+            .submit_immediate(|command_buffer| {
+                cmd_readback(
+                    &self.device,
+                    command_buffer,
+                    image.raw,
+                    image.desc.extent,
+                    AccessType::ColorAttachmentWrite,
+                    &readback_buffer,
+                );
+                Ok(())
+            })
+            .context("Failed to read back offscreen image")?;
+
+        let mapped = readback_buffer
+            .mapped_ptr()
+            .context("Readback buffer is not mapped")?;
+        let pixels =
+            unsafe { std::slice::from_raw_parts(mapped.as_ptr() as *const u8, size as usize) };
+        Ok(pixels.to_vec())
+    }
+
+    // copies a swapchain image whose frame has already been submitted, call it
+    // between the submit and present_image. the image has to be in the present
+    // layout and is left there, the gpu is idle afterwards
+    pub fn capture_frame(
+        &self,
+        swapchain_image: &swapchain::SwapchainImage,
+    ) -> Result<screenshot::Screenshot> {
+        let swapchain = self
+            .swapchain
+            .as_ref()
+            .context("capture_frame requires a swapchain, use read_pixels when headless")?;
+        if !swapchain
+            .image_usage()
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            anyhow::bail!("Swapchain images don't support TRANSFER_SRC usage");
+        }
+
+        let format = swapchain_image.format;
+        let texel_size = screenshot::texel_size(format)
+            .with_context(|| format!("Capturing {format:?} swapchains is not supported"))?;
+        let extent = swapchain_image.extent;
+        let size = extent.width as u64 * extent.height as u64 * texel_size;
+
+        let readback_buffer = self.device.create_buffer(buffer::BufferDesc::new(
+            "capture",
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+        ))?;
+
+        // queue order puts the copy after the frame's submission
+        self.device
+            .submit_immediate(|command_buffer| {
+                cmd_readback(
+                    &self.device,
+                    command_buffer,
+                    swapchain_image.image,
+                    extent.into(),
+                    AccessType::Present,
+                    &readback_buffer,
+                );
+                Ok(())
+            })
+            .context("Failed to capture swapchain image")?;
+
+        let mapped = readback_buffer
+            .mapped_ptr()
+            .context("Capture buffer is not mapped")?;
+        let pixels =
+            unsafe { std::slice::from_raw_parts(mapped.as_ptr() as *const u8, size as usize) };
+        screenshot::Screenshot::from_texels(pixels, extent, format)
+    }
 }
 
 impl Drop for RenderBackend {
     fn drop(&mut self) {
         let _ = unsafe { self.device.raw.device_wait_idle() };
+        // registered and deferred resources keep the device alive
+        self.device.resources().clear();
+        self.device.flush_deferred_destroys();
         // struct fields are dropped in order
         // swapchain, then surface, then device
     }
 }
+
+// same format as the swapchain so pipelines work in both modes
+fn create_offscreen_image(
+    device: &Arc<device::Device>,
+    extent: vk::Extent2D,
+) -> Result<image::Image> {
+    device.create_image(image::ImageDesc::new_2d(
+        "offscreen",
+        extent,
+        vk::Format::B8G8R8A8_SRGB,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+    ))
+}
+
+// the image is copied with tightly packed rows and goes back to previous_access
+fn cmd_readback(
+    device: &device::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent3D,
+    previous_access: AccessType,
+    readback_buffer: &buffer::Buffer,
+) {
+    let device = &device.raw;
+    let range = vk::ImageSubresourceRange::default()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .level_count(1)
+        .layer_count(1);
+    let previous_accesses = [previous_access];
+    let to_transfer = vk_sync::ImageBarrier {
+        previous_accesses: &previous_accesses,
+        next_accesses: &[AccessType::TransferRead],
+        previous_layout: vk_sync::ImageLayout::Optimal,
+        next_layout: vk_sync::ImageLayout::Optimal,
+        discard_contents: false,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        range,
+    };
+    let to_previous = vk_sync::ImageBarrier {
+        previous_accesses: &[AccessType::TransferRead],
+        next_accesses: &previous_accesses,
+        ..to_transfer
+    };
+
+    vk_sync::cmd::pipeline_barrier(device, command_buffer, None, &[], &[to_transfer]);
+
+    let region = vk::BufferImageCopy::default()
+        .image_subresource(
+            vk::ImageSubresourceLayers::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1),
+        )
+        .image_extent(extent);
+    unsafe {
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            readback_buffer.raw,
+            std::slice::from_ref(&region),
+        );
+    }
+
+    vk_sync::cmd::pipeline_barrier(device, command_buffer, None, &[], &[to_previous]);
+}
@@ -1,14 +1,44 @@
 use super::instance::Instance;
+use super::surface::Surface;
 use anyhow::{Context, Result};
 use ash::vk;
 
+enum DevicePreference {
+    Index(usize),
+    Name(String),
+}
+
 pub struct PhysicalDeviceSelector<'a> {
     instance: &'a Instance,
+    surface: Option<&'a Surface>,
+    preference: Option<DevicePreference>,
 }
 
 impl<'a> PhysicalDeviceSelector<'a> {
     pub fn with_instance(instance: &'a Instance) -> Self {
-        Self { instance }
+        Self {
+            instance,
+            surface: None,
+            preference: None,
+        }
+    }
+
+    // index into the order the driver enumerates devices in
+    pub fn prefer_index(mut self, index: usize) -> Self {
+        self.preference = Some(DevicePreference::Index(index));
+        self
+    }
+
+    // substring match on the device name
+    pub fn prefer_name(mut self, name: &str) -> Self {
+        self.preference = Some(DevicePreference::Name(name.to_string()));
+        self
+    }
+
+    // only devices with a graphics queue that can present to the surface are considered
+    pub fn with_surface(mut self, surface: &'a Surface) -> Self {
+        self.surface = Some(surface);
+        self
     }
 
     pub fn select(&self) -> Result<PhysicalDevice> {
@@ -19,23 +49,153 @@ impl<'a> PhysicalDeviceSelector<'a> {
                 .context("Failed to enumerate physical devices")?
         };
 
-        let raw = *physical_devices
+        let candidates = physical_devices
+            .iter()
+            .filter_map(|&device| match self.surface {
+                Some(surface) => self
+                    .find_present_queue_family(device, surface)
+                    .map(|index| (device, Some(index))),
+                None => Some((device, None)),
+            })
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() && self.surface.is_some() {
+            anyhow::bail!(
+                "None of the {} physical devices can present to the surface",
+                physical_devices.len()
+            );
+        }
+
+        let (raw, graphics_present_queue_family_index) = match &self.preference {
+            Some(preference) => {
+                let preferred = self.find_preferred(&physical_devices, preference)?;
+                *candidates
+                    .iter()
+                    .find(|(device, _)| *device == preferred)
+                    .context("Preferred physical device can't present to the surface")?
+            }
+            None => *candidates
+                .iter()
+                .max_by_key(|(device, _)| {
+                    let properties =
+                        unsafe { self.instance.raw.get_physical_device_properties(*device) };
+                    match properties.device_type {
+                        vk::PhysicalDeviceType::DISCRETE_GPU => 1000,
+                        vk::PhysicalDeviceType::INTEGRATED_GPU => 10,
+                        _ => 0,
+                    }
+                })
+                .ok_or(anyhow::anyhow!("failed to find physical device"))?,
+        };
+
+        let properties = unsafe { self.instance.raw.get_physical_device_properties(raw) };
+        let features = unsafe { self.instance.raw.get_physical_device_features(raw) };
+
+        Ok(PhysicalDevice {
+            raw,
+            properties,
+            features,
+            graphics_present_queue_family_index,
+        })
+    }
+
+    fn find_preferred(
+        &self,
+        physical_devices: &[vk::PhysicalDevice],
+        preference: &DevicePreference,
+    ) -> Result<vk::PhysicalDevice> {
+        let names = physical_devices
             .iter()
-            .max_by_key(|device| {
+            .map(|&device| {
                 let properties =
-                    unsafe { self.instance.raw.get_physical_device_properties(**device) };
-                match properties.device_type {
-                    vk::PhysicalDeviceType::DISCRETE_GPU => 1000,
-                    vk::PhysicalDeviceType::INTEGRATED_GPU => 10,
-                    _ => 0,
-                }
+                    unsafe { self.instance.raw.get_physical_device_properties(device) };
+                properties
+                    .device_name_as_c_str()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
             })
-            .ok_or(anyhow::anyhow!("failed to find physical device"))?;
+            .collect::<Vec<_>>();
 
-        Ok(PhysicalDevice { raw })
+        let index = match preference {
+            DevicePreference::Index(index) => Some(*index).filter(|&index| index < names.len()),
+            DevicePreference::Name(name) => names.iter().position(|n| n.contains(name.as_str())),
+        };
+
+        let Some(index) = index else {
+            let available = names
+                .iter()
+                .enumerate()
+                .map(|(index, name)| format!("{index}: {name}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::bail!("Preferred physical device not found, available devices: {available}");
+        };
+
+        Ok(physical_devices[index])
+    }
+
+    fn find_present_queue_family(
+        &self,
+        device: vk::PhysicalDevice,
+        surface: &Surface,
+    ) -> Option<u32> {
+        let queue_family_properties = unsafe {
+            self.instance
+                .raw
+                .get_physical_device_queue_family_properties(device)
+        };
+
+        (0..queue_family_properties.len() as u32).find(|&index| {
+            let supports_graphics = queue_family_properties[index as usize]
+                .queue_flags
+                .contains(vk::QueueFlags::GRAPHICS);
+            // treat a failed query the same as no support
+            let supports_present = unsafe {
+                surface
+                    .loader
+                    .get_physical_device_surface_support(device, index, surface.raw)
+                    .unwrap_or(false)
+            };
+            supports_graphics && supports_present
+        })
     }
 }
 
 pub struct PhysicalDevice {
     pub raw: vk::PhysicalDevice,
+    pub properties: vk::PhysicalDeviceProperties,
+    pub features: vk::PhysicalDeviceFeatures,
+    // set when selected with a surface, the device uses it as its graphics queue
+    pub graphics_present_queue_family_index: Option<u32>,
+}
+
+// in order of preference, D32_SFLOAT has the best precision for reversed z
+pub const DEPTH_FORMAT_CANDIDATES: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
+
+impl PhysicalDevice {
+    // first candidate usable as an optimally tiled depth attachment
+    pub fn find_depth_format(
+        &self,
+        instance: &Instance,
+        candidates: &[vk::Format],
+    ) -> Result<vk::Format> {
+        candidates
+            .iter()
+            .copied()
+            .find(|&format| {
+                let properties = unsafe {
+                    instance
+                        .raw
+                        .get_physical_device_format_properties(self.raw, format)
+                };
+                properties
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            })
+            .with_context(|| format!("None of the depth formats {candidates:?} are supported"))
+    }
 }
@@ -1,55 +1,557 @@
-use std::{collections::btree_map::Entry, ffi::CString, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, btree_map::Entry},
+    ffi::CString,
+    path::PathBuf,
+    sync::Arc,
+};
 
-use super::device;
-use super::shader_compiler;
+use super::descriptor::DescriptorPool;
+use super::device::{self, DeviceFeature};
+use super::image;
+use super::shader_compiler::{self, ShaderCompiler, ShaderCompilerOptions};
 use anyhow::{Context, Result};
 use ash::vk;
 use bytes::Bytes;
 use log::info;
 
 pub const MAX_DESCRIPTOR_SETS: usize = 4;
+pub const MAX_BINDLESS_DESCRIPTORS: u32 = 16384;
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ShaderStage {
     Vertex,
+    TessControl,
+    TessEval,
+    Geometry,
     Fragment,
+    Task,
+    Mesh,
+    Compute,
 }
 
+impl ShaderStage {
+    pub fn to_vk(self) -> vk::ShaderStageFlags {
+        match self {
+            ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
+            ShaderStage::TessControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            ShaderStage::TessEval => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            ShaderStage::Geometry => vk::ShaderStageFlags::GEOMETRY,
+            ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+            ShaderStage::Task => vk::ShaderStageFlags::TASK_EXT,
+            ShaderStage::Mesh => vk::ShaderStageFlags::MESH_EXT,
+            ShaderStage::Compute => vk::ShaderStageFlags::COMPUTE,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum SpecValue {
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    Bool(bool),
+}
+
+impl SpecValue {
+    fn to_bytes(self) -> [u8; 4] {
+        match self {
+            SpecValue::U32(value) => value.to_ne_bytes(),
+            SpecValue::I32(value) => value.to_ne_bytes(),
+            SpecValue::F32(value) => value.to_ne_bytes(),
+            // spec constant booleans are VkBool32
+            SpecValue::Bool(value) => (value as vk::Bool32).to_ne_bytes(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct ShaderDesc {
     name: String,
     spirv: Bytes,
     stage: ShaderStage,
     entry_point: CString,
+    specialization: Vec<(u32, SpecValue)>,
+    // shaders without a source can't be hot reloaded
+    source_path: Option<PathBuf>,
+    compiler_options: ShaderCompilerOptions,
+}
+
+struct SpecializationData {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationData {
+    fn new(constants: &[(u32, SpecValue)]) -> Self {
+        let mut entries = Vec::with_capacity(constants.len());
+        let mut data = Vec::with_capacity(constants.len() * 4);
+        for (constant_id, value) in constants {
+            let bytes = value.to_bytes();
+            entries.push(
+                vk::SpecializationMapEntry::default()
+                    .constant_id(*constant_id)
+                    .offset(data.len() as u32)
+                    .size(bytes.len()),
+            );
+            data.extend_from_slice(&bytes);
+        }
+
+        Self { entries, data }
+    }
+
+    fn info(&self) -> vk::SpecializationInfo<'_> {
+        vk::SpecializationInfo::default()
+            .map_entries(&self.entries)
+            .data(&self.data)
+    }
 }
 
 impl ShaderDesc {
     pub fn new(compiled_shader: shader_compiler::CompiledShader, stage: ShaderStage) -> Self {
         Self {
+            entry_point: CString::new(compiled_shader.entry_point)
+                .expect("Entry point name contains a nul byte"),
             name: compiled_shader.name,
             spirv: compiled_shader.spirv,
             stage,
-            // TODO: specify entry point function name
-            entry_point: c"main".into(),
+            specialization: Vec::new(),
+            source_path: compiled_shader.source_path,
+            compiler_options: compiled_shader.options,
+        }
+    }
+
+    fn recompile(&self, compiler: &ShaderCompiler) -> Result<Self> {
+        let Some(source_path) = &self.source_path else {
+            return Ok(self.clone());
+        };
+
+        let entry_point = self.entry_point.to_str()?;
+        let compiled_shader =
+            compiler.compile_slang(source_path, entry_point, &self.compiler_options)?;
+        Ok(Self {
+            spirv: compiled_shader.spirv,
+            ..self.clone()
+        })
+    }
+
+    pub fn specialization(mut self, constant_id: u32, value: SpecValue) -> Self {
+        self.specialization.push((constant_id, value));
+        self
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct VertexBindingDesc {
+    pub binding: u32,
+    pub stride: u32,
+    pub input_rate: vk::VertexInputRate,
+}
+
+impl VertexBindingDesc {
+    pub fn per_vertex(binding: u32, stride: u32) -> Self {
+        Self {
+            binding,
+            stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    // attributes of this binding advance once per instance, not per vertex
+    pub fn per_instance(binding: u32, stride: u32) -> Self {
+        Self {
+            binding,
+            stride,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct VertexAttributeDesc {
+    pub location: u32,
+    pub binding: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+#[derive(Copy, Clone)]
+pub struct RasterizationDesc {
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub line_width: f32,
+}
+
+impl Default for RasterizationDesc {
+    fn default() -> Self {
+        Self {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+        }
+    }
+}
+
+// only used when the pipeline has tessellation shaders, which also need
+// PATCH_LIST as the topology
+#[derive(Copy, Clone)]
+pub struct TessellationDesc {
+    pub patch_control_points: u32,
+}
+
+impl Default for TessellationDesc {
+    fn default() -> Self {
+        Self {
+            patch_control_points: 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BlendDesc {
+    pub blend_enable: bool,
+    pub src_color_blend_factor: vk::BlendFactor,
+    pub dst_color_blend_factor: vk::BlendFactor,
+    pub color_blend_op: vk::BlendOp,
+    pub src_alpha_blend_factor: vk::BlendFactor,
+    pub dst_alpha_blend_factor: vk::BlendFactor,
+    pub alpha_blend_op: vk::BlendOp,
+    // the channels the attachment writes, RGBA in every preset
+    pub color_write_mask: vk::ColorComponentFlags,
+}
+
+impl BlendDesc {
+    pub fn opaque() -> Self {
+        Self {
+            blend_enable: false,
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ZERO,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+
+    pub fn alpha() -> Self {
+        Self {
+            blend_enable: true,
+            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+
+    // for colors that were already multiplied by their alpha, e.g. egui output
+    pub fn premultiplied_alpha() -> Self {
+        Self {
+            blend_enable: true,
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_DST_ALPHA,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+
+    pub fn additive() -> Self {
+        Self {
+            blend_enable: true,
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ONE,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+
+    // e.g. RGB to keep the alpha of the attachment, or a single channel
+    pub fn with_color_write_mask(mut self, color_write_mask: vk::ColorComponentFlags) -> Self {
+        self.color_write_mask = color_write_mask;
+        self
+    }
+
+    fn to_vk(self) -> vk::PipelineColorBlendAttachmentState {
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: self.blend_enable.into(),
+            src_color_blend_factor: self.src_color_blend_factor,
+            dst_color_blend_factor: self.dst_color_blend_factor,
+            color_blend_op: self.color_blend_op,
+            src_alpha_blend_factor: self.src_alpha_blend_factor,
+            dst_alpha_blend_factor: self.dst_alpha_blend_factor,
+            alpha_blend_op: self.alpha_blend_op,
+            color_write_mask: self.color_write_mask,
+        }
+    }
+}
+
+// the reference value is part of the pipeline, masks are 0xff for 8 bit stencil
+#[derive(Copy, Clone)]
+pub struct StencilDesc {
+    pub front: vk::StencilOpState,
+    pub back: vk::StencilOpState,
+}
+
+impl StencilDesc {
+    // the same ops for front and back faces
+    pub fn both(state: vk::StencilOpState) -> Self {
+        Self {
+            front: state,
+            back: state,
+        }
+    }
+
+    // marks every fragment that passes the depth test with reference, e.g.
+    // the selected object of an outline effect
+    pub fn write(reference: u32) -> Self {
+        Self::both(
+            vk::StencilOpState::default()
+                .compare_op(vk::CompareOp::ALWAYS)
+                .pass_op(vk::StencilOp::REPLACE)
+                .fail_op(vk::StencilOp::KEEP)
+                .depth_fail_op(vk::StencilOp::KEEP)
+                .compare_mask(0xff)
+                .write_mask(0xff)
+                .reference(reference),
+        )
+    }
+
+    // only draws where the stencil is not reference and leaves it untouched,
+    // e.g. the enlarged outline pass after write
+    pub fn not_equal(reference: u32) -> Self {
+        Self::both(
+            vk::StencilOpState::default()
+                .compare_op(vk::CompareOp::NOT_EQUAL)
+                .pass_op(vk::StencilOp::KEEP)
+                .fail_op(vk::StencilOp::KEEP)
+                .depth_fail_op(vk::StencilOp::KEEP)
+                .compare_mask(0xff)
+                .write_mask(0)
+                .reference(reference),
+        )
+    }
+}
+
+// only used when the pipeline has a depth attachment, the stencil test also
+// needs that format to have a stencil aspect
+#[derive(Copy, Clone)]
+pub struct DepthStencilDesc {
+    pub depth_test_enable: bool,
+    pub depth_write_enable: bool,
+    pub depth_compare_op: vk::CompareOp,
+    // None disables the stencil test
+    pub stencil: Option<StencilDesc>,
+}
+
+impl Default for DepthStencilDesc {
+    fn default() -> Self {
+        Self {
+            depth_test_enable: true,
+            depth_write_enable: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            stencil: None,
+        }
+    }
+}
+
+impl DepthStencilDesc {
+    fn to_vk(self) -> vk::PipelineDepthStencilStateCreateInfo<'static> {
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(self.depth_test_enable)
+            .depth_write_enable(self.depth_write_enable)
+            .depth_compare_op(self.depth_compare_op)
+            .max_depth_bounds(1.0);
+
+        match self.stencil {
+            Some(stencil) => depth_stencil_state
+                .stencil_test_enable(true)
+                .front(stencil.front)
+                .back(stencil.back),
+            None => depth_stencil_state,
         }
     }
 }
 
+#[derive(Clone)]
 pub struct RasterPipelineDesc {
     pub shaders: Vec<ShaderDesc>,
+    // may be empty for depth only passes like shadow maps
+    pub color_attachments: Vec<vk::Format>,
+    pub blend: Vec<BlendDesc>,
+    // replaces blending for every attachment, needs the logicOp feature and
+    // only affects integer and normalized formats
+    pub logic_op_enable: bool,
+    pub logic_op: vk::LogicOp,
+    pub depth_attachment: Option<vk::Format>,
+    pub depth_stencil: DepthStencilDesc,
+    pub rasterization: RasterizationDesc,
+    pub tessellation: TessellationDesc,
+    pub vertex_bindings: Vec<VertexBindingDesc>,
+    pub vertex_attributes: Vec<VertexAttributeDesc>,
+    pub topology: vk::PrimitiveTopology,
+    pub primitive_restart_enable: bool,
+    // has to match the sample count of every attachment
+    pub sample_count: vk::SampleCountFlags,
+    // has to match the view_mask the pass renders with, see multiview_rendering_info
+    pub view_mask: u32,
+}
+
+impl Default for RasterPipelineDesc {
+    fn default() -> Self {
+        Self {
+            shaders: Vec::new(),
+            color_attachments: Vec::new(),
+            blend: Vec::new(),
+            logic_op_enable: false,
+            logic_op: vk::LogicOp::COPY,
+            depth_attachment: None,
+            depth_stencil: DepthStencilDesc::default(),
+            rasterization: RasterizationDesc::default(),
+            tessellation: TessellationDesc::default(),
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart_enable: false,
+            sample_count: vk::SampleCountFlags::TYPE_1,
+            view_mask: 0,
+        }
+    }
+}
+
+impl RasterPipelineDesc {
+    fn has_stage(&self, stage: ShaderStage) -> bool {
+        self.shaders.iter().any(|shader| shader.stage == stage)
+    }
+}
+
+#[derive(Clone)]
+pub struct MeshPipelineDesc {
+    pub task_shader: Option<ShaderDesc>,
+    pub mesh_shader: ShaderDesc,
+    pub fragment_shader: ShaderDesc,
     pub color_attachments: Vec<vk::Format>,
 }
 
+#[derive(Clone)]
+pub struct ComputePipelineDesc {
+    pub shader: ShaderDesc,
+}
+
+#[derive(Clone)]
+enum PipelineDesc {
+    Raster(RasterPipelineDesc),
+    Mesh(MeshPipelineDesc),
+    Compute(ComputePipelineDesc),
+}
+
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding {
+    pub info: rspirv_reflect::DescriptorInfo,
+    // every stage that references the binding
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+impl ReflectedBinding {
+    // None for descriptor types pipelines can't be created with
+    pub fn descriptor_type(&self) -> Option<vk::DescriptorType> {
+        use rspirv_reflect::DescriptorType as BindType;
+        Some(match self.info.ty {
+            BindType::UNIFORM_BUFFER => vk::DescriptorType::UNIFORM_BUFFER,
+            BindType::STORAGE_BUFFER => vk::DescriptorType::STORAGE_BUFFER,
+            BindType::COMBINED_IMAGE_SAMPLER => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            BindType::SAMPLED_IMAGE => vk::DescriptorType::SAMPLED_IMAGE,
+            BindType::SAMPLER => vk::DescriptorType::SAMPLER,
+            BindType::STORAGE_IMAGE => vk::DescriptorType::STORAGE_IMAGE,
+            BindType::UNIFORM_TEXEL_BUFFER => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            BindType::STORAGE_TEXEL_BUFFER => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            // read with subpassLoad, see Image::begin_local_read
+            BindType::INPUT_ATTACHMENT => vk::DescriptorType::INPUT_ATTACHMENT,
+            _ => return None,
+        })
+    }
+
+    // the number of array elements, None for unbounded bindless arrays. an
+    // arrayed image like Texture2DArray is a single descriptor
+    pub fn descriptor_count(&self) -> Option<u32> {
+        match self.info.binding_count {
+            rspirv_reflect::BindingCount::One => Some(1),
+            rspirv_reflect::BindingCount::StaticSized(count) => Some(count as u32),
+            rspirv_reflect::BindingCount::Unbounded => None,
+        }
+    }
+}
+
+// descriptor sets and push constants of every stage, merged the same way
+// the pipeline layout was built from them
+#[derive(Clone, Debug, Default)]
+pub struct PipelineReflection {
+    pub descriptor_sets: BTreeMap<u32, BTreeMap<u32, ReflectedBinding>>,
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineReflection {
+    // enough for set_count allocations of the given set, unbounded arrays are
+    // skipped since those sets come from a DescriptorHeap
+    pub fn descriptor_pool_sizes(
+        &self,
+        set_index: u32,
+        set_count: u32,
+    ) -> Vec<vk::DescriptorPoolSize> {
+        let mut sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+        let bindings = self.descriptor_sets.get(&set_index).into_iter().flatten();
+        for (_, binding) in bindings {
+            let Some(count) = binding.descriptor_count() else {
+                continue;
+            };
+            let Some(ty) = binding.descriptor_type() else {
+                continue;
+            };
+            match sizes.iter_mut().find(|size| size.ty == ty) {
+                Some(size) => size.descriptor_count += count * set_count,
+                None => sizes.push(vk::DescriptorPoolSize {
+                    ty,
+                    descriptor_count: count * set_count,
+                }),
+            }
+        }
+        sizes
+    }
+}
+
+struct RawPipeline {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    reflection: PipelineReflection,
+}
+
 pub struct RasterPipeline {
     device: Arc<device::Device>,
     pub pipeline: vk::Pipeline,
     layout: vk::PipelineLayout,
     set_layouts: Vec<vk::DescriptorSetLayout>,
+    reflection: PipelineReflection,
+    desc: PipelineDesc,
 }
 
-pub fn create_raster_pipeline(
-    device: Arc<device::Device>,
-    pipeline_desc: RasterPipelineDesc,
-) -> Result<RasterPipeline> {
-    let shaders = pipeline_desc.shaders;
+fn create_pipeline_layout(
+    device: &device::Device,
+    shaders: &[ShaderDesc],
+) -> Result<(
+    vk::PipelineLayout,
+    Vec<vk::DescriptorSetLayout>,
+    PipelineReflection,
+)> {
     let reflection = shaders
         .iter()
         .map(|shader| {
@@ -67,28 +569,25 @@ pub fn create_raster_pipeline(
         })
         .collect::<Result<Vec<_>>>()?;
 
-    let mut descriptor_sets = descriptor_sets.into_iter();
-    let mut merged_sets = descriptor_sets.next().unwrap_or_default();
-    for set in descriptor_sets {
+    let mut merged_sets: BTreeMap<u32, BTreeMap<u32, ReflectedBinding>> = BTreeMap::new();
+    for (shader, set) in shaders.iter().zip(descriptor_sets) {
+        let stage_flags = shader.stage.to_vk();
         for (set_index, set_bindings) in set.into_iter() {
-            match merged_sets.entry(set_index) {
-                Entry::Occupied(mut existing_set) => {
-                    let occupied_entry = existing_set.get_mut();
-                    for (binding_index, binding) in set_bindings {
-                        match occupied_entry.entry(binding_index) {
-                            Entry::Occupied(occupied_binding) => {
-                                let occupied_binding = occupied_binding.get();
-                                assert_eq!(occupied_binding.ty, binding.ty);
-                                assert_eq!(occupied_binding.name, binding.name);
-                            }
-                            Entry::Vacant(vacant_binding) => {
-                                vacant_binding.insert(binding);
-                            }
-                        }
+            let merged_bindings = merged_sets.entry(set_index).or_default();
+            for (binding_index, binding) in set_bindings {
+                match merged_bindings.entry(binding_index) {
+                    Entry::Occupied(mut occupied_binding) => {
+                        let occupied_binding = occupied_binding.get_mut();
+                        assert_eq!(occupied_binding.info.ty, binding.ty);
+                        assert_eq!(occupied_binding.info.name, binding.name);
+                        occupied_binding.stage_flags |= stage_flags;
+                    }
+                    Entry::Vacant(vacant_binding) => {
+                        vacant_binding.insert(ReflectedBinding {
+                            info: binding,
+                            stage_flags,
+                        });
                     }
-                }
-                Entry::Vacant(vacant_set) => {
-                    vacant_set.insert(set_bindings);
                 }
             }
         }
@@ -106,25 +605,59 @@ pub fn create_raster_pipeline(
         if let Some(set_bindings) = merged_sets.get(&set_index) {
             let mut bindings: Vec<vk::DescriptorSetLayoutBinding> =
                 Vec::with_capacity(set_bindings.len());
-            let binding_flags: Vec<vk::DescriptorBindingFlags> =
-                vec![vk::DescriptorBindingFlags::PARTIALLY_BOUND; set_bindings.len()];
-            let layout_create_flags = vk::DescriptorSetLayoutCreateFlags::empty();
+            let mut binding_flags: Vec<vk::DescriptorBindingFlags> =
+                Vec::with_capacity(set_bindings.len());
+            let mut layout_create_flags = vk::DescriptorSetLayoutCreateFlags::empty();
 
-            use rspirv_reflect::DescriptorType as BindType;
-            for (binding_index, binding) in set_bindings {
-                match binding.ty {
-                    BindType::UNIFORM_BUFFER => {
-                        info!("Found uniform buffer: set({set_index}), binding({binding_index})");
-                        bindings.push(
-                            vk::DescriptorSetLayoutBinding::default()
-                                .binding(*binding_index)
-                                .descriptor_count(1)
-                                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                                .stage_flags(vk::ShaderStageFlags::ALL),
-                        );
-                    }
-                    _ => todo!(),
+            for (binding_index, reflected) in set_bindings {
+                let binding = &reflected.info;
+                let descriptor_type = reflected.descriptor_type().with_context(|| {
+                    format!(
+                        "Unsupported descriptor type {:?}: set({set_index}), binding({binding_index})",
+                        binding.ty
+                    )
+                })?;
+                if descriptor_type == vk::DescriptorType::INPUT_ATTACHMENT
+                    && !device.dynamic_rendering_local_read
+                {
+                    anyhow::bail!(
+                        "Input attachment at set({set_index}), binding({binding_index}) needs \
+                         VK_KHR_dynamic_rendering_local_read, which is not supported, render \
+                         the attachment in a separate pass and sample it instead"
+                    );
                 }
+
+                let (descriptor_count, flags) = match binding.binding_count {
+                    rspirv_reflect::BindingCount::One => {
+                        (1, vk::DescriptorBindingFlags::PARTIALLY_BOUND)
+                    }
+                    rspirv_reflect::BindingCount::StaticSized(count) => {
+                        (count as u32, vk::DescriptorBindingFlags::PARTIALLY_BOUND)
+                    }
+                    // bindless, the actual count is given when allocating the set
+                    rspirv_reflect::BindingCount::Unbounded => {
+                        layout_create_flags |=
+                            vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL;
+                        (
+                            MAX_BINDLESS_DESCRIPTORS,
+                            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                                | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+                                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND,
+                        )
+                    }
+                };
+
+                info!(
+                    "Found {descriptor_type:?}[{descriptor_count}]: set({set_index}), binding({binding_index})"
+                );
+                bindings.push(
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(*binding_index)
+                        .descriptor_count(descriptor_count)
+                        .descriptor_type(descriptor_type)
+                        .stage_flags(reflected.stage_flags),
+                );
+                binding_flags.push(flags);
             }
 
             let mut binding_flags_create_info =
@@ -142,26 +675,50 @@ pub fn create_raster_pipeline(
                     .create_descriptor_set_layout(&set_layout_create_info, None)?
             };
 
+            set_layouts.push(set_layout);
+        } else {
+            // sets unused by every stage still need a layout so the set indices line up
+            let set_layout = unsafe {
+                device.raw.create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::default(),
+                    None,
+                )?
+            };
             set_layouts.push(set_layout);
         }
     }
 
-    let push_constant_ranges = reflection
+    let mut stage_ranges = shaders
         .iter()
-        .map(|shader| {
-            shader
+        .zip(&reflection)
+        .map(|(shader, reflection)| {
+            let range = reflection
                 .get_push_constant_range()
-                .context("Failed to get push constant range")
-        })
-        .filter_map(Result::ok)
-        .flatten()
-        .map(|pc| {
-            vk::PushConstantRange::default()
-                .stage_flags(vk::ShaderStageFlags::ALL)
-                .size(pc.size)
-                .offset(pc.offset)
+                .with_context(|| format!("Failed to get push constant range of {}", shader.name))?;
+            Ok(range.map(|pc| {
+                vk::PushConstantRange::default()
+                    .stage_flags(shader.stage.to_vk())
+                    .size(pc.size)
+                    .offset(pc.offset)
+            }))
         })
-        .collect::<Vec<_>>();
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+
+    // a stage may only appear in one range, so overlapping blocks from
+    // different stages are merged into a range visible to all of them
+    stage_ranges.sort_by_key(|range| range.offset);
+    let mut push_constant_ranges: Vec<vk::PushConstantRange> = Vec::new();
+    for range in stage_ranges {
+        match push_constant_ranges.last_mut() {
+            Some(last) if range.offset < last.offset + last.size => {
+                let end = (last.offset + last.size).max(range.offset + range.size);
+                last.size = end - last.offset;
+                last.stage_flags |= range.stage_flags;
+            }
+            _ => push_constant_ranges.push(range),
+        }
+    }
 
     let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
         .set_layouts(&set_layouts)
@@ -173,13 +730,26 @@ pub fn create_raster_pipeline(
             .create_pipeline_layout(&pipeline_layout_create_info, None)?
     };
 
-    let shader_stages = shaders
+    Ok((
+        pipeline_layout,
+        set_layouts,
+        PipelineReflection {
+            descriptor_sets: merged_sets,
+            push_constant_ranges,
+        },
+    ))
+}
+
+fn create_shader_stages<'a>(
+    device: &device::Device,
+    shaders: &'a [ShaderDesc],
+    specialization_infos: &'a [vk::SpecializationInfo<'a>],
+) -> Result<Vec<vk::PipelineShaderStageCreateInfo<'a>>> {
+    shaders
         .iter()
-        .map(|shader| {
-            let stage = match shader.stage {
-                ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
-                ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
-            };
+        .zip(specialization_infos)
+        .map(|(shader, specialization_info)| {
+            let stage = shader.stage.to_vk();
 
             let module_create_info = vk::ShaderModuleCreateInfo {
                 code_size: shader.spirv.len(),
@@ -194,24 +764,485 @@ pub fn create_raster_pipeline(
                     .with_context(|| format!("Failed to create shader module {}", shader.name))?
             };
 
-            Ok(vk::PipelineShaderStageCreateInfo::default()
+            let mut stage_create_info = vk::PipelineShaderStageCreateInfo::default()
                 .stage(stage)
                 .module(module)
-                .name(&shader.entry_point))
+                .name(&shader.entry_point);
+            if !shader.specialization.is_empty() {
+                stage_create_info = stage_create_info.specialization_info(specialization_info);
+            }
+
+            Ok(stage_create_info)
         })
-        .collect::<Result<Vec<_>>>()?;
+        .collect::<Result<Vec<_>>>()
+}
 
-    // TODO: vertex input & pvp
-    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+fn destroy_shader_modules(
+    device: &device::Device,
+    shader_stages: &[vk::PipelineShaderStageCreateInfo<'_>],
+) {
+    // shader modules can be destroyed after pipeline has been created
+    shader_stages.iter().for_each(|shader_stage| {
+        unsafe { device.raw.destroy_shader_module(shader_stage.module, None) };
+    });
+}
+
+pub fn create_raster_pipeline(
+    device: Arc<device::Device>,
+    pipeline_desc: RasterPipelineDesc,
+) -> Result<RasterPipeline> {
+    let raw = build_raster_pipeline(&device, &pipeline_desc)?;
+    Ok(RasterPipeline::new(
+        device,
+        raw,
+        PipelineDesc::Raster(pipeline_desc),
+    ))
+}
+
+pub fn create_compute_pipeline(
+    device: Arc<device::Device>,
+    pipeline_desc: ComputePipelineDesc,
+) -> Result<RasterPipeline> {
+    let raw = build_compute_pipeline(&device, &pipeline_desc)?;
+    Ok(RasterPipeline::new(
+        device,
+        raw,
+        PipelineDesc::Compute(pipeline_desc),
+    ))
+}
+
+fn build_compute_pipeline(
+    device: &device::Device,
+    pipeline_desc: &ComputePipelineDesc,
+) -> Result<RawPipeline> {
+    if !matches!(pipeline_desc.shader.stage, ShaderStage::Compute) {
+        anyhow::bail!(
+            "Compute pipeline {} needs a compute shader",
+            pipeline_desc.shader.name
+        );
+    }
+
+    let shaders = std::slice::from_ref(&pipeline_desc.shader);
+    let (pipeline_layout, set_layouts, reflection) = create_pipeline_layout(device, shaders)?;
+    let specialization = SpecializationData::new(&pipeline_desc.shader.specialization);
+    let specialization_info = specialization.info();
+    let shader_stages =
+        create_shader_stages(device, shaders, std::slice::from_ref(&specialization_info))?;
+
+    let pipeline_create_info = vk::ComputePipelineCreateInfo::default()
+        .stage(shader_stages[0])
+        .layout(pipeline_layout);
+
+    let pipeline = unsafe {
+        device
+            .raw
+            .create_compute_pipelines(
+                device.pipeline_cache.raw,
+                std::slice::from_ref(&pipeline_create_info),
+                None,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to create compute pipeline"))?[0]
+    };
+
+    destroy_shader_modules(device, &shader_stages);
+
+    Ok(RawPipeline {
+        pipeline,
+        layout: pipeline_layout,
+        set_layouts,
+        reflection,
+    })
+}
+
+// the stencil attachment format, only set for pipelines with a stencil test so
+// depth only passes can use a depth stencil format without binding the stencil
+// aspect, see depth_stencil_rendering_info
+fn validate_stencil(pipeline_desc: &RasterPipelineDesc) -> Result<Option<vk::Format>> {
+    if pipeline_desc.depth_stencil.stencil.is_none() {
+        return Ok(None);
+    }
+    let stencil_attachment = pipeline_desc.depth_attachment.filter(|&format| {
+        image::format_aspect_mask(format).contains(vk::ImageAspectFlags::STENCIL)
+    });
+    if stencil_attachment.is_none() {
+        anyhow::bail!(
+            "The stencil test needs a depth attachment with a stencil aspect, got {:?}",
+            pipeline_desc.depth_attachment
+        );
+    }
+    Ok(stencil_attachment)
+}
+
+fn validate_multiview(
+    device: &device::Device,
+    pipeline_desc: &RasterPipelineDesc,
+    has_tessellation: bool,
+) -> Result<()> {
+    if pipeline_desc.view_mask == 0 {
+        return Ok(());
+    }
+    device.validate_view_mask(pipeline_desc.view_mask)?;
+    if pipeline_desc.has_stage(ShaderStage::Geometry) && !device.multiview.geometry_shader {
+        anyhow::bail!("Multiview with geometry shaders requires multiviewGeometryShader");
+    }
+    if has_tessellation && !device.multiview.tessellation_shader {
+        anyhow::bail!("Multiview with tessellation requires multiviewTessellationShader");
+    }
+    Ok(())
+}
+
+fn validate_vertex_input(pipeline_desc: &RasterPipelineDesc) -> Result<()> {
+    let bindings = &pipeline_desc.vertex_bindings;
+    for (i, binding) in bindings.iter().enumerate() {
+        if bindings[..i].iter().any(|b| b.binding == binding.binding) {
+            anyhow::bail!("Vertex binding {} is described twice", binding.binding);
+        }
+    }
+
+    let attributes = &pipeline_desc.vertex_attributes;
+    for (i, attribute) in attributes.iter().enumerate() {
+        if attributes[..i]
+            .iter()
+            .any(|a| a.location == attribute.location)
+        {
+            anyhow::bail!("Vertex location {} is described twice", attribute.location);
+        }
+        if !bindings.iter().any(|b| b.binding == attribute.binding) {
+            anyhow::bail!(
+                "Vertex location {} uses undescribed binding {}",
+                attribute.location,
+                attribute.binding
+            );
+        }
+    }
+
+    // attributes the shader doesn't read are allowed, missing ones are not
+    if let Some(vertex_shader) = pipeline_desc
+        .shaders
+        .iter()
+        .find(|shader| shader.stage == ShaderStage::Vertex)
+    {
+        for location in vertex_input_locations(&vertex_shader.spirv)
+            .with_context(|| format!("Failed to reflect the inputs of {}", vertex_shader.name))?
+        {
+            if !attributes.iter().any(|a| a.location == location) {
+                anyhow::bail!(
+                    "{} reads location {location}, which has no vertex attribute",
+                    vertex_shader.name
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// locations of the Input variables, builtins like the vertex index have none.
+// rspirv_reflect only reflects descriptors and push constants
+fn vertex_input_locations(spirv: &[u8]) -> Result<Vec<u32>> {
+    const MAGIC: u32 = 0x0723_0203;
+    const OP_DECORATE: u32 = 71;
+    const OP_VARIABLE: u32 = 59;
+    const DECORATION_LOCATION: u32 = 30;
+    const STORAGE_CLASS_INPUT: u32 = 1;
+
+    let words = spirv
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    if words.len() < 5 || words[0] != MAGIC {
+        anyhow::bail!("Not a little endian spirv module");
+    }
+
+    let mut locations = HashMap::new();
+    let mut inputs = Vec::new();
+    let mut rest = &words[5..];
+    while let Some(&first) = rest.first() {
+        let word_count = (first >> 16) as usize;
+        if word_count == 0 || word_count > rest.len() {
+            anyhow::bail!("Malformed spirv instruction");
+        }
+        let operands = &rest[1..word_count];
+        match (first & 0xffff, operands) {
+            (OP_DECORATE, &[target, DECORATION_LOCATION, location, ..]) => {
+                locations.insert(target, location);
+            }
+            (OP_VARIABLE, &[_, id, STORAGE_CLASS_INPUT, ..]) => inputs.push(id),
+            _ => {}
+        }
+        rest = &rest[word_count..];
+    }
+
+    Ok(inputs
+        .iter()
+        .filter_map(|id| locations.get(id).copied())
+        .collect())
+}
+
+// returns whether the pipeline has tessellation shaders
+fn validate_tessellation(
+    device: &device::Device,
+    pipeline_desc: &RasterPipelineDesc,
+) -> Result<bool> {
+    let has_control = pipeline_desc.has_stage(ShaderStage::TessControl);
+    let has_eval = pipeline_desc.has_stage(ShaderStage::TessEval);
+    let is_patch_list = pipeline_desc.topology == vk::PrimitiveTopology::PATCH_LIST;
+
+    if has_control != has_eval {
+        anyhow::bail!("Tessellation needs both a control and an evaluation shader");
+    }
+    if has_control != is_patch_list {
+        anyhow::bail!("PATCH_LIST topology has to be used exactly when tessellating");
+    }
+    if !has_control {
+        return Ok(false);
+    }
+
+    if !device.is_feature_enabled(DeviceFeature::TessellationShader) {
+        anyhow::bail!("Tessellation shaders require the tessellationShader feature");
+    }
+    let patch_control_points = pipeline_desc.tessellation.patch_control_points;
+    let max_patch_size = device
+        .physical_device
+        .properties
+        .limits
+        .max_tessellation_patch_size;
+    if patch_control_points == 0 || patch_control_points > max_patch_size {
+        anyhow::bail!(
+            "Patch control point count {patch_control_points} is outside of 1..={max_patch_size}"
+        );
+    }
+
+    Ok(true)
+}
+
+fn build_raster_pipeline(
+    device: &device::Device,
+    pipeline_desc: &RasterPipelineDesc,
+) -> Result<RawPipeline> {
+    let rasterization = pipeline_desc.rasterization;
+    if rasterization.polygon_mode != vk::PolygonMode::FILL
+        && !device.is_feature_enabled(DeviceFeature::FillModeNonSolid)
+    {
+        anyhow::bail!(
+            "Polygon mode {:?} requires the fillModeNonSolid feature",
+            rasterization.polygon_mode
+        );
+    }
+    if rasterization.line_width != 1.0 && !device.is_feature_enabled(DeviceFeature::WideLines) {
+        anyhow::bail!("Line width other than 1.0 requires the wideLines feature");
+    }
+    if pipeline_desc.logic_op_enable && !device.is_feature_enabled(DeviceFeature::LogicOp) {
+        anyhow::bail!("Logic ops require the logicOp feature");
+    }
+    if pipeline_desc.has_stage(ShaderStage::Geometry)
+        && !device.is_feature_enabled(DeviceFeature::GeometryShader)
+    {
+        anyhow::bail!("Geometry shaders require the geometryShader feature");
+    }
+    let has_tessellation = validate_tessellation(device, pipeline_desc)?;
+    validate_multiview(device, pipeline_desc, has_tessellation)?;
+
+    validate_vertex_input(pipeline_desc)?;
+    let stencil_attachment = validate_stencil(pipeline_desc)?;
+
+    if pipeline_desc.blend.len() != pipeline_desc.color_attachments.len() {
+        anyhow::bail!(
+            "Blend state mismatch: {} color attachments but {} blend descs",
+            pipeline_desc.color_attachments.len(),
+            pipeline_desc.blend.len()
+        );
+    }
+    if pipeline_desc
+        .blend
+        .windows(2)
+        .any(|pair| pair[0] != pair[1])
+        && !device.is_feature_enabled(DeviceFeature::IndependentBlend)
+    {
+        anyhow::bail!("Different blend descs per attachment require the independentBlend feature");
+    }
+
+    let max_sample_count = device.max_usable_sample_count();
+    if pipeline_desc.sample_count.as_raw() > max_sample_count.as_raw() {
+        anyhow::bail!(
+            "Sample count {:?} is above the supported maximum {:?}",
+            pipeline_desc.sample_count,
+            max_sample_count
+        );
+    }
+
+    let shaders = &pipeline_desc.shaders;
+    let (pipeline_layout, set_layouts, reflection) = create_pipeline_layout(device, shaders)?;
+    // specialization data has to outlive pipeline creation
+    let specializations = shaders
+        .iter()
+        .map(|shader| SpecializationData::new(&shader.specialization))
+        .collect::<Vec<_>>();
+    let specialization_infos = specializations
+        .iter()
+        .map(SpecializationData::info)
+        .collect::<Vec<_>>();
+    let shader_stages = create_shader_stages(device, shaders, &specialization_infos)?;
+
+    let vertex_binding_descriptions = pipeline_desc
+        .vertex_bindings
+        .iter()
+        .map(|binding| {
+            vk::VertexInputBindingDescription::default()
+                .binding(binding.binding)
+                .stride(binding.stride)
+                .input_rate(binding.input_rate)
+        })
+        .collect::<Vec<_>>();
+    let vertex_attribute_descriptions = pipeline_desc
+        .vertex_attributes
+        .iter()
+        .map(|attribute| {
+            vk::VertexInputAttributeDescription::default()
+                .location(attribute.location)
+                .binding(attribute.binding)
+                .format(attribute.format)
+                .offset(attribute.offset)
+        })
+        .collect::<Vec<_>>();
+
+    // empty when vertices are pulled in the shader
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default()
+        .vertex_binding_descriptions(&vertex_binding_descriptions)
+        .vertex_attribute_descriptions(&vertex_attribute_descriptions);
 
     let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
-        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        .topology(pipeline_desc.topology)
+        .primitive_restart_enable(pipeline_desc.primitive_restart_enable);
+
+    let tessellation_state = vk::PipelineTessellationStateCreateInfo::default()
+        .patch_control_points(pipeline_desc.tessellation.patch_control_points);
+
+    let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+        .scissor_count(1)
+        .viewport_count(1);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+        .polygon_mode(rasterization.polygon_mode)
+        .cull_mode(rasterization.cull_mode)
+        .front_face(rasterization.front_face)
+        .line_width(rasterization.line_width);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+        .rasterization_samples(pipeline_desc.sample_count);
+
+    // no attachment states and no color formats for depth only passes
+    let color_blend_attachment_states = pipeline_desc
+        .blend
+        .iter()
+        .map(|blend| blend.to_vk())
+        .collect::<Vec<_>>();
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::default()
+        .logic_op_enable(pipeline_desc.logic_op_enable)
+        .logic_op(pipeline_desc.logic_op)
+        .attachments(&color_blend_attachment_states);
+
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+        .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]);
+
+    let depth_stencil_state = pipeline_desc.depth_stencil.to_vk();
+
+    let mut dynamic_rendering = vk::PipelineRenderingCreateInfo::default()
+        .view_mask(pipeline_desc.view_mask)
+        .color_attachment_formats(&pipeline_desc.color_attachments)
+        .depth_attachment_format(
+            pipeline_desc
+                .depth_attachment
+                .unwrap_or(vk::Format::UNDEFINED),
+        )
+        .stencil_attachment_format(stencil_attachment.unwrap_or(vk::Format::UNDEFINED));
+
+    let mut pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
+        .stages(&shader_stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(pipeline_layout)
+        .push_next(&mut dynamic_rendering);
+
+    if pipeline_desc.depth_attachment.is_some() {
+        pipeline_create_info = pipeline_create_info.depth_stencil_state(&depth_stencil_state);
+    }
+    if has_tessellation {
+        pipeline_create_info = pipeline_create_info.tessellation_state(&tessellation_state);
+    }
+
+    let pipeline = unsafe {
+        device
+            .raw
+            .create_graphics_pipelines(
+                device.pipeline_cache.raw,
+                &std::slice::from_ref(&pipeline_create_info),
+                None,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to create graphics pipeline"))?[0]
+    };
+
+    destroy_shader_modules(device, &shader_stages);
+
+    Ok(RawPipeline {
+        pipeline,
+        layout: pipeline_layout,
+        set_layouts,
+        reflection,
+    })
+}
+
+pub fn create_mesh_pipeline(
+    device: Arc<device::Device>,
+    pipeline_desc: MeshPipelineDesc,
+) -> Result<RasterPipeline> {
+    let raw = build_mesh_pipeline(&device, &pipeline_desc)?;
+    Ok(RasterPipeline::new(
+        device,
+        raw,
+        PipelineDesc::Mesh(pipeline_desc),
+    ))
+}
+
+fn build_mesh_pipeline(
+    device: &device::Device,
+    pipeline_desc: &MeshPipelineDesc,
+) -> Result<RawPipeline> {
+    if device.mesh_shader.is_none() {
+        anyhow::bail!("Mesh shaders are not supported or were not requested on this device");
+    }
+    if pipeline_desc.task_shader.is_some() && !device.task_shader_supported {
+        anyhow::bail!("Task shaders are not supported on this device");
+    }
+
+    let shaders = pipeline_desc
+        .task_shader
+        .iter()
+        .chain([&pipeline_desc.mesh_shader, &pipeline_desc.fragment_shader])
+        .cloned()
+        .collect::<Vec<_>>();
+    let (pipeline_layout, set_layouts, reflection) = create_pipeline_layout(device, &shaders)?;
+    // specialization data has to outlive pipeline creation
+    let specializations = shaders
+        .iter()
+        .map(|shader| SpecializationData::new(&shader.specialization))
+        .collect::<Vec<_>>();
+    let specialization_infos = specializations
+        .iter()
+        .map(SpecializationData::info)
+        .collect::<Vec<_>>();
+    let shader_stages = create_shader_stages(device, &shaders, &specialization_infos)?;
 
     let viewport_state = vk::PipelineViewportStateCreateInfo::default()
         .scissor_count(1)
         .viewport_count(1);
 
-    // TODO: hardcoded for testing, allow changes later
     let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
         .polygon_mode(vk::PolygonMode::FILL)
         .cull_mode(vk::CullModeFlags::NONE)
@@ -221,19 +1252,14 @@ pub fn create_raster_pipeline(
     let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
         .rasterization_samples(vk::SampleCountFlags::TYPE_1);
 
-    let color_blend_attachment_state = vk::PipelineColorBlendAttachmentState {
-        blend_enable: vk::TRUE,
-        src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
-        dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
-        color_blend_op: vk::BlendOp::ADD,
-        src_alpha_blend_factor: vk::BlendFactor::ONE,
-        dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
-        alpha_blend_op: vk::BlendOp::ADD,
-        color_write_mask: vk::ColorComponentFlags::RGBA,
-    };
+    let color_blend_attachment_states = pipeline_desc
+        .color_attachments
+        .iter()
+        .map(|_| BlendDesc::alpha().to_vk())
+        .collect::<Vec<_>>();
 
     let color_blend_state = vk::PipelineColorBlendStateCreateInfo::default()
-        .attachments(std::slice::from_ref(&color_blend_attachment_state));
+        .attachments(&color_blend_attachment_states);
 
     let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
         .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]);
@@ -241,10 +1267,9 @@ pub fn create_raster_pipeline(
     let mut dynamic_rendering = vk::PipelineRenderingCreateInfo::default()
         .color_attachment_formats(&pipeline_desc.color_attachments);
 
+    // mesh pipelines have no vertex input or input assembly state
     let pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
         .stages(&shader_stages)
-        .vertex_input_state(&vertex_input_state)
-        .input_assembly_state(&input_assembly_state)
         .viewport_state(&viewport_state)
         .rasterization_state(&rasterization_state)
         .multisample_state(&multisample_state)
@@ -257,34 +1282,195 @@ pub fn create_raster_pipeline(
         device
             .raw
             .create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &std::slice::from_ref(&pipeline_create_info),
+                device.pipeline_cache.raw,
+                std::slice::from_ref(&pipeline_create_info),
                 None,
             )
-            .map_err(|_| anyhow::anyhow!("Failed to create graphics pipeline"))?[0]
+            .map_err(|_| anyhow::anyhow!("Failed to create mesh pipeline"))?[0]
     };
 
-    // shader modules can be destroyed after pipeline has been created
-    shader_stages.iter().for_each(|shader_stage| {
-        unsafe { device.raw.destroy_shader_module(shader_stage.module, None) };
-    });
+    destroy_shader_modules(device, &shader_stages);
 
-    Ok(RasterPipeline {
-        device: device,
+    Ok(RawPipeline {
         pipeline,
         layout: pipeline_layout,
         set_layouts,
+        reflection,
     })
 }
 
+impl RasterPipeline {
+    fn new(device: Arc<device::Device>, raw: RawPipeline, desc: PipelineDesc) -> Self {
+        Self {
+            device,
+            pipeline: raw.pipeline,
+            layout: raw.layout,
+            set_layouts: raw.set_layouts,
+            reflection: raw.reflection,
+            desc,
+        }
+    }
+
+    // recompiles every shader that came from a slang source and recreates the
+    // pipeline in place, on failure the current pipeline is left untouched
+    pub fn reload(&mut self, compiler: &ShaderCompiler) -> Result<()> {
+        let desc = match &self.desc {
+            PipelineDesc::Raster(desc) => PipelineDesc::Raster(RasterPipelineDesc {
+                shaders: desc
+                    .shaders
+                    .iter()
+                    .map(|shader| shader.recompile(compiler))
+                    .collect::<Result<Vec<_>>>()?,
+                ..desc.clone()
+            }),
+            PipelineDesc::Mesh(desc) => PipelineDesc::Mesh(MeshPipelineDesc {
+                task_shader: desc
+                    .task_shader
+                    .as_ref()
+                    .map(|shader| shader.recompile(compiler))
+                    .transpose()?,
+                mesh_shader: desc.mesh_shader.recompile(compiler)?,
+                fragment_shader: desc.fragment_shader.recompile(compiler)?,
+                ..desc.clone()
+            }),
+            PipelineDesc::Compute(desc) => PipelineDesc::Compute(ComputePipelineDesc {
+                shader: desc.shader.recompile(compiler)?,
+            }),
+        };
+
+        let raw = match &desc {
+            PipelineDesc::Raster(desc) => build_raster_pipeline(&self.device, desc)?,
+            PipelineDesc::Mesh(desc) => build_mesh_pipeline(&self.device, desc)?,
+            PipelineDesc::Compute(desc) => build_compute_pipeline(&self.device, desc)?,
+        };
+
+        // the old pipeline may still be used by frames in flight
+        let old_pipeline = std::mem::replace(&mut self.pipeline, raw.pipeline);
+        let old_layout = std::mem::replace(&mut self.layout, raw.layout);
+        let old_set_layouts = std::mem::replace(&mut self.set_layouts, raw.set_layouts);
+        self.device.defer_destroy(move |device| {
+            destroy_pipeline_objects(device, old_pipeline, old_layout, &old_set_layouts);
+        });
+
+        self.reflection = raw.reflection;
+        self.desc = desc;
+
+        info!("Reloaded pipeline");
+        Ok(())
+    }
+
+    fn destroy_raw(&self) {
+        destroy_pipeline_objects(&self.device, self.pipeline, self.layout, &self.set_layouts);
+    }
+
+    // for binding descriptor sets, changes when the pipeline is reloaded
+    pub fn layout(&self) -> vk::PipelineLayout {
+        self.layout
+    }
+
+    pub fn bind_point(&self) -> vk::PipelineBindPoint {
+        match self.desc {
+            PipelineDesc::Raster(_) | PipelineDesc::Mesh(_) => vk::PipelineBindPoint::GRAPHICS,
+            PipelineDesc::Compute(_) => vk::PipelineBindPoint::COMPUTE,
+        }
+    }
+
+    pub fn reflection(&self) -> &PipelineReflection {
+        &self.reflection
+    }
+
+    // T has to match the reflected push constant range the given stages are in,
+    // starting at its offset. stages whose blocks overlap share one range and
+    // are pushed together, disjoint blocks of different stages are pushed
+    // with separate calls
+    pub fn push_constants<T: bytemuck::Pod>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        stage_flags: vk::ShaderStageFlags,
+        data: &T,
+    ) -> Result<()> {
+        let mut ranges = self
+            .reflection
+            .push_constant_ranges
+            .iter()
+            .filter(|range| range.stage_flags.intersects(stage_flags));
+        let range = ranges
+            .next()
+            .context("Pipeline has no push constants for the given stages")?;
+        if let Some(other) = ranges.next() {
+            anyhow::bail!(
+                "{stage_flags:?} spans the push constant ranges of {:?} and {:?}, push them separately",
+                range.stage_flags,
+                other.stage_flags
+            );
+        }
+
+        let size = std::mem::size_of::<T>();
+        if size != range.size as usize {
+            anyhow::bail!(
+                "Push constant size mismatch: got {size} bytes, the range at offset {} is {} bytes",
+                range.offset,
+                range.size
+            );
+        }
+
+        // every stage of the range has to be updated, not just the requested ones
+        unsafe {
+            self.device.raw.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                range.stage_flags,
+                range.offset,
+                bytemuck::bytes_of(data),
+            )
+        };
+
+        Ok(())
+    }
+
+    // bindless sets with an unbounded array should come from a DescriptorHeap instead
+    pub fn allocate_descriptor_set(
+        &self,
+        pool: &DescriptorPool,
+        set_index: usize,
+    ) -> Result<vk::DescriptorSet> {
+        let set_layout = self
+            .set_layouts
+            .get(set_index)
+            .with_context(|| format!("Pipeline has no descriptor set {set_index}"))?;
+
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool.raw)
+            .set_layouts(std::slice::from_ref(set_layout));
+
+        let set = unsafe {
+            self.device
+                .raw
+                .allocate_descriptor_sets(&allocate_info)
+                .with_context(|| format!("Failed to allocate descriptor set {set_index}"))?[0]
+        };
+
+        Ok(set)
+    }
+}
+
+fn destroy_pipeline_objects(
+    device: &device::Device,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    set_layouts: &[vk::DescriptorSetLayout],
+) {
+    unsafe {
+        set_layouts.iter().for_each(|set_layout| {
+            device.raw.destroy_descriptor_set_layout(*set_layout, None);
+        });
+        device.raw.destroy_pipeline_layout(layout, None);
+        device.raw.destroy_pipeline(pipeline, None);
+    }
+}
+
 impl Drop for RasterPipeline {
     fn drop(&mut self) {
-        unsafe {
-            self.set_layouts.iter().for_each(|layout| {
-                self.device.raw.destroy_descriptor_set_layout(*layout, None);
-            });
-            self.device.raw.destroy_pipeline_layout(self.layout, None);
-            self.device.raw.destroy_pipeline(self.pipeline, None);
-        }
+        self.destroy_raw();
     }
 }
@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+
+// VkPipelineCacheHeaderVersionOne
+const HEADER_SIZE: usize = 32;
+
+pub struct PipelineCache {
+    pub raw: vk::PipelineCache,
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    pub fn new(
+        device: &ash::Device,
+        properties: &vk::PhysicalDeviceProperties,
+        path: Option<&Path>,
+    ) -> Result<Self> {
+        let initial_data = path
+            .and_then(|path| Self::read_cache_file(path, properties))
+            .unwrap_or_default();
+
+        let create_info = vk::PipelineCacheCreateInfo::default().initial_data(&initial_data);
+        let raw = match unsafe { device.create_pipeline_cache(&create_info, None) } {
+            Ok(raw) => raw,
+            Err(e) if !initial_data.is_empty() => {
+                warn!("Failed to create pipeline cache from file ({e}), starting empty");
+                let create_info = vk::PipelineCacheCreateInfo::default();
+                unsafe { device.create_pipeline_cache(&create_info, None) }
+                    .context("Failed to create pipeline cache")?
+            }
+            Err(e) => return Err(e).context("Failed to create pipeline cache"),
+        };
+
+        Ok(Self {
+            raw,
+            path: path.map(Path::to_path_buf),
+        })
+    }
+
+    fn read_cache_file(path: &Path, properties: &vk::PhysicalDeviceProperties) -> Option<Vec<u8>> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                info!("No pipeline cache loaded from {} ({e})", path.display());
+                return None;
+            }
+        };
+
+        if !Self::is_header_valid(&data, properties) {
+            warn!(
+                "Pipeline cache {} is corrupt or from another device, ignoring",
+                path.display()
+            );
+            return None;
+        }
+
+        info!(
+            "Loaded pipeline cache from {} ({} bytes)",
+            path.display(),
+            data.len()
+        );
+        Some(data)
+    }
+
+    fn is_header_valid(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+        if data.len() < HEADER_SIZE {
+            return false;
+        }
+
+        let read_u32 =
+            |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+        let header_size = read_u32(0);
+        let header_version = read_u32(4);
+        let vendor_id = read_u32(8);
+        let device_id = read_u32(12);
+        let uuid = &data[16..32];
+
+        header_size as usize >= HEADER_SIZE
+            && header_version == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+            && vendor_id == properties.vendor_id
+            && device_id == properties.device_id
+            && uuid == properties.pipeline_cache_uuid
+    }
+
+    pub fn save(&self, device: &ash::Device) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let data = unsafe { device.get_pipeline_cache_data(self.raw) }
+            .context("Failed to get pipeline cache data")?;
+        std::fs::write(path, &data)
+            .with_context(|| format!("Failed to write pipeline cache to {}", path.display()))?;
+
+        info!(
+            "Saved pipeline cache to {} ({} bytes)",
+            path.display(),
+            data.len()
+        );
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe { device.destroy_pipeline_cache(self.raw, None) };
+    }
+}
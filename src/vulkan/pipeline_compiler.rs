@@ -0,0 +1,85 @@
+use anyhow::Result;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::device::Device;
+use super::pipeline::{self, RasterPipeline, RasterPipelineDesc};
+
+// compiles shaders and creates pipelines on scoped worker threads, see
+// compile_batch_with. vkCreateGraphicsPipelines is
+// thread safe and the shared pipeline cache is internally synchronized since
+// it isn't created with EXTERNALLY_SYNCHRONIZED, so workers only share the
+// device and its cache
+pub struct PipelineCompiler {
+    device: Arc<Device>,
+    thread_count: usize,
+}
+
+impl PipelineCompiler {
+    pub fn new(device: Arc<Device>) -> Self {
+        let thread_count = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        Self {
+            device,
+            thread_count,
+        }
+    }
+
+    pub fn thread_count(mut self, thread_count: usize) -> Self {
+        self.thread_count = thread_count.max(1);
+        self
+    }
+
+    // blocks until every pipeline is created, the results are in the same
+    // order as descs. the shaders of descs are compiled already, only the
+    // pipelines are created on the workers
+    pub fn compile_batch(&self, descs: &[RasterPipelineDesc]) -> Vec<Result<RasterPipeline>> {
+        self.compile_batch_with(descs, |desc| Ok(desc.clone()))
+    }
+
+    // build_desc runs on the worker that creates the pipeline, so shaders
+    // compiled in it with ShaderCompiler::compile_slang are compiled on the
+    // pool as well. the results are in the same order as sources. workers pull
+    // the next source when done, so a few expensive pipelines don't hold up a
+    // whole chunk behind them
+    pub fn compile_batch_with<T, F>(
+        &self,
+        sources: &[T],
+        build_desc: F,
+    ) -> Vec<Result<RasterPipeline>>
+    where
+        T: Sync,
+        F: Fn(&T) -> Result<RasterPipelineDesc> + Sync,
+    {
+        let next_index = AtomicUsize::new(0);
+        let thread_count = self.thread_count.min(sources.len());
+
+        let mut results = std::thread::scope(|scope| {
+            let workers = (0..thread_count)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        loop {
+                            let index = next_index.fetch_add(1, Ordering::Relaxed);
+                            let Some(source) = sources.get(index) else {
+                                break results;
+                            };
+                            let pipeline = build_desc(source).and_then(|desc| {
+                                pipeline::create_raster_pipeline(self.device.clone(), desc)
+                            });
+                            results.push((index, pipeline));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("Pipeline worker panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, pipeline)| pipeline).collect()
+    }
+}
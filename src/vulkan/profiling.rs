@@ -0,0 +1,120 @@
+// thin wrappers around tracy so call sites don't need to be feature gated,
+// everything here compiles to nothing without the `tracy` feature
+
+pub fn start() {
+    #[cfg(feature = "tracy")]
+    tracy_client::Client::start();
+}
+
+pub fn frame_mark() {
+    #[cfg(feature = "tracy")]
+    {
+        if let Some(client) = tracy_client::Client::running() {
+            client.frame_mark();
+        }
+    }
+}
+
+/// CPU zone that ends when dropped.
+pub struct Zone {
+    #[cfg(feature = "tracy")]
+    _span: Option<tracy_client::Span>,
+}
+
+#[cfg(feature = "tracy")]
+#[track_caller]
+pub fn zone(name: &str) -> Zone {
+    let location = std::panic::Location::caller();
+    Zone {
+        _span: tracy_client::Client::running()
+            .map(|client| client.span_alloc(Some(name), "", location.file(), location.line(), 0)),
+    }
+}
+
+#[cfg(not(feature = "tracy"))]
+pub fn zone(_name: &str) -> Zone {
+    Zone {}
+}
+
+#[cfg(feature = "tracy")]
+pub fn enabled() -> bool {
+    tracy_client::Client::running().is_some()
+}
+
+#[cfg(not(feature = "tracy"))]
+pub fn enabled() -> bool {
+    false
+}
+
+// tracy gpu timeline, timestamps are in raw gpu ticks
+pub struct GpuContext {
+    #[cfg(feature = "tracy")]
+    context: Option<tracy_client::GpuContext>,
+}
+
+// gpu zone, uploaded to tracy once the query results are read back
+pub struct GpuSpan {
+    #[cfg(feature = "tracy")]
+    span: Option<tracy_client::GpuSpan>,
+}
+
+impl GpuContext {
+    #[cfg(feature = "tracy")]
+    pub fn new(name: &str, gpu_timestamp: i64, period: f32) -> Self {
+        let context = tracy_client::Client::running().and_then(|client| {
+            client
+                .new_gpu_context(
+                    Some(name),
+                    tracy_client::GpuContextType::Vulkan,
+                    gpu_timestamp,
+                    period,
+                )
+                .ok()
+        });
+        Self { context }
+    }
+
+    #[cfg(not(feature = "tracy"))]
+    pub fn new(_name: &str, _gpu_timestamp: i64, _period: f32) -> Self {
+        Self {}
+    }
+
+    #[cfg(feature = "tracy")]
+    #[track_caller]
+    pub fn span(&self, name: &str) -> GpuSpan {
+        let location = std::panic::Location::caller();
+        GpuSpan {
+            span: self.context.as_ref().and_then(|context| {
+                context
+                    .span_alloc(name, "", location.file(), location.line())
+                    .ok()
+            }),
+        }
+    }
+
+    #[cfg(not(feature = "tracy"))]
+    pub fn span(&self, _name: &str) -> GpuSpan {
+        GpuSpan {}
+    }
+}
+
+impl GpuSpan {
+    pub fn end(&mut self) {
+        #[cfg(feature = "tracy")]
+        {
+            if let Some(span) = &mut self.span {
+                span.end_zone();
+            }
+        }
+    }
+
+    pub fn upload(&self, _start: i64, _end: i64) {
+        #[cfg(feature = "tracy")]
+        {
+            if let Some(span) = &self.span {
+                span.upload_timestamp_start(_start);
+                span.upload_timestamp_end(_end);
+            }
+        }
+    }
+}
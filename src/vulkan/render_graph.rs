@@ -0,0 +1,267 @@
+use ash::vk;
+use vk_sync::{AccessType, BufferBarrier, ImageBarrier, ImageLayout};
+
+use super::device::Device;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GraphImage(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GraphBuffer(usize);
+
+#[derive(Copy, Clone)]
+enum ResourceKind {
+    Image {
+        raw: vk::Image,
+        range: vk::ImageSubresourceRange,
+    },
+    Buffer {
+        raw: vk::Buffer,
+        size: u64,
+    },
+}
+
+struct GraphResource {
+    name: String,
+    kind: ResourceKind,
+    // accesses of the last pass that used the resource
+    last_accesses: Vec<AccessType>,
+}
+
+type PassCallback<'a> = Box<dyn FnOnce(vk::CommandBuffer) + 'a>;
+
+struct Pass<'a> {
+    name: String,
+    // grouped by resource, a pass can use one resource in several ways
+    accesses: Vec<(usize, Vec<AccessType>)>,
+    callback: Option<PassCallback<'a>>,
+}
+
+// records passes in declaration order, the barriers between them are derived
+// from the resources each pass declares instead of being written by hand
+pub struct RenderGraph<'a> {
+    resources: Vec<GraphResource>,
+    passes: Vec<Pass<'a>>,
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    pass: Pass<'a>,
+}
+
+impl Default for RenderGraph<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    // the whole image with the aspect of its format, previous_access is how the
+    // image was last used before this graph, Nothing discards the contents
+    pub fn import_image(
+        &mut self,
+        name: impl Into<String>,
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        previous_access: AccessType,
+    ) -> GraphImage {
+        let range = vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        };
+        GraphImage(self.import(
+            name,
+            ResourceKind::Image { raw: image, range },
+            previous_access,
+        ))
+    }
+
+    pub fn import_buffer(
+        &mut self,
+        name: impl Into<String>,
+        buffer: vk::Buffer,
+        size: u64,
+        previous_access: AccessType,
+    ) -> GraphBuffer {
+        GraphBuffer(self.import(
+            name,
+            ResourceKind::Buffer { raw: buffer, size },
+            previous_access,
+        ))
+    }
+
+    fn import(
+        &mut self,
+        name: impl Into<String>,
+        kind: ResourceKind,
+        previous_access: AccessType,
+    ) -> usize {
+        self.resources.push(GraphResource {
+            name: name.into(),
+            kind,
+            last_accesses: vec![previous_access],
+        });
+        self.resources.len() - 1
+    }
+
+    pub fn add_pass<'g>(&'g mut self, name: impl Into<String>) -> PassBuilder<'g, 'a> {
+        PassBuilder {
+            graph: self,
+            pass: Pass {
+                name: name.into(),
+                accesses: Vec::new(),
+                callback: None,
+            },
+        }
+    }
+
+    // terminal node, leaves the image ready for vkQueuePresentKHR
+    pub fn present(&mut self, image: GraphImage) {
+        self.passes.push(Pass {
+            name: format!("present {}", self.resources[image.0].name),
+            accesses: vec![(image.0, vec![AccessType::Present])],
+            callback: None,
+        });
+    }
+
+    // how the image is left after the last pass, for tracking across frames
+    pub fn final_image_accesses(&self, image: GraphImage) -> &[AccessType] {
+        &self.resources[image.0].last_accesses
+    }
+
+    pub fn final_buffer_accesses(&self, buffer: GraphBuffer) -> &[AccessType] {
+        &self.resources[buffer.0].last_accesses
+    }
+
+    // every pass gets a single batched barrier before its commands
+    pub fn execute(&mut self, device: &Device, command_buffer: vk::CommandBuffer) {
+        for mut pass in std::mem::take(&mut self.passes) {
+            let mut image_barriers = Vec::new();
+            let mut buffer_barriers = Vec::new();
+
+            for (resource_index, next_accesses) in &pass.accesses {
+                let resource = &self.resources[*resource_index];
+                if !needs_barrier(&resource.last_accesses, next_accesses) {
+                    continue;
+                }
+
+                match resource.kind {
+                    ResourceKind::Image { raw, range } => image_barriers.push(ImageBarrier {
+                        previous_accesses: &resource.last_accesses,
+                        next_accesses,
+                        previous_layout: ImageLayout::Optimal,
+                        next_layout: ImageLayout::Optimal,
+                        discard_contents: resource.last_accesses == [AccessType::Nothing],
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        image: raw,
+                        range,
+                    }),
+                    ResourceKind::Buffer { raw, size } => buffer_barriers.push(BufferBarrier {
+                        previous_accesses: &resource.last_accesses,
+                        next_accesses,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        buffer: raw,
+                        offset: 0,
+                        size: size as usize,
+                    }),
+                }
+            }
+
+            if !image_barriers.is_empty() || !buffer_barriers.is_empty() {
+                vk_sync::cmd::pipeline_barrier(
+                    &device.raw,
+                    command_buffer,
+                    None,
+                    &buffer_barriers,
+                    &image_barriers,
+                );
+            }
+
+            for (resource_index, next_accesses) in std::mem::take(&mut pass.accesses) {
+                self.resources[resource_index].last_accesses = next_accesses;
+            }
+
+            if let Some(callback) = pass.callback.take() {
+                let _scope = device.debug_scope(command_buffer, &pass.name, [0.4, 0.6, 1.0, 1.0]);
+                callback(command_buffer);
+            }
+        }
+    }
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    fn access(mut self, resource_index: usize, access: AccessType) -> Self {
+        match self
+            .pass
+            .accesses
+            .iter_mut()
+            .find(|(index, _)| *index == resource_index)
+        {
+            Some((_, accesses)) => accesses.push(access),
+            None => self.pass.accesses.push((resource_index, vec![access])),
+        }
+        self
+    }
+
+    pub fn read_image(self, image: GraphImage, access: AccessType) -> Self {
+        self.access(image.0, access)
+    }
+
+    pub fn write_image(self, image: GraphImage, access: AccessType) -> Self {
+        self.access(image.0, access)
+    }
+
+    pub fn read_buffer(self, buffer: GraphBuffer, access: AccessType) -> Self {
+        self.access(buffer.0, access)
+    }
+
+    pub fn write_buffer(self, buffer: GraphBuffer, access: AccessType) -> Self {
+        self.access(buffer.0, access)
+    }
+
+    // the callback runs during RenderGraph::execute, after the pass's barriers
+    pub fn execute(mut self, callback: impl FnOnce(vk::CommandBuffer) + 'a) {
+        self.pass.callback = Some(Box::new(callback));
+        self.graph.passes.push(self.pass);
+    }
+}
+
+pub(crate) fn is_write(access: AccessType) -> bool {
+    matches!(
+        access,
+        AccessType::VertexShaderWrite
+            | AccessType::TessellationControlShaderWrite
+            | AccessType::TessellationEvaluationShaderWrite
+            | AccessType::GeometryShaderWrite
+            | AccessType::FragmentShaderWrite
+            | AccessType::ColorAttachmentWrite
+            | AccessType::DepthStencilAttachmentWrite
+            | AccessType::DepthAttachmentWriteStencilReadOnly
+            | AccessType::StencilAttachmentWriteDepthReadOnly
+            | AccessType::ComputeShaderWrite
+            | AccessType::AnyShaderWrite
+            | AccessType::TransferWrite
+            | AccessType::HostWrite
+            | AccessType::ColorAttachmentReadWrite
+            | AccessType::General
+    )
+}
+
+// consecutive identical reads need neither a layout change nor a dependency
+fn needs_barrier(previous: &[AccessType], next: &[AccessType]) -> bool {
+    previous != next
+        || previous.iter().any(|access| is_write(*access))
+        || next.iter().any(|access| is_write(*access))
+}
@@ -0,0 +1,240 @@
+use ash::vk;
+
+use super::device::Device;
+
+// relative to the shader search path, see draw_fullscreen_triangle
+pub const FULLSCREEN_VERTEX_SHADER: &str = "fullscreen/fullscreen_vert.slang";
+
+// ends dynamic rendering when dropped, so an early return can't leave the
+// command buffer inside a rendering instance
+#[must_use]
+pub struct RenderingScope<'a> {
+    device: &'a Device,
+    command_buffer: vk::CommandBuffer,
+}
+
+impl<'a> RenderingScope<'a> {
+    pub fn new(
+        device: &'a Device,
+        command_buffer: vk::CommandBuffer,
+        rendering_info: &vk::RenderingInfo<'_>,
+    ) -> Self {
+        device.cmd_begin_rendering(command_buffer, rendering_info);
+        Self {
+            device,
+            command_buffer,
+        }
+    }
+}
+
+impl Drop for RenderingScope<'_> {
+    fn drop(&mut self) {
+        self.device.cmd_end_rendering(self.command_buffer);
+    }
+}
+
+impl Device {
+    pub fn begin_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        rendering_info: &vk::RenderingInfo<'_>,
+    ) -> RenderingScope<'_> {
+        RenderingScope::new(self, command_buffer, rendering_info)
+    }
+
+    // for screen space passes with FULLSCREEN_VERTEX_SHADER as the vertex stage,
+    // the pipeline needs no vertex input and only the fragment shader differs
+    // between passes. pair it with cmd_set_viewport_unflipped
+    pub fn draw_fullscreen_triangle(&self, command_buffer: vk::CommandBuffer) {
+        unsafe { self.raw.cmd_draw(command_buffer, 3, 1, 0, 0) };
+    }
+
+    // makes the color attachment writes of earlier draws in the pass visible
+    // to input attachment reads of later ones, only valid inside rendering
+    pub fn cmd_local_read_barrier(&self, command_buffer: vk::CommandBuffer) {
+        let barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::INPUT_ATTACHMENT_READ);
+        let dependency_info = vk::DependencyInfo::default()
+            .dependency_flags(vk::DependencyFlags::BY_REGION)
+            .memory_barriers(std::slice::from_ref(&barrier));
+        self.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+    }
+
+    // vulkan clip space has y pointing down, a negative height flips it so y
+    // points up like in opengl and d3d, which keeps the usual right handed
+    // projection matrices working. negative heights are core since vulkan 1.1
+    pub fn cmd_set_viewport_flipped(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+    ) {
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: extent.height as f32,
+            width: extent.width as f32,
+            height: -(extent.height as f32),
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        unsafe {
+            self.raw
+                .cmd_set_viewport(command_buffer, 0, std::slice::from_ref(&viewport))
+        };
+    }
+
+    // for passes working in vulkan's native y down convention, e.g. fullscreen passes
+    pub fn cmd_set_viewport_unflipped(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+    ) {
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        unsafe {
+            self.raw
+                .cmd_set_viewport(command_buffer, 0, std::slice::from_ref(&viewport))
+        };
+    }
+}
+
+// what happens to an attachment's previous contents when rendering begins
+#[derive(Copy, Clone)]
+pub enum LoadOp {
+    Clear(vk::ClearValue),
+    // for passes that accumulate or composite over what is already there
+    Load,
+    // the previous contents are undefined, for passes that overwrite every pixel
+    DontCare,
+}
+
+impl LoadOp {
+    pub fn clear_color(color: [f32; 4]) -> Self {
+        Self::Clear(vk::ClearValue {
+            color: vk::ClearColorValue { float32: color },
+        })
+    }
+
+    pub fn clear_depth(depth: f32) -> Self {
+        Self::Clear(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+        })
+    }
+
+    // only the stencil value is used by stencil_attachment
+    pub fn clear_stencil(stencil: u32) -> Self {
+        Self::Clear(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 0.0,
+                stencil,
+            },
+        })
+    }
+
+    fn to_vk(self) -> (vk::AttachmentLoadOp, vk::ClearValue) {
+        match self {
+            Self::Clear(clear_value) => (vk::AttachmentLoadOp::CLEAR, clear_value),
+            Self::Load => (vk::AttachmentLoadOp::LOAD, vk::ClearValue::default()),
+            Self::DontCare => (vk::AttachmentLoadOp::DONT_CARE, vk::ClearValue::default()),
+        }
+    }
+}
+
+// the contents are always stored so later passes and present can see them
+pub fn color_attachment(
+    image_view: vk::ImageView,
+    load_op: LoadOp,
+) -> vk::RenderingAttachmentInfo<'static> {
+    let (load_op, clear_value) = load_op.to_vk();
+    vk::RenderingAttachmentInfo::default()
+        .image_view(image_view)
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(load_op)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(clear_value)
+}
+
+// in the layout vk_sync uses for depth attachment accesses
+pub fn depth_attachment(
+    image_view: vk::ImageView,
+    load_op: LoadOp,
+) -> vk::RenderingAttachmentInfo<'static> {
+    let (load_op, clear_value) = load_op.to_vk();
+    vk::RenderingAttachmentInfo::default()
+        .image_view(image_view)
+        .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .load_op(load_op)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(clear_value)
+}
+
+// a color attachment that is also read as an input attachment in the same
+// pass, input_attachment_index i reads color attachment i. the image has to
+// be moved to the layout with Image::begin_local_read first
+pub fn local_read_attachment(
+    image_view: vk::ImageView,
+    load_op: LoadOp,
+) -> vk::RenderingAttachmentInfo<'static> {
+    color_attachment(image_view, load_op).image_layout(vk::ImageLayout::RENDERING_LOCAL_READ_KHR)
+}
+
+// for the stencil aspect of a depth stencil image, usually the same view as
+// the depth attachment with its own load op
+pub fn stencil_attachment(
+    image_view: vk::ImageView,
+    load_op: LoadOp,
+) -> vk::RenderingAttachmentInfo<'static> {
+    depth_attachment(image_view, load_op)
+}
+
+// covers the whole extent with a single layer, the attachments have to be in
+// the same order as RasterPipelineDesc::color_attachments. depth only passes
+// pass no color attachments and just the depth attachment
+pub fn rendering_info<'a>(
+    extent: vk::Extent2D,
+    color_attachments: &'a [vk::RenderingAttachmentInfo<'a>],
+    depth_attachment: Option<&'a vk::RenderingAttachmentInfo<'a>>,
+) -> vk::RenderingInfo<'a> {
+    let rendering_info = vk::RenderingInfo::default()
+        .layer_count(1)
+        .render_area(vk::Rect2D::default().extent(extent))
+        .color_attachments(color_attachments);
+
+    match depth_attachment {
+        Some(depth_attachment) => rendering_info.depth_attachment(depth_attachment),
+        None => rendering_info,
+    }
+}
+
+// for pipelines with a stencil test, the attachments have to use a format with
+// depth and stencil aspects
+pub fn depth_stencil_rendering_info<'a>(
+    extent: vk::Extent2D,
+    color_attachments: &'a [vk::RenderingAttachmentInfo<'a>],
+    depth_attachment: &'a vk::RenderingAttachmentInfo<'a>,
+    stencil_attachment: &'a vk::RenderingAttachmentInfo<'a>,
+) -> vk::RenderingInfo<'a> {
+    rendering_info(extent, color_attachments, Some(depth_attachment))
+        .stencil_attachment(stencil_attachment)
+}
+
+// every draw is broadcast to the views in view_mask, view i goes to layer i of
+// the attachments, e.g. 0b111111 for the faces of a cube shadow map. the
+// attachment views need at least as many layers and the pipelines have to
+// be created with the same RasterPipelineDesc::view_mask
+pub fn multiview_rendering_info<'a>(
+    extent: vk::Extent2D,
+    view_mask: u32,
+    color_attachments: &'a [vk::RenderingAttachmentInfo<'a>],
+    depth_attachment: Option<&'a vk::RenderingAttachmentInfo<'a>>,
+) -> vk::RenderingInfo<'a> {
+    rendering_info(extent, color_attachments, depth_attachment).view_mask(view_mask)
+}
@@ -0,0 +1,177 @@
+use std::marker::PhantomData;
+
+use super::buffer::Buffer;
+use super::image::Image;
+
+// index into a SlotMap, the generation makes handles to removed or reused
+// slots detectable instead of silently aliasing a newer resource
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+// manual impls so handles are Copy regardless of T
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+pub type BufferHandle = Handle<Buffer>;
+pub type ImageHandle = Handle<Image>;
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+pub struct SlotMap<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for SlotMap<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> SlotMap<T> {
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize].value = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        Handle {
+            index,
+            generation: self.slots[index as usize].generation,
+            _marker: PhantomData,
+        }
+    }
+
+    // None for stale handles
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?;
+        let value = slot.value.take()?;
+        // bumping the generation invalidates every copy of the handle
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.value.take().is_some() {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+            }
+        }
+    }
+}
+
+// resources hold an Arc to the device, so everything still registered when
+// shutting down has to be cleared before the device can be dropped
+#[derive(Default)]
+pub struct ResourceManager {
+    pub buffers: SlotMap<Buffer>,
+    pub images: SlotMap<Image>,
+}
+
+impl ResourceManager {
+    pub fn insert_buffer(&mut self, buffer: Buffer) -> BufferHandle {
+        self.buffers.insert(buffer)
+    }
+
+    pub fn insert_image(&mut self, image: Image) -> ImageHandle {
+        self.images.insert(image)
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> Option<&Buffer> {
+        self.buffers.get(handle)
+    }
+
+    pub fn image(&self, handle: ImageHandle) -> Option<&Image> {
+        self.images.get(handle)
+    }
+
+    pub fn image_mut(&mut self, handle: ImageHandle) -> Option<&mut Image> {
+        self.images.get_mut(handle)
+    }
+
+    // the returned resource is destroyed when dropped, hand it to
+    // Device::defer_destroy if frames in flight may still use it
+    pub fn remove_buffer(&mut self, handle: BufferHandle) -> Option<Buffer> {
+        self.buffers.remove(handle)
+    }
+
+    pub fn remove_image(&mut self, handle: ImageHandle) -> Option<Image> {
+        self.images.remove(handle)
+    }
+
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+        self.images.clear();
+    }
+}
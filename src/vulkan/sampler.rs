@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use log::warn;
+use std::sync::Arc;
+
+use super::device::{Device, DeviceFeature};
+
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    // None disables anisotropic filtering
+    pub max_anisotropy: Option<f32>,
+    // Some makes this a comparison sampler for depth textures
+    pub compare_op: Option<vk::CompareOp>,
+    // only used by CLAMP_TO_BORDER
+    pub border_color: vk::BorderColor,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self::linear()
+    }
+}
+
+impl SamplerDesc {
+    // trilinear filtering with repeating coordinates
+    pub fn linear() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+            compare_op: None,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_BLACK,
+        }
+    }
+
+    // linear filtering of the comparison results gives 2x2 pcf for free. the
+    // white border makes everything outside the shadow map lit. bind it as a
+    // separate sampler next to the depth texture, see update_sampler
+    pub fn shadow() -> Self {
+        Self {
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            compare_op: Some(vk::CompareOp::LESS),
+            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            ..Self::linear().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        }
+    }
+
+    pub fn nearest() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..Self::linear()
+        }
+    }
+
+    // the same mode on every axis
+    pub fn with_address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode_u = address_mode;
+        self.address_mode_v = address_mode;
+        self.address_mode_w = address_mode;
+        self
+    }
+
+    // clamped to maxSamplerAnisotropy when the sampler is created
+    pub fn with_anisotropy(mut self, level: f32) -> Self {
+        self.max_anisotropy = Some(level);
+        self
+    }
+}
+
+pub struct Sampler {
+    pub raw: vk::Sampler,
+    pub desc: SamplerDesc,
+    device: Arc<Device>,
+}
+
+impl Device {
+    pub fn create_sampler(self: &Arc<Self>, desc: SamplerDesc) -> Result<Sampler> {
+        // anisotropy only affects quality, so a missing feature isn't an error
+        let max_anisotropy = match desc.max_anisotropy {
+            Some(_) if !self.is_feature_enabled(DeviceFeature::SamplerAnisotropy) => {
+                warn!("samplerAnisotropy is not enabled, creating the sampler without it");
+                None
+            }
+            Some(level) => {
+                let max_level = self
+                    .physical_device
+                    .properties
+                    .limits
+                    .max_sampler_anisotropy;
+                Some(level.clamp(1.0, max_level))
+            }
+            None => None,
+        };
+
+        let create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(desc.mag_filter)
+            .min_filter(desc.min_filter)
+            .mipmap_mode(desc.mipmap_mode)
+            .address_mode_u(desc.address_mode_u)
+            .address_mode_v(desc.address_mode_v)
+            .address_mode_w(desc.address_mode_w)
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .compare_enable(desc.compare_op.is_some())
+            .compare_op(desc.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .border_color(desc.border_color)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let raw = unsafe {
+            self.raw
+                .create_sampler(&create_info, None)
+                .context("Failed to create sampler")?
+        };
+
+        Ok(Sampler {
+            raw,
+            desc,
+            device: self.clone(),
+        })
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe { self.device.raw.destroy_sampler(self.raw, None) };
+    }
+}
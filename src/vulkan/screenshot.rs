@@ -0,0 +1,64 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use std::path::Path;
+
+// tightly packed rgba8 rows, top row first. the values are stored as they were
+// presented, which is already srgb encoded for both the _SRGB and _UNORM
+// swapchain formats, so no transfer function is applied
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+// bytes per texel of the formats that can be converted to rgba8
+pub(crate) fn texel_size(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::B8G8R8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R8G8B8A8_UNORM => Some(4),
+        _ => None,
+    }
+}
+
+impl Screenshot {
+    pub(crate) fn from_texels(
+        texels: &[u8],
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Self> {
+        let mut pixels = texels.to_vec();
+        match format {
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => {
+                pixels
+                    .chunks_exact_mut(4)
+                    .for_each(|texel| texel.swap(0, 2));
+            }
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => {}
+            _ => anyhow::bail!("Can't convert {format:?} to rgba8"),
+        }
+
+        Ok(Self {
+            width: extent.width,
+            height: extent.height,
+            pixels,
+        })
+    }
+
+    pub fn save_png<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        image::save_buffer_with_format(
+            path,
+            &self.pixels,
+            self.width,
+            self.height,
+            image::ExtendedColorType::Rgba8,
+            image::ImageFormat::Png,
+        )
+        .with_context(|| format!("Failed to save screenshot to {}", path.display()))
+    }
+}
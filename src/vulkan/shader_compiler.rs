@@ -1,6 +1,14 @@
 use anyhow::{Context, Result};
-use log::info;
-use std::{cell::OnceCell, ffi::CStr, path::Path};
+use log::{info, warn};
+use std::{
+    cell::OnceCell,
+    ffi::{CStr, CString},
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use shader_slang::{self as slang, Downcast};
 
@@ -8,6 +16,10 @@ use bytes::Bytes;
 
 static SEARCH_PATH: &CStr = c"assets/shaders/slang";
 
+pub fn default_search_path() -> &'static Path {
+    Path::new(SEARCH_PATH.to_str().unwrap())
+}
+
 thread_local! {
     static SLANG_GLOBAL_SESSION: OnceCell<slang::GlobalSession> = const { OnceCell::new() };
 }
@@ -23,28 +35,275 @@ where
     })
 }
 
-pub struct ShaderCompiler {}
+// set by build.rs from the locked slang crates, so stale spirv gets dropped
+// when they are updated
+const SLANG_VERSION: &str = env!("SLANG_COMPILER_VERSION");
+
+#[derive(Clone)]
+pub struct ShaderCompilerOptions {
+    // slang profile name, e.g. glsl_450 or spirv_1_6
+    pub profile: String,
+    pub target: slang::CompileTarget,
+    pub optimization: slang::OptimizationLevel,
+    pub matrix_layout_row: bool,
+    // preprocessor macros as (name, value) pairs
+    pub defines: Vec<(String, String)>,
+}
+
+impl Default for ShaderCompilerOptions {
+    fn default() -> Self {
+        Self {
+            profile: "glsl_450".to_string(),
+            target: slang::CompileTarget::Spirv,
+            optimization: slang::OptimizationLevel::Default,
+            matrix_layout_row: true,
+            defines: Vec::new(),
+        }
+    }
+}
+
+impl Hash for ShaderCompilerOptions {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.profile.hash(state);
+        (self.target as i64).hash(state);
+        (self.optimization as i64).hash(state);
+        self.matrix_layout_row.hash(state);
+        self.defines.hash(state);
+    }
+}
+
+pub struct ShaderCompiler {
+    search_paths: Vec<PathBuf>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl Default for ShaderCompiler {
+    fn default() -> Self {
+        Self {
+            search_paths: vec![default_search_path().to_path_buf()],
+            cache_dir: None,
+        }
+    }
+}
 
 impl ShaderCompiler {
-    pub fn compile_slang<P>(path: P) -> Result<CompiledShader>
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // replaces the default search path, searched in order for imports and includes
+    pub fn with_search_paths<I, P>(mut self, search_paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.search_paths = search_paths.into_iter().map(Into::into).collect();
+        if self.search_paths.is_empty() {
+            self.search_paths.push(default_search_path().to_path_buf());
+        }
+        self
+    }
+
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    // spirv is cached by source and options hash, and only reused while none of
+    // the files slang loaded for it changed
+    pub fn with_cache_dir<P>(mut self, cache_dir: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    pub fn compile_slang<P>(
+        &self,
+        path: P,
+        entry_point: &str,
+        options: &ShaderCompilerOptions,
+    ) -> Result<CompiledShader>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
 
+        let shader_name = path
+            .file_stem()
+            .expect("Failed to get shader filename")
+            .to_string_lossy()
+            .to_string();
+
+        let cache_path = self
+            .cache_dir
+            .as_ref()
+            .and_then(|cache_dir| Some((cache_dir, self.source_hash(path, entry_point, options)?)))
+            .map(|(cache_dir, hash)| {
+                cache_dir.join(format!("{shader_name}.{entry_point}.{hash:016x}.spv"))
+            });
+
+        if let Some(cache_path) = &cache_path
+            && dependencies_unchanged(&cache_path.with_extension("deps"))
+            && let Ok(spirv) = fs::read(cache_path)
+        {
+            info!("Loaded {}::{} from cache", shader_name, entry_point);
+            return Ok(CompiledShader {
+                name: shader_name,
+                entry_point: entry_point.to_string(),
+                spirv: Bytes::from(spirv),
+                source_path: Some(path.to_path_buf()),
+                options: options.clone(),
+            });
+        }
+
+        let (spirv, dependencies) = self.compile(path, entry_point, options)?;
+
+        info!(
+            "Compiled {}::{} ({} bytes)",
+            shader_name,
+            entry_point,
+            spirv.len()
+        );
+
+        if let Some(cache_path) = &cache_path {
+            let written = cache_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(cache_path, &spirv))
+                .and_then(|_| {
+                    write_dependencies(&cache_path.with_extension("deps"), &dependencies)
+                });
+            if let Err(e) = written {
+                warn!("Failed to write {}: {e}", cache_path.display());
+            }
+        }
+
+        Ok(CompiledShader {
+            name: shader_name,
+            entry_point: entry_point.to_string(),
+            spirv,
+            source_path: Some(path.to_path_buf()),
+            options: options.clone(),
+        })
+    }
+
+    // compiles (path, entry point) pairs on worker threads, the results are in
+    // the order of shaders
+    pub fn compile_many<P>(
+        &self,
+        shaders: &[(P, &str)],
+        options: &ShaderCompilerOptions,
+    ) -> Vec<Result<CompiledShader>>
+    where
+        P: AsRef<Path> + Sync,
+    {
+        let next_index = AtomicUsize::new(0);
+        let thread_count = std::thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(shaders.len());
+
+        let mut results = std::thread::scope(|scope| {
+            let workers = (0..thread_count)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        loop {
+                            let index = next_index.fetch_add(1, Ordering::Relaxed);
+                            let Some((path, entry_point)) = shaders.get(index) else {
+                                break results;
+                            };
+                            let path = path.as_ref();
+                            let shader = self
+                                .compile_slang(path, entry_point, options)
+                                .with_context(|| {
+                                    format!("Failed to compile {}::{entry_point}", path.display())
+                                });
+                            results.push((index, shader));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("Shader compiler worker panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, shader)| shader).collect()
+    }
+
+    // slang resolves module paths against the search paths first
+    fn resolve_source(&self, path: &Path) -> Option<PathBuf> {
+        self.search_paths
+            .iter()
+            .map(|search_path| search_path.join(path))
+            .chain([path.to_path_buf()])
+            .find(|path| path.is_file())
+    }
+
+    fn source_hash(
+        &self,
+        path: &Path,
+        entry_point: &str,
+        options: &ShaderCompilerOptions,
+    ) -> Option<u64> {
+        let source = fs::read(self.resolve_source(path)?).ok()?;
+
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        entry_point.hash(&mut hasher);
+        SLANG_VERSION.hash(&mut hasher);
+        options.hash(&mut hasher);
+        self.search_paths.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    // also returns every file slang loaded for the module, the module itself included
+    fn compile(
+        &self,
+        path: &Path,
+        entry_point_name: &str,
+        options: &ShaderCompilerOptions,
+    ) -> Result<(Bytes, Vec<PathBuf>)> {
+        let search_paths = self
+            .search_paths
+            .iter()
+            .map(|search_path| CString::new(search_path.to_string_lossy().as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Search path contains a nul byte")?;
+        let search_path_ptrs = search_paths
+            .iter()
+            .map(|search_path| search_path.as_ptr())
+            .collect::<Vec<_>>();
+
         with_slang_global_session(|global_session| {
             let compiler_options = slang::CompilerOptions::default()
-                .emit_spirv_directly(true)
-                .matrix_layout_row(true);
+                .emit_spirv_directly(matches!(options.target, slang::CompileTarget::Spirv))
+                .optimization(options.optimization)
+                // keep the entry point name instead of renaming it to main
+                .vulkan_use_entry_point_name(true);
+            let compiler_options = if options.matrix_layout_row {
+                compiler_options.matrix_layout_row(true)
+            } else {
+                compiler_options.matrix_layout_column(true)
+            };
+            let compiler_options = options
+                .defines
+                .iter()
+                .fold(compiler_options, |compiler_options, (name, value)| {
+                    compiler_options.macro_define(name, value)
+                });
             let target_desc = slang::TargetDesc::default()
-                .format(slang::CompileTarget::Spirv)
-                .profile(global_session.find_profile("glsl_450"));
+                .format(options.target)
+                .profile(global_session.find_profile(&options.profile));
 
             let targets = [target_desc];
-            let search_paths = [SEARCH_PATH.as_ptr()];
             let session_desc = slang::SessionDesc::default()
                 .targets(&targets)
-                .search_paths(&search_paths)
+                .search_paths(&search_path_ptrs)
                 .options(&compiler_options);
 
             let session = global_session
@@ -52,42 +311,137 @@ impl ShaderCompiler {
                 .context("Failed to create slang session")?;
             let module = session
                 .load_module(&path.to_string_lossy())
-                .context("Failed to load slang module")?;
+                .map_err(diagnostics)
+                .with_context(|| format!("Failed to load slang module {}", path.display()))?;
+            let dependencies = (0..module.dependency_file_count())
+                .map(|index| PathBuf::from(module.dependency_file_path(index)))
+                .collect::<Vec<_>>();
             let entry_point = module
-                .find_entry_point_by_name("main")
-                .context("Failed to find entry point")?;
+                .find_entry_point_by_name(entry_point_name)
+                .with_context(|| format!("Failed to find entry point {entry_point_name}"))?;
 
             let program = session
                 .create_composite_component_type(&[
                     module.downcast().clone(),
                     entry_point.downcast().clone(),
                 ])
+                .map_err(diagnostics)
                 .context("Failed to create shader program")?;
-            let linked_program = program.link().context("Failed to link shader program")?;
+            let linked_program = program
+                .link()
+                .map_err(diagnostics)
+                .context("Failed to link shader program")?;
 
             let shader_bytecode = linked_program
                 .entry_point_code(0, 0)
-                .context("Failed to find entry point in shader")?;
+                .map_err(diagnostics)
+                .context("Failed to generate code for entry point")?;
 
-            let shader_name = path
-                .file_stem()
-                .expect("Failed to get shader filename")
-                .to_string_lossy()
-                .to_string();
+            Ok((
+                Bytes::copy_from_slice(shader_bytecode.as_slice()),
+                dependencies,
+            ))
+        })
+    }
+}
 
-            let spirv = Bytes::copy_from_slice(shader_bytecode.as_slice());
+// None if any of the files can't be read
+fn dependencies_hash(dependencies: &[PathBuf]) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    for dependency in dependencies {
+        dependency.hash(&mut hasher);
+        fs::read(dependency).ok()?.hash(&mut hasher);
+    }
+    Some(hasher.finish())
+}
 
-            info!("Compiled {} ({} bytes)", shader_name, spirv.len());
+// the first line is the hash of the dependencies' contents, then one path per line
+fn write_dependencies(deps_path: &Path, dependencies: &[PathBuf]) -> std::io::Result<()> {
+    let hash = dependencies_hash(dependencies).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "Failed to read a dependency")
+    })?;
+    let mut contents = format!("{hash:016x}\n");
+    for dependency in dependencies {
+        contents.push_str(&dependency.to_string_lossy());
+        contents.push('\n');
+    }
+    fs::write(deps_path, contents)
+}
 
-            Ok(CompiledShader {
-                name: shader_name,
-                spirv,
-            })
-        })
+// a missing or unreadable list counts as changed
+fn dependencies_unchanged(deps_path: &Path) -> bool {
+    let Ok(contents) = fs::read_to_string(deps_path) else {
+        return false;
+    };
+    let mut lines = contents.lines();
+    let Some(stored_hash) = lines
+        .next()
+        .and_then(|hash| u64::from_str_radix(hash, 16).ok())
+    else {
+        return false;
+    };
+    let dependencies = lines.map(PathBuf::from).collect::<Vec<_>>();
+    dependencies_hash(&dependencies) == Some(stored_hash)
+}
+
+// slang reports errors with file and line in a diagnostics blob, keep its text
+// instead of the bare result code
+fn diagnostics(error: slang::Error) -> anyhow::Error {
+    match error {
+        slang::Error::Blob(blob) => {
+            anyhow::anyhow!("{}", String::from_utf8_lossy(blob.as_slice()).trim_end())
+        }
+        error => anyhow::anyhow!("{error:?}"),
     }
 }
 
 pub struct CompiledShader {
     pub name: String,
+    pub entry_point: String,
     pub spirv: Bytes,
+    pub source_path: Option<PathBuf>,
+    pub options: ShaderCompilerOptions,
+}
+
+impl CompiledShader {
+    const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+    // offline compiled spirv, the entry point is assumed to be main
+    pub fn from_spirv_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .expect("Failed to get shader filename")
+            .to_string_lossy()
+            .to_string();
+        let spirv = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+        Self::from_spirv_bytes(name, spirv)
+    }
+
+    pub fn from_spirv_bytes<B>(name: impl Into<String>, spirv: B) -> Result<Self>
+    where
+        B: Into<Bytes>,
+    {
+        let name = name.into();
+        let spirv = spirv.into();
+
+        let magic = spirv
+            .first_chunk::<4>()
+            .map(|magic| u32::from_le_bytes(*magic));
+        if spirv.len() % 4 != 0 || magic != Some(Self::SPIRV_MAGIC) {
+            anyhow::bail!("{name} is not valid spirv");
+        }
+
+        Ok(Self {
+            name,
+            entry_point: "main".to_string(),
+            spirv,
+            source_path: None,
+            options: ShaderCompilerOptions::default(),
+        })
+    }
 }
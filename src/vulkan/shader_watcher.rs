@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+use log::warn;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+pub struct ShaderWatcher {
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl ShaderWatcher {
+    pub fn new<P>(search_paths: &[P]) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })
+        .context("Failed to create shader watcher")?;
+
+        for path in search_paths {
+            watcher
+                .watch(path.as_ref(), RecursiveMode::Recursive)
+                .with_context(|| format!("Failed to watch {}", path.as_ref().display()))?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    // drains pending events and returns the slang files that changed since the
+    // last call, without blocking
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Shader watcher error: {e}");
+                    continue;
+                }
+            };
+
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+
+            for path in event.paths {
+                let is_slang = path.extension().is_some_and(|ext| ext == "slang");
+                if is_slang && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+
+        changed
+    }
+}
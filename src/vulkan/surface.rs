@@ -1,7 +1,8 @@
 use anyhow::Result;
 use ash::vk;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use std::sync::Arc;
+
+use super::instance::Instance;
 
 pub struct Surface {
     pub raw: vk::SurfaceKHR,
@@ -10,20 +11,20 @@ pub struct Surface {
 
 impl Surface {
     pub fn new(
-        device: &Arc<super::device::Device>,
+        instance: &Instance,
         window: &(impl HasDisplayHandle + HasWindowHandle),
     ) -> Result<Self> {
         let raw = unsafe {
             ash_window::create_surface(
-                &device.instance.entry,
-                &device.instance.raw,
+                &instance.entry,
+                &instance.raw,
                 window.display_handle().unwrap().as_raw(),
                 window.window_handle().unwrap().as_raw(),
                 None,
             )?
         };
 
-        let loader = ash::khr::surface::Instance::new(&device.instance.entry, &device.instance.raw);
+        let loader = ash::khr::surface::Instance::new(&instance.entry, &instance.raw);
         Ok(Self { raw, loader })
     }
 }
@@ -3,18 +3,56 @@ use std::sync::Arc;
 use anyhow::Result;
 use ash::vk;
 use ash::vk::SwapchainCreateInfoKHR;
-use log::info;
+use log::{info, warn};
+use vk_sync::{AccessType, ImageLayout};
 
 use super::device;
+use super::image::{Image, format_aspect_mask};
+use super::profiling;
 use super::surface;
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PresentModePreference {
+    // mailbox, falling back to fifo
+    AutoVsync,
+    // fifo relaxed or immediate, falling back to fifo
+    AutoNoVsync,
+    Mailbox,
+    Immediate,
+    Fifo,
+}
+
+impl PresentModePreference {
+    pub fn is_vsync(self) -> bool {
+        !matches!(self, Self::AutoNoVsync | Self::Immediate)
+    }
+
+    fn candidates(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            Self::AutoVsync => &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+            Self::AutoNoVsync => &[
+                vk::PresentModeKHR::FIFO_RELAXED,
+                vk::PresentModeKHR::IMMEDIATE,
+            ],
+            Self::Mailbox => &[vk::PresentModeKHR::MAILBOX],
+            Self::Immediate => &[vk::PresentModeKHR::IMMEDIATE],
+            Self::Fifo => &[vk::PresentModeKHR::FIFO],
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SwapchainDesc {
     pub old_swapchain: Option<vk::SwapchainKHR>,
     pub format: vk::SurfaceFormatKHR,
-    pub vsync: bool,
+    pub present_mode: PresentModePreference,
 }
 
+// the frame's graphics submit has to wait on acquire_semaphore and signal
+// present_semaphore, present_image waits on present_semaphore. acquire
+// semaphores come from a pool and are only handed out again once the frame
+// that waited on them has finished, present semaphores belong to the image
+// and are reused when the image is acquired again
 #[derive(Copy, Clone)]
 pub struct SwapchainSync {
     pub acquire_semaphore: vk::Semaphore,
@@ -25,21 +63,45 @@ pub struct SwapchainImage {
     pub image: vk::Image,
     pub image_view: vk::ImageView,
     pub image_index: u32,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
     pub sync: SwapchainSync,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SwapchainRebuild {
+    SameImageCount,
+    ImageCountChanged { old: usize, new: usize },
+    // the surface has a zero extent, rendering is paused until a rebuild succeeds
+    Minimized,
+}
+
+#[must_use]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SwapchainStatus {
+    Optimal,
+    // the swapchain no longer matches the surface, rebuild it before the next frame
+    NeedsRebuild,
+}
+
 pub struct Swapchain {
     pub loader: ash::khr::swapchain::Device,
     pub desc: SwapchainDesc,
 
     pub raw: vk::SwapchainKHR,
     extent: vk::Extent2D,
+    present_mode: vk::PresentModeKHR,
+    supported_present_modes: Vec<vk::PresentModeKHR>,
+    image_usage: vk::ImageUsageFlags,
 
     images: Vec<vk::Image>,
     image_views: Vec<vk::ImageView>,
 
-    syncs: Vec<SwapchainSync>,
-    sync_index: usize,
+    // indexed by image index
+    present_semaphores: Vec<vk::Semaphore>,
+    // with the absolute frame index they were last acquired in
+    acquire_semaphores: Vec<(vk::Semaphore, Option<usize>)>,
+    minimized: bool,
 
     device: Arc<device::Device>,
     surface: Arc<surface::Surface>,
@@ -57,21 +119,58 @@ impl Swapchain {
         }
     }
 
-    pub fn new(
-        device: &Arc<device::Device>,
-        surface: &Arc<surface::Surface>,
-        desc: SwapchainDesc,
-    ) -> Result<Self> {
-        let loader = ash::khr::swapchain::Device::new(&device.instance.raw, &device.raw);
-
-        let surface_capabilities = unsafe {
-            surface
-                .loader
-                .get_physical_device_surface_capabilities(device.physical_device.raw, surface.raw)?
+    // hdr10 when requested and available, otherwise srgb
+    pub fn choose_surface_format(
+        supported_formats: &[vk::SurfaceFormatKHR],
+        hdr: bool,
+    ) -> Result<vk::SurfaceFormatKHR> {
+        let hdr_formats = [
+            vk::SurfaceFormatKHR {
+                format: vk::Format::A2B10G10R10_UNORM_PACK32,
+                color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            },
+            vk::SurfaceFormatKHR {
+                format: vk::Format::A2R10G10B10_UNORM_PACK32,
+                color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            },
+        ];
+        let sdr_format = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_SRGB,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
         };
 
-        let mut extent = surface_capabilities.current_extent;
-        extent = vk::Extent2D {
+        if hdr {
+            if let Some(format) = hdr_formats
+                .into_iter()
+                .find(|format| supported_formats.contains(format))
+            {
+                return Ok(format);
+            }
+            warn!("HDR requested but no HDR10 surface format is available, using sRGB");
+        }
+
+        if !supported_formats.contains(&sdr_format) {
+            anyhow::bail!("Surface format not available");
+        }
+        Ok(sdr_format)
+    }
+
+    fn surface_capabilities(
+        device: &device::Device,
+        surface: &surface::Surface,
+    ) -> Result<vk::SurfaceCapabilitiesKHR> {
+        unsafe {
+            Ok(surface.loader.get_physical_device_surface_capabilities(
+                device.physical_device.raw,
+                surface.raw,
+            )?)
+        }
+    }
+
+    // windows report a zero extent while minimized
+    fn surface_extent(surface_capabilities: &vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
+        let extent = surface_capabilities.current_extent;
+        vk::Extent2D {
             width: extent.width.clamp(
                 surface_capabilities.min_image_extent.width,
                 surface_capabilities.max_image_extent.width,
@@ -80,11 +179,28 @@ impl Swapchain {
                 surface_capabilities.min_image_extent.height,
                 surface_capabilities.max_image_extent.height,
             ),
-        };
-        if extent.width == 0 && extent.height == 0 {
+        }
+    }
+
+    pub fn new(
+        device: &Arc<device::Device>,
+        surface: &Arc<surface::Surface>,
+        desc: SwapchainDesc,
+    ) -> Result<Self> {
+        let loader = ash::khr::swapchain::Device::new(&device.instance.raw, &device.raw);
+
+        let surface_capabilities = Self::surface_capabilities(device, surface)?;
+        let extent = Self::surface_extent(&surface_capabilities);
+        if extent.width == 0 || extent.height == 0 {
             anyhow::bail!("Swapchain extent cannot be zero");
         }
 
+        // transfer dst lets offscreen render targets be blitted in,
+        // transfer src lets presented frames be captured
+        let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        image_usage |= surface_capabilities.supported_usage_flags
+            & (vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC);
+
         let mut image_count = surface_capabilities.min_image_count + 1;
         if surface_capabilities.max_image_count > 0
             && image_count > surface_capabilities.max_image_count
@@ -99,19 +215,24 @@ impl Swapchain {
             )?
         };
 
-        let present_mode_preference = if desc.vsync {
-            [vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO]
-        } else {
-            [
-                vk::PresentModeKHR::FIFO_RELAXED,
-                vk::PresentModeKHR::IMMEDIATE,
-            ]
-        };
-
-        let chosen_present_mode = present_mode_preference
-            .into_iter()
+        // fifo is the only mode that is always supported
+        let chosen_present_mode = desc
+            .present_mode
+            .candidates()
+            .iter()
+            .copied()
             .find(|mode| present_modes.contains(mode))
             .unwrap_or(vk::PresentModeKHR::FIFO);
+        if !desc
+            .present_mode
+            .candidates()
+            .contains(&chosen_present_mode)
+        {
+            warn!(
+                "Present mode {:?} is not supported, falling back to {:?}",
+                desc.present_mode, chosen_present_mode
+            );
+        }
 
         let mut create_info = SwapchainCreateInfoKHR::default()
             .surface(surface.raw)
@@ -120,7 +241,7 @@ impl Swapchain {
             .image_color_space(desc.format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(surface_capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -136,7 +257,7 @@ impl Swapchain {
         info!("Created swapchain: {}x{}", extent.width, extent.height);
 
         let images = unsafe { loader.get_swapchain_images(raw)? };
-        let image_views = images
+        let image_views: Vec<_> = images
             .iter()
             .map(|image| unsafe {
                 let image_view_create_info = vk::ImageViewCreateInfo::default()
@@ -163,77 +284,353 @@ impl Swapchain {
             })
             .collect();
 
-        let mut syncs = Vec::with_capacity(images.len());
-        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
-        for _ in &images {
-            let acquire_semaphore =
-                unsafe { device.raw.create_semaphore(&semaphore_create_info, None)? };
-            let present_semaphore =
-                unsafe { device.raw.create_semaphore(&semaphore_create_info, None)? };
-            syncs.push(SwapchainSync {
-                acquire_semaphore,
-                present_semaphore,
-            });
+        for (index, (&image, &image_view)) in images.iter().zip(&image_views).enumerate() {
+            device.set_debug_name(image, &format!("swapchain image {index}"));
+            device.set_debug_name(image_view, &format!("swapchain image view {index}"));
         }
 
+        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+        let present_semaphores = images
+            .iter()
+            .map(|_| unsafe { device.raw.create_semaphore(&semaphore_create_info, None) })
+            .collect::<Result<Vec<_>, _>>()?;
+        // one more than the image count so an acquire never has to wait for
+        // the semaphore of the image that is currently being presented
+        let acquire_semaphores = (0..images.len() + 1)
+            .map(|_| unsafe { device.raw.create_semaphore(&semaphore_create_info, None) })
+            .map(|semaphore| semaphore.map(|semaphore| (semaphore, None)))
+            .collect::<Result<Vec<_>, _>>()?;
+
         Ok(Self {
             raw,
             loader,
             desc,
             extent,
+            present_mode: chosen_present_mode,
+            supported_present_modes: present_modes,
+            image_usage,
             device: device.clone(),
             surface: surface.clone(),
-            syncs,
-            sync_index: 0,
+            present_semaphores,
+            acquire_semaphores,
+            minimized: false,
             images,
             image_views,
         })
     }
 
-    pub fn rebuild(&mut self) -> Result<()> {
+    // recreates the images and their semaphores, callers should recreate
+    // anything that depends on the image count if it changed
+    pub fn rebuild(&mut self) -> Result<SwapchainRebuild> {
         unsafe {
             self.device.raw.device_wait_idle()?;
         }
 
+        // keep the old swapchain around until the surface can be presented to again
+        let extent =
+            Self::surface_extent(&Self::surface_capabilities(&self.device, &self.surface)?);
+        if extent.width == 0 || extent.height == 0 {
+            if !self.minimized {
+                info!("Surface has a zero extent, pausing rendering");
+            }
+            self.minimized = true;
+            return Ok(SwapchainRebuild::Minimized);
+        }
+
         let desc = SwapchainDesc {
             old_swapchain: Some(self.raw),
             ..self.desc
         };
 
+        let old_image_count = self.images.len();
         let mut new_swapchain = Self::new(&self.device, &self.surface, desc)?;
         std::mem::swap(self, &mut new_swapchain);
 
-        Ok(())
+        let new_image_count = self.images.len();
+        if new_image_count == old_image_count {
+            Ok(SwapchainRebuild::SameImageCount)
+        } else {
+            info!("Swapchain image count changed: {old_image_count} -> {new_image_count}");
+            Ok(SwapchainRebuild::ImageCountChanged {
+                old: old_image_count,
+                new: new_image_count,
+            })
+        }
+    }
+
+    // switches present modes by rebuilding, rebuild waits for in flight frames
+    pub fn set_vsync(&mut self, vsync: bool) -> Result<SwapchainRebuild> {
+        let present_mode = if vsync {
+            PresentModePreference::AutoVsync
+        } else {
+            PresentModePreference::AutoNoVsync
+        };
+        self.set_present_mode(present_mode)
+    }
+
+    pub fn set_present_mode(
+        &mut self,
+        present_mode: PresentModePreference,
+    ) -> Result<SwapchainRebuild> {
+        self.desc.present_mode = present_mode;
+        self.rebuild()
+    }
+
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
+    // as reported by the surface when the swapchain was last built
+    pub fn supported_present_modes(&self) -> &[vk::PresentModeKHR] {
+        &self.supported_present_modes
+    }
+
+    // set by a rebuild against a zero extent, skip frames until a rebuild clears it
+    pub fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    pub fn image_usage(&self) -> vk::ImageUsageFlags {
+        self.image_usage
+    }
+
+    pub fn image_count(&self) -> usize {
+        self.images.len()
     }
 
     pub fn get_extent(&self) -> vk::Extent2D {
         self.extent
     }
 
-    pub fn acquire_next_image(&mut self) -> Result<SwapchainImage> {
-        self.sync_index += 1;
-        let sync = self.syncs[self.sync_index % self.images.len()];
+    pub fn format(&self) -> vk::Format {
+        self.desc.format.format
+    }
+
+    // HDR10_ST2084_EXT means output is expected in pq encoded rec2020
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.desc.format.color_space
+    }
+
+    // returns None when the swapchain is out of date, in which case nothing was
+    // acquired and the caller must rebuild before trying again
+    // call after Device::begin_frame, the acquired image has to be submitted
+    // and presented within the same frame
+    pub fn acquire_next_image(&mut self) -> Result<Option<SwapchainImage>> {
+        let frame = self.device.absolute_frame_index();
+        let slot = self.free_acquire_semaphore(frame)?;
+        let acquire_semaphore = self.acquire_semaphores[slot].0;
 
-        let image_index = unsafe {
+        let result = unsafe {
             self.loader
-                .acquire_next_image(
-                    self.raw,
-                    u64::MAX,
-                    sync.acquire_semaphore,
-                    vk::Fence::null(),
-                )
-                .map(|(index, _)| index)?
+                .acquire_next_image(self.raw, u64::MAX, acquire_semaphore, vk::Fence::null())
+        };
+
+        // a suboptimal image is still acquired and has to be presented,
+        // present_image will ask for the rebuild afterwards. a failed acquire
+        // leaves the semaphore unsignaled so it stays free
+        let image_index = match result {
+            Ok((image_index, _suboptimal)) => image_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        self.acquire_semaphores[slot].1 = Some(frame);
+
+        let sync = SwapchainSync {
+            acquire_semaphore,
+            present_semaphore: self.present_semaphores[image_index as usize],
         };
 
-        Ok(SwapchainImage {
+        Ok(Some(SwapchainImage {
             image: self.images[image_index as usize],
             image_view: self.image_views[image_index as usize],
             image_index,
+            extent: self.extent,
+            format: self.desc.format.format,
             sync,
-        })
+        }))
+    }
+
+    // scales src_image to the whole swapchain image, the swapchain image is
+    // expected to be freshly acquired and is left as a color attachment so
+    // overlays can still be drawn on top before presenting. src_image goes back
+    // to the access it had before the blit
+    pub fn cmd_blit_to_swapchain(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        src_image: &mut Image,
+        swapchain_image: &SwapchainImage,
+        filter: vk::Filter,
+    ) -> Result<()> {
+        if !self.image_usage.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+            anyhow::bail!("Surface doesn't support blitting into swapchain images");
+        }
+        if !src_image
+            .desc
+            .usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            anyhow::bail!(
+                "Image {} is missing TRANSFER_SRC usage",
+                src_image.desc.name
+            );
+        }
+        if format_aspect_mask(src_image.format()) != vk::ImageAspectFlags::COLOR {
+            anyhow::bail!("Only color images can be blitted to the swapchain");
+        }
+        // blits can't resolve, multisampled images need cmd_resolve_image first
+        if src_image.desc.samples != vk::SampleCountFlags::TYPE_1 {
+            anyhow::bail!(
+                "Image {} is multisampled and has to be resolved before blitting",
+                src_image.desc.name
+            );
+        }
+
+        let src_features = self.optimal_tiling_features(src_image.format());
+        let dst_features = self.optimal_tiling_features(swapchain_image.format);
+        let mut required_src_features = vk::FormatFeatureFlags::BLIT_SRC;
+        if filter == vk::Filter::LINEAR {
+            required_src_features |= vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        }
+        if !src_features.contains(required_src_features) {
+            anyhow::bail!(
+                "Format {:?} can't be blitted from with {filter:?} filtering",
+                src_image.format()
+            );
+        }
+        if !dst_features.contains(vk::FormatFeatureFlags::BLIT_DST) {
+            anyhow::bail!(
+                "Swapchain format {:?} can't be blitted to",
+                swapchain_image.format
+            );
+        }
+        // blits convert between formats except between integer and non integer ones
+        if is_integer_format(src_image.format()) != is_integer_format(swapchain_image.format) {
+            anyhow::bail!(
+                "Can't blit between {:?} and {:?}",
+                src_image.format(),
+                swapchain_image.format
+            );
+        }
+
+        let src_previous_access = src_image.current_access();
+        src_image.transition(command_buffer, AccessType::TransferRead);
+
+        let color_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_transfer_dst = vk_sync::ImageBarrier {
+            previous_accesses: &[AccessType::Nothing],
+            next_accesses: &[AccessType::TransferWrite],
+            previous_layout: ImageLayout::Optimal,
+            next_layout: ImageLayout::Optimal,
+            discard_contents: true,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: swapchain_image.image,
+            range: color_range,
+        };
+        let to_attachment = vk_sync::ImageBarrier {
+            previous_accesses: &[AccessType::TransferWrite],
+            next_accesses: &[AccessType::ColorAttachmentWrite],
+            discard_contents: false,
+            ..to_transfer_dst
+        };
+        vk_sync::cmd::pipeline_barrier(
+            &self.device.raw,
+            command_buffer,
+            None,
+            &[],
+            &[to_transfer_dst],
+        );
+
+        let src_extent = src_image.extent();
+        let subresource = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        let blit = vk::ImageBlit::default()
+            .src_subresource(subresource)
+            .src_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: src_extent.width as i32,
+                    y: src_extent.height as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(subresource)
+            .dst_offsets([
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: swapchain_image.extent.width as i32,
+                    y: swapchain_image.extent.height as i32,
+                    z: 1,
+                },
+            ]);
+        unsafe {
+            self.device.raw.cmd_blit_image(
+                command_buffer,
+                src_image.raw,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                std::slice::from_ref(&blit),
+                filter,
+            )
+        };
+
+        vk_sync::cmd::pipeline_barrier(
+            &self.device.raw,
+            command_buffer,
+            None,
+            &[],
+            &[to_attachment],
+        );
+        if src_previous_access != AccessType::Nothing {
+            src_image.transition(command_buffer, src_previous_access);
+        }
+
+        Ok(())
     }
 
-    pub fn present_image(&self, swapchain_image: SwapchainImage) {
+    fn optimal_tiling_features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        unsafe {
+            self.device
+                .instance
+                .raw
+                .get_physical_device_format_properties(self.device.physical_device.raw, format)
+                .optimal_tiling_features
+        }
+    }
+
+    // a semaphore is free once begin_frame has waited for the frame that was
+    // submitted waiting on it
+    fn free_acquire_semaphore(&mut self, frame: usize) -> Result<usize> {
+        let frames_in_flight = self.device.frames_in_flight();
+        let free = self.acquire_semaphores.iter().position(|(_, last_frame)| {
+            last_frame.is_none_or(|last_frame| last_frame + frames_in_flight <= frame)
+        });
+        if let Some(slot) = free {
+            return Ok(slot);
+        }
+
+        // only happens with more frames in flight than swapchain images
+        let semaphore = unsafe {
+            self.device
+                .raw
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
+        };
+        self.acquire_semaphores.push((semaphore, None));
+        Ok(self.acquire_semaphores.len() - 1)
+    }
+
+    // callers must rebuild before the next frame on NeedsRebuild
+    pub fn present_image(&self, swapchain_image: SwapchainImage) -> Result<SwapchainStatus> {
+        let _zone = profiling::zone("present");
+
         let present_info = vk::PresentInfoKHR::default()
             .wait_semaphores(std::slice::from_ref(
                 &swapchain_image.sync.present_semaphore,
@@ -246,11 +643,9 @@ impl Swapchain {
                 .queue_present(self.device.graphics_queue.raw, &present_info)
         };
         match res {
-            Ok(_) => {}
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) | Err(vk::Result::SUBOPTIMAL_KHR) => {}
-            Err(e) => {
-                panic!("Failed to present image: {e:?}");
-            }
+            Ok(false) => Ok(SwapchainStatus::Optimal),
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(SwapchainStatus::NeedsRebuild),
+            Err(e) => Err(anyhow::anyhow!("Failed to present image: {e:?}")),
         }
     }
 }
@@ -258,13 +653,11 @@ impl Swapchain {
 impl Drop for Swapchain {
     fn drop(&mut self) {
         unsafe {
-            for sync in &self.syncs {
-                self.device
-                    .raw
-                    .destroy_semaphore(sync.acquire_semaphore, None);
-                self.device
-                    .raw
-                    .destroy_semaphore(sync.present_semaphore, None);
+            for (semaphore, _) in &self.acquire_semaphores {
+                self.device.raw.destroy_semaphore(*semaphore, None);
+            }
+            for semaphore in &self.present_semaphores {
+                self.device.raw.destroy_semaphore(*semaphore, None);
             }
             for image_view in &self.image_views {
                 self.device.raw.destroy_image_view(*image_view, None);
@@ -273,3 +666,31 @@ impl Drop for Swapchain {
         }
     }
 }
+
+fn is_integer_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8_UINT
+            | vk::Format::R8_SINT
+            | vk::Format::R8G8_UINT
+            | vk::Format::R8G8_SINT
+            | vk::Format::R8G8B8A8_UINT
+            | vk::Format::R8G8B8A8_SINT
+            | vk::Format::B8G8R8A8_UINT
+            | vk::Format::B8G8R8A8_SINT
+            | vk::Format::A2B10G10R10_UINT_PACK32
+            | vk::Format::A2R10G10B10_UINT_PACK32
+            | vk::Format::R16_UINT
+            | vk::Format::R16_SINT
+            | vk::Format::R16G16_UINT
+            | vk::Format::R16G16_SINT
+            | vk::Format::R16G16B16A16_UINT
+            | vk::Format::R16G16B16A16_SINT
+            | vk::Format::R32_UINT
+            | vk::Format::R32_SINT
+            | vk::Format::R32G32_UINT
+            | vk::Format::R32G32_SINT
+            | vk::Format::R32G32B32A32_UINT
+            | vk::Format::R32G32B32A32_SINT
+    )
+}
@@ -0,0 +1,545 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use std::path::Path;
+use std::sync::Arc;
+use vk_sync::AccessType;
+
+use super::device::{Device, DeviceFeature};
+use super::image::{self, Image, ImageDesc};
+use super::upload::Uploader;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+// identifier, 9 header fields and the dfd/kvd/sgd index
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+
+// the parts of a ktx2 file needed to upload it, level 0 is the largest mip
+struct Ktx2<'a> {
+    format: vk::Format,
+    extent: vk::Extent2D,
+    levels: Vec<&'a [u8]>,
+}
+
+impl<'a> Ktx2<'a> {
+    // only single layer 2d textures without supercompression are supported
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.get(..12) != Some(&KTX2_IDENTIFIER[..]) {
+            anyhow::bail!("Not a ktx2 file");
+        }
+        let read_u32 = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .context("Truncated ktx2 header")
+        };
+        let read_u64 = |offset: usize| {
+            data.get(offset..offset + 8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .context("Truncated ktx2 level index")
+        };
+
+        let format = vk::Format::from_raw(read_u32(12)? as i32);
+        let extent = vk::Extent2D {
+            width: read_u32(20)?,
+            height: read_u32(24)?,
+        };
+        let depth = read_u32(28)?;
+        let layer_count = read_u32(32)?;
+        let face_count = read_u32(36)?;
+        let level_count = read_u32(40)?;
+        let supercompression_scheme = read_u32(44)?;
+
+        if format == vk::Format::UNDEFINED {
+            anyhow::bail!("Basis universal ktx2 files have to be transcoded first");
+        }
+        if supercompression_scheme != 0 {
+            anyhow::bail!("Supercompressed ktx2 files are not supported");
+        }
+        if extent.height == 0 || depth > 1 || layer_count > 1 || face_count != 1 {
+            anyhow::bail!("Only 2d ktx2 textures without layers or faces are supported");
+        }
+        // 0 asks the loader to generate mips, which isn't possible for compressed formats
+        if level_count == 0 {
+            anyhow::bail!("Ktx2 file has no mip levels stored");
+        }
+        if level_count > mip_level_count(extent) {
+            anyhow::bail!(
+                "Ktx2 file has {level_count} mip levels but a {}x{} image has at most {}",
+                extent.width,
+                extent.height,
+                mip_level_count(extent)
+            );
+        }
+        let (block_size, block_bytes) =
+            format_block(format).with_context(|| format!("{format:?} is not supported"))?;
+
+        let levels = (0..level_count as usize)
+            .map(|level| {
+                let entry = KTX2_LEVEL_INDEX_OFFSET + level * 24;
+                let offset = read_u64(entry)?;
+                let length = read_u64(entry + 8)?;
+                let bytes = offset
+                    .checked_add(length)
+                    .and_then(|end| {
+                        data.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?)
+                    })
+                    .with_context(|| format!("Ktx2 mip level {level} is out of bounds"))?;
+
+                // the copy reads whole blocks, a shorter level would read past it
+                let blocks = |size: u32| (size >> level).max(1).div_ceil(block_size) as u64;
+                let needed = blocks(extent.width) * blocks(extent.height) * block_bytes;
+                if length != needed {
+                    anyhow::bail!(
+                        "Ktx2 mip level {level} has {length} bytes but {needed} are needed"
+                    );
+                }
+                Ok(bytes)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            format,
+            extent,
+            levels,
+        })
+    }
+}
+
+// block width and height in texels and bytes per block, uncompressed formats
+// have 1x1 blocks
+fn format_block(format: vk::Format) -> Option<(u32, u64)> {
+    let block = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => (1, 1),
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB | vk::Format::R16_SFLOAT => (1, 2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::E5B9G9R9_UFLOAT_PACK32 => (1, 4),
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT => (1, 8),
+        vk::Format::R32G32B32A32_SFLOAT => (1, 16),
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK
+        | vk::Format::EAC_R11_UNORM_BLOCK
+        | vk::Format::EAC_R11_SNORM_BLOCK => (4, 8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK
+        | vk::Format::EAC_R11G11_UNORM_BLOCK
+        | vk::Format::EAC_R11G11_SNORM_BLOCK => (4, 16),
+        _ => return None,
+    };
+    Some(block)
+}
+
+fn is_bc_format(format: vk::Format) -> bool {
+    (vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::BC7_SRGB_BLOCK.as_raw())
+        .contains(&format.as_raw())
+}
+
+// full mip chain down to 1x1
+pub fn mip_level_count(extent: vk::Extent2D) -> u32 {
+    32 - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
+// a sampled image that is ready to be read by shaders, view covers every mip
+pub struct Texture {
+    pub image: Image,
+}
+
+impl Texture {
+    // decodes png or jpeg files, grayscale and rgb images are expanded to rgba.
+    // the texels are treated as srgb encoded like most color textures
+    pub fn from_file<P>(device: &Arc<Device>, path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let decoded = ::image::open(path)
+            .with_context(|| format!("Failed to load texture {}", path.display()))?
+            .into_rgba8();
+        let extent = vk::Extent2D {
+            width: decoded.width(),
+            height: decoded.height(),
+        };
+
+        Self::from_rgba8(
+            device,
+            path.to_string_lossy(),
+            extent,
+            vk::Format::R8G8B8A8_SRGB,
+            decoded.as_raw(),
+        )
+    }
+
+    // uploads tightly packed rgba8 texels as mip 0 and generates the rest
+    pub fn from_rgba8(
+        device: &Arc<Device>,
+        name: impl Into<String>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        pixels: &[u8],
+    ) -> Result<Self> {
+        let desc = ImageDesc::new_2d(name, extent, format, vk::ImageUsageFlags::empty());
+        Self::from_rgba8_layers(device, desc, &[pixels])
+    }
+
+    // a 2d array with one layer per file, sampled as a Texture2DArray. every
+    // file has to have the same size
+    pub fn array_from_files<P>(device: &Arc<Device>, paths: &[P]) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let layers = paths
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                ::image::open(path)
+                    .with_context(|| format!("Failed to load texture layer {}", path.display()))
+                    .map(|layer| layer.into_rgba8())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let first_layer = layers
+            .first()
+            .context("Texture arrays need at least one layer")?;
+        let extent = vk::Extent2D {
+            width: first_layer.width(),
+            height: first_layer.height(),
+        };
+
+        // the explicit view type keeps single layer arrays arrayed
+        let desc = ImageDesc::new_2d(
+            "texture array",
+            extent,
+            vk::Format::R8G8B8A8_SRGB,
+            vk::ImageUsageFlags::empty(),
+        )
+        .array_layers(layers.len() as u32)
+        .view_type(vk::ImageViewType::TYPE_2D_ARRAY);
+        let texels = layers
+            .iter()
+            .map(|layer| layer.as_raw().as_slice())
+            .collect::<Vec<_>>();
+        Self::from_rgba8_layers(device, desc, &texels)
+    }
+
+    // desc gives the name, extent, format and layout, usage and mips are filled in
+    fn from_rgba8_layers(device: &Arc<Device>, desc: ImageDesc, layers: &[&[u8]]) -> Result<Self> {
+        let extent = vk::Extent2D {
+            width: desc.extent.width,
+            height: desc.extent.height,
+        };
+        let expected_size = extent.width as usize * extent.height as usize * 4;
+        if let Some(layer) = layers.iter().find(|layer| layer.len() != expected_size) {
+            anyhow::bail!(
+                "Expected {expected_size} bytes of rgba8 texels but got {}",
+                layer.len()
+            );
+        }
+
+        let mip_levels = mip_level_count(extent);
+        let layer_count = desc.array_layers;
+        let format = desc.format;
+        let mut image = device.create_image(ImageDesc {
+            usage: vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            mip_levels,
+            ..desc
+        })?;
+
+        // the copy can run on the transfer queue, blits need the graphics queue
+        let mut uploader = Uploader::new(device.clone())?;
+        uploader.upload_image_layers(layers, &mut image, extent.into())?;
+        uploader.flush()?;
+
+        device.submit_immediate(|command_buffer| {
+            image::generate_mipmaps(
+                device,
+                command_buffer,
+                image.raw,
+                extent,
+                mip_levels,
+                layer_count,
+                format,
+                image.current_access(),
+            )
+        })?;
+        image.set_current_access(AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer);
+
+        Ok(Self { image })
+    }
+
+    // uploads every mip stored in the file as is, no mips are generated
+    pub fn from_ktx2<P>(device: &Arc<Device>, path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read texture {}", path.display()))?;
+        let ktx2 =
+            Ktx2::parse(&data).with_context(|| format!("Failed to parse {}", path.display()))?;
+        let format = ktx2.format;
+
+        if is_bc_format(format) && !device.is_feature_enabled(DeviceFeature::TextureCompressionBc) {
+            anyhow::bail!(
+                "{} uses {format:?}, which requires the textureCompressionBC feature",
+                path.display()
+            );
+        }
+        let format_properties = unsafe {
+            device
+                .instance
+                .raw
+                .get_physical_device_format_properties(device.physical_device.raw, format)
+        };
+        if !format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST)
+        {
+            anyhow::bail!(
+                "{format:?} of {} can't be sampled on this device",
+                path.display()
+            );
+        }
+
+        // every level starts at an offset that is valid for any block size
+        let mut texels = Vec::new();
+        let mut regions = Vec::with_capacity(ktx2.levels.len());
+        for (mip_level, level) in ktx2.levels.iter().enumerate() {
+            texels.resize(texels.len().next_multiple_of(16), 0);
+            regions.push(
+                vk::BufferImageCopy::default()
+                    .buffer_offset(texels.len() as u64)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(mip_level as u32)
+                            .layer_count(1),
+                    )
+                    .image_extent(vk::Extent3D {
+                        width: (ktx2.extent.width >> mip_level).max(1),
+                        height: (ktx2.extent.height >> mip_level).max(1),
+                        depth: 1,
+                    }),
+            );
+            texels.extend_from_slice(level);
+        }
+
+        let mut image = device.create_image(
+            ImageDesc::new_2d(
+                path.to_string_lossy(),
+                ktx2.extent,
+                format,
+                vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .mip_levels(regions.len() as u32),
+        )?;
+
+        let mut uploader = Uploader::new(device.clone())?;
+        uploader.upload_image_regions(&texels, &mut image, &regions)?;
+        uploader.flush()?;
+
+        Ok(Self { image })
+    }
+
+    // the faces are in +x, -x, +y, -y, +z, -z order and have to be square and
+    // of the same size. cubemaps only get mip 0
+    pub fn cube_from_files<P>(device: &Arc<Device>, faces: [P; 6]) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let faces = faces
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                ::image::open(path)
+                    .with_context(|| format!("Failed to load cube face {}", path.display()))
+                    .map(|face| face.into_rgba8())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_cube_faces(device, faces[0].width(), &faces)
+    }
+
+    // a horizontal cross, 4 faces wide and 3 high:
+    //     +y
+    //  -x +z +x -z
+    //     -y
+    pub fn cube_from_cross<P>(device: &Arc<Device>, path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let cross = ::image::open(path)
+            .with_context(|| format!("Failed to load cubemap {}", path.display()))?
+            .into_rgba8();
+        let size = cross.width() / 4;
+        if size == 0 || cross.width() != size * 4 || cross.height() != size * 3 {
+            anyhow::bail!(
+                "{} is {}x{}, a horizontal cross has to be 4:3",
+                path.display(),
+                cross.width(),
+                cross.height()
+            );
+        }
+
+        // column and row of each face in the cross
+        let face_cells = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+        let faces = face_cells
+            .map(|(column, row)| {
+                ::image::imageops::crop_imm(&cross, column * size, row * size, size, size)
+                    .to_image()
+            })
+            .to_vec();
+        Self::from_cube_faces(device, size, &faces)
+    }
+
+    fn from_cube_faces(
+        device: &Arc<Device>,
+        size: u32,
+        faces: &[::image::RgbaImage],
+    ) -> Result<Self> {
+        if faces
+            .iter()
+            .any(|face| face.width() != size || face.height() != size)
+        {
+            anyhow::bail!("Cube faces have to be square and {size}x{size}");
+        }
+
+        let mut image = device.create_image(ImageDesc::new_cube(
+            "cubemap",
+            size,
+            vk::Format::R8G8B8A8_SRGB,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        ))?;
+
+        // upload_image takes every layer tightly packed one after another
+        let texels = faces
+            .iter()
+            .flat_map(|face| face.as_raw().iter().copied())
+            .collect::<Vec<_>>();
+        let extent = image.extent();
+        let mut uploader = Uploader::new(device.clone())?;
+        uploader.upload_image(&texels, &mut image, extent)?;
+        uploader.flush()?;
+
+        Ok(Self { image })
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.image.view
+    }
+
+    pub fn extent(&self) -> vk::Extent3D {
+        self.image.extent()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KTX2_IDENTIFIER, KTX2_LEVEL_INDEX_OFFSET, Ktx2};
+    use ash::vk;
+
+    // a 4x4 rgba8 file with a full mip chain, the levels follow the index
+    fn ktx2_file(level_count: u32) -> Vec<u8> {
+        let mut data = KTX2_IDENTIFIER.to_vec();
+        let header = [
+            vk::Format::R8G8B8A8_UNORM.as_raw() as u32,
+            1,
+            4,
+            4,
+            0,
+            0,
+            1,
+            level_count,
+            0,
+        ];
+        data.extend(header.iter().flat_map(|field| field.to_le_bytes()));
+        data.resize(KTX2_LEVEL_INDEX_OFFSET, 0);
+
+        let level_sizes = [64u64, 16, 4];
+        let mut offset = (KTX2_LEVEL_INDEX_OFFSET + 24 * level_count as usize) as u64;
+        for &size in level_sizes.iter().take(level_count as usize) {
+            data.extend(offset.to_le_bytes());
+            data.extend(size.to_le_bytes());
+            data.extend(size.to_le_bytes());
+            offset += size;
+        }
+        data.resize(offset as usize, 0xff);
+        data
+    }
+
+    fn set_u64(data: &mut [u8], offset: usize, value: u64) {
+        data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn parses_every_level() {
+        let data = ktx2_file(3);
+        let ktx2 = Ktx2::parse(&data).unwrap();
+        assert_eq!(ktx2.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(
+            ktx2.extent,
+            vk::Extent2D {
+                width: 4,
+                height: 4
+            }
+        );
+        let lengths = ktx2
+            .levels
+            .iter()
+            .map(|level| level.len())
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, [64, 16, 4]);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = ktx2_file(3);
+        assert!(Ktx2::parse(&data[..40]).is_err());
+        assert!(Ktx2::parse(&data[..KTX2_LEVEL_INDEX_OFFSET + 30]).is_err());
+        assert!(Ktx2::parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_levels() {
+        let mut data = ktx2_file(1);
+        set_u64(&mut data, KTX2_LEVEL_INDEX_OFFSET, u64::MAX - 8);
+        assert!(Ktx2::parse(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_short_levels() {
+        let mut data = ktx2_file(1);
+        set_u64(&mut data, KTX2_LEVEL_INDEX_OFFSET + 8, 32);
+        assert!(Ktx2::parse(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_more_levels_than_the_extent_has() {
+        let mut data = ktx2_file(3);
+        data[40..44].copy_from_slice(&4u32.to_le_bytes());
+        assert!(Ktx2::parse(&data).is_err());
+    }
+}
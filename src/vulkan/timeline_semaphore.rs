@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use ash::vk;
+
+use super::device::SemaphoreSubmit;
+
+// a timeline semaphore starting at 0. the frame helpers are for the
+// graphics timeline, where frame n signals n + 1 so 0 means no frame has
+// finished yet
+pub struct TimelineSemaphore {
+    pub raw: vk::Semaphore,
+    device: ash::Device,
+}
+
+impl TimelineSemaphore {
+    pub fn new(device: &ash::Device) -> Result<Self> {
+        let mut type_create_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_create_info);
+        let raw = unsafe {
+            device
+                .create_semaphore(&create_info, None)
+                .context("Failed to create timeline semaphore")?
+        };
+
+        Ok(Self {
+            raw,
+            device: device.clone(),
+        })
+    }
+
+    // the value the submit of the given absolute frame signals
+    pub fn signal_value_for_frame(absolute_frame_index: usize) -> u64 {
+        absolute_frame_index as u64 + 1
+    }
+
+    // the value that has to be reached before the frame can reuse the
+    // resources of its slot, None while the first frames are still in flight
+    pub fn wait_value_for_frame(
+        absolute_frame_index: usize,
+        frames_in_flight: usize,
+    ) -> Option<u64> {
+        absolute_frame_index
+            .checked_sub(frames_in_flight)
+            .map(Self::signal_value_for_frame)
+    }
+
+    pub fn wait(&self, value: u64) -> Result<()> {
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(std::slice::from_ref(&self.raw))
+            .values(std::slice::from_ref(&value));
+        unsafe {
+            self.device
+                .wait_semaphores(&wait_info, u64::MAX)
+                .context("Failed to wait for timeline semaphore")?
+        };
+        Ok(())
+    }
+
+    pub fn current_value(&self) -> Result<u64> {
+        let value = unsafe {
+            self.device
+                .get_semaphore_counter_value(self.raw)
+                .context("Failed to query timeline semaphore")?
+        };
+        Ok(value)
+    }
+
+    // for SubmitDesc::wait
+    pub fn wait_info(&self, value: u64, stage_mask: vk::PipelineStageFlags2) -> SemaphoreSubmit {
+        SemaphoreSubmit::timeline(self.raw, value, stage_mask)
+    }
+
+    // for SubmitDesc::signal
+    pub fn signal_info(&self, value: u64, stage_mask: vk::PipelineStageFlags2) -> SemaphoreSubmit {
+        SemaphoreSubmit::timeline(self.raw, value, stage_mask)
+    }
+
+    // not a Drop impl, the owner destroys it once the gpu is done with it,
+    // e.g. the Device before destroying itself
+    pub(crate) fn destroy(&self) {
+        unsafe { self.device.destroy_semaphore(self.raw, None) };
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+use super::buffer::{Buffer, BufferDesc};
+use super::device::Device;
+
+#[derive(Copy, Clone, Debug)]
+pub struct UniformAllocation {
+    pub buffer: vk::Buffer,
+    // passed as the dynamic offset of a UNIFORM_BUFFER_DYNAMIC descriptor
+    pub offset: u32,
+    pub size: u64,
+}
+
+// one host visible buffer per frame in flight, a frame's buffer is only
+// rewritten once Device::begin_frame has waited for it
+pub struct UniformRing {
+    device: Arc<Device>,
+    buffers: Vec<Buffer>,
+    alignment: u64,
+    offset: u64,
+}
+
+impl UniformRing {
+    pub fn new(device: Arc<Device>, size_per_frame: u64) -> Result<Self> {
+        let buffers = (0..device.frames_in_flight())
+            .map(|frame| {
+                device.create_buffer(BufferDesc::new(
+                    format!("uniform ring {frame}"),
+                    size_per_frame,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    MemoryLocation::CpuToGpu,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let alignment = device
+            .physical_device
+            .properties
+            .limits
+            .min_uniform_buffer_offset_alignment
+            .max(1);
+
+        Ok(Self {
+            device,
+            buffers,
+            alignment,
+            offset: 0,
+        })
+    }
+
+    // call after Device::begin_frame
+    pub fn begin_frame(&mut self) {
+        self.offset = 0;
+    }
+
+    // the buffer the current frame allocates from, descriptors are written once
+    // per frame in flight and only the dynamic offset changes per draw
+    pub fn buffer(&self, frame_index: usize) -> &Buffer {
+        &self.buffers[frame_index]
+    }
+
+    pub fn push<T: bytemuck::Pod>(&mut self, data: &T) -> Result<UniformAllocation> {
+        let size = std::mem::size_of::<T>() as u64;
+        let offset = self.offset.next_multiple_of(self.alignment);
+        let buffer = &mut self.buffers[self.device.frame_index()];
+        assert!(
+            offset + size <= buffer.size(),
+            "Uniform ring overflowed its {} byte frame budget",
+            buffer.size()
+        );
+
+        buffer.write_slice(std::slice::from_ref(data), offset as usize)?;
+        self.offset = offset + size;
+
+        Ok(UniformAllocation {
+            buffer: buffer.raw,
+            offset: offset as u32,
+            size,
+        })
+    }
+}
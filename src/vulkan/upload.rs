@@ -0,0 +1,620 @@
+use anyhow::{Context, Result};
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use vk_sync::AccessType;
+
+use super::buffer::{Buffer, BufferDesc};
+use super::device::{Device, Queue};
+use super::image::{Image, format_aspect_mask};
+
+struct UploadCommands {
+    queue: Queue,
+    pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+}
+
+impl UploadCommands {
+    fn new(device: &Device, queue: Queue) -> Result<Self> {
+        let pool_create_info = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue.family);
+        let pool = unsafe { device.raw.create_command_pool(&pool_create_info, None)? };
+
+        let alloc_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = unsafe { device.raw.allocate_command_buffers(&alloc_info)?[0] };
+
+        Ok(Self {
+            queue,
+            pool,
+            command_buffer,
+        })
+    }
+
+    fn begin(&self, device: &Device) -> Result<()> {
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            device
+                .raw
+                .begin_command_buffer(self.command_buffer, &begin_info)?
+        };
+        Ok(())
+    }
+
+    fn submit(
+        &self,
+        device: &Device,
+        wait: Option<vk::SemaphoreSubmitInfo<'_>>,
+        signal: vk::SemaphoreSubmitInfo<'_>,
+    ) -> Result<()> {
+        unsafe { device.raw.end_command_buffer(self.command_buffer)? };
+
+        let command_buffer_submit_info =
+            vk::CommandBufferSubmitInfo::default().command_buffer(self.command_buffer);
+        let wait_semaphores = wait.as_slice();
+        let submit_info = vk::SubmitInfo2::default()
+            .wait_semaphore_infos(wait_semaphores)
+            .signal_semaphore_infos(std::slice::from_ref(&signal))
+            .command_buffer_infos(std::slice::from_ref(&command_buffer_submit_info));
+
+        device
+            .queue_submit2(
+                self.queue.raw,
+                std::slice::from_ref(&submit_info),
+                vk::Fence::null(),
+            )
+            .context("Failed to submit uploads")?;
+        Ok(())
+    }
+
+    fn reset(&self, device: &Device) -> Result<()> {
+        unsafe {
+            device
+                .raw
+                .reset_command_pool(self.pool, vk::CommandPoolResetFlags::empty())?
+        };
+        Ok(())
+    }
+
+    fn destroy(&self, device: &Device) {
+        unsafe { device.raw.destroy_command_pool(self.pool, None) };
+    }
+}
+
+// graphics submissions using the uploaded resources have to wait on it
+#[derive(Copy, Clone, Debug)]
+pub struct UploadToken {
+    pub semaphore: vk::Semaphore,
+    pub value: u64,
+}
+
+impl UploadToken {
+    pub fn wait_info(
+        &self,
+        stage_mask: vk::PipelineStageFlags2,
+    ) -> vk::SemaphoreSubmitInfo<'static> {
+        vk::SemaphoreSubmitInfo::default()
+            .semaphore(self.semaphore)
+            .value(self.value)
+            .stage_mask(stage_mask)
+    }
+}
+
+// resources of a submitted async upload, recycled once the timeline passes value
+struct InFlightUpload {
+    value: u64,
+    transfer: UploadCommands,
+    graphics: Option<UploadCommands>,
+    // only kept alive until the copies are done
+    _staging_buffers: Vec<Buffer>,
+}
+
+// batches staging copies into a single submission on the transfer queue,
+// everything recorded is only valid on the graphics queue after flush()
+pub struct Uploader {
+    device: Arc<Device>,
+
+    transfer: UploadCommands,
+    // used to acquire ownership when the transfer queue is a separate family
+    graphics: Option<UploadCommands>,
+
+    timeline_semaphore: vk::Semaphore,
+    timeline_value: u64,
+
+    staging_buffers: Vec<Buffer>,
+    buffer_barriers: Vec<vk::BufferMemoryBarrier2<'static>>,
+    image_barriers: Vec<vk::ImageMemoryBarrier2<'static>>,
+    recording: bool,
+
+    in_flight: VecDeque<InFlightUpload>,
+    free_transfer: Vec<UploadCommands>,
+    free_graphics: Vec<UploadCommands>,
+}
+
+impl Uploader {
+    pub fn new(device: Arc<Device>) -> Result<Self> {
+        let transfer = UploadCommands::new(&device, device.transfer_queue)?;
+        let graphics = if device.transfer_queue.family != device.graphics_queue.family {
+            Some(UploadCommands::new(&device, device.graphics_queue)?)
+        } else {
+            None
+        };
+
+        let mut timeline_semaphore_type_create_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let timeline_semaphore_create_info =
+            vk::SemaphoreCreateInfo::default().push_next(&mut timeline_semaphore_type_create_info);
+        let timeline_semaphore = unsafe {
+            device
+                .raw
+                .create_semaphore(&timeline_semaphore_create_info, None)?
+        };
+
+        Ok(Self {
+            device,
+            transfer,
+            graphics,
+            timeline_semaphore,
+            timeline_value: 0,
+            staging_buffers: Vec::new(),
+            buffer_barriers: Vec::new(),
+            image_barriers: Vec::new(),
+            recording: false,
+            in_flight: VecDeque::new(),
+            free_transfer: Vec::new(),
+            free_graphics: Vec::new(),
+        })
+    }
+
+    fn ensure_recording(&mut self) -> Result<()> {
+        if !self.recording {
+            self.transfer.begin(&self.device)?;
+            self.recording = true;
+        }
+        Ok(())
+    }
+
+    fn create_staging_buffer(&mut self, data: &[u8]) -> Result<vk::Buffer> {
+        let mut staging_buffer = self.device.create_buffer(BufferDesc::new(
+            "staging",
+            data.len() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        ))?;
+        staging_buffer.write_slice(data, 0)?;
+
+        let raw = staging_buffer.raw;
+        self.staging_buffers.push(staging_buffer);
+        Ok(raw)
+    }
+
+    pub fn upload_buffer<T: bytemuck::Pod>(
+        &mut self,
+        data: &[T],
+        dst_buffer: &Buffer,
+        dst_offset: u64,
+    ) -> Result<()> {
+        let size = std::mem::size_of_val(data) as u64;
+        if dst_offset
+            .checked_add(size)
+            .is_none_or(|end| end > dst_buffer.size())
+        {
+            anyhow::bail!(
+                "Upload of {size} bytes at offset {dst_offset} overflows buffer {}",
+                dst_buffer.desc.name
+            );
+        }
+
+        self.ensure_recording()?;
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let staging_buffer = self.create_staging_buffer(bytes)?;
+
+        let region = vk::BufferCopy {
+            src_offset: 0,
+            dst_offset,
+            size,
+        };
+        unsafe {
+            self.device.raw.cmd_copy_buffer(
+                self.transfer.command_buffer,
+                staging_buffer,
+                dst_buffer.raw,
+                std::slice::from_ref(&region),
+            )
+        };
+
+        self.buffer_barriers.push(
+            vk::BufferMemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(dst_buffer.raw)
+                .offset(dst_offset)
+                .size(size),
+        );
+
+        Ok(())
+    }
+
+    // writes mip 0 of every array layer, data has to be tightly packed,
+    // the whole image is left ready to be sampled
+    pub fn upload_image(
+        &mut self,
+        data: &[u8],
+        dst_image: &mut Image,
+        extent: vk::Extent3D,
+    ) -> Result<()> {
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(format_aspect_mask(dst_image.format()))
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(dst_image.desc.array_layers),
+            )
+            .image_extent(extent);
+        self.upload_image_regions(data, dst_image, &[region])
+    }
+
+    // writes mip 0 of each layer from its own block of tightly packed texels,
+    // layers[i] goes to array layer i and every layer has to be written
+    pub fn upload_image_layers(
+        &mut self,
+        layers: &[&[u8]],
+        dst_image: &mut Image,
+        extent: vk::Extent3D,
+    ) -> Result<()> {
+        if layers.len() != dst_image.desc.array_layers as usize {
+            anyhow::bail!(
+                "Got {} layers for {}, which has {}",
+                layers.len(),
+                dst_image.desc.name,
+                dst_image.desc.array_layers
+            );
+        }
+        let layer_size = layers.first().map_or(0, |layer| layer.len());
+        if layers.iter().any(|layer| layer.len() != layer_size) {
+            anyhow::bail!(
+                "Every layer of {} has to be the same size",
+                dst_image.desc.name
+            );
+        }
+
+        let data = layers.concat();
+        let regions = (0..layers.len())
+            .map(|layer| {
+                vk::BufferImageCopy::default()
+                    .buffer_offset((layer * layer_size) as u64)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::default()
+                            .aspect_mask(format_aspect_mask(dst_image.format()))
+                            .mip_level(0)
+                            .base_array_layer(layer as u32)
+                            .layer_count(1),
+                    )
+                    .image_extent(extent)
+            })
+            .collect::<Vec<_>>();
+        self.upload_image_regions(&data, dst_image, &regions)
+    }
+
+    // for uploads of several mips or layers from one block of data, the
+    // buffer offsets of the regions are relative to the start of data.
+    // subresources not covered by any region are left undefined, the whole
+    // image is left ready to be sampled
+    pub fn upload_image_regions(
+        &mut self,
+        data: &[u8],
+        dst_image: &mut Image,
+        regions: &[vk::BufferImageCopy],
+    ) -> Result<()> {
+        self.ensure_recording()?;
+        let staging_buffer = self.create_staging_buffer(data)?;
+
+        let range = vk::ImageSubresourceRange::default()
+            .aspect_mask(format_aspect_mask(dst_image.format()))
+            .level_count(dst_image.desc.mip_levels)
+            .layer_count(dst_image.desc.array_layers);
+
+        let to_transfer_dst = vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(dst_image.raw)
+            .subresource_range(range);
+        let dependency_info = vk::DependencyInfo::default()
+            .image_memory_barriers(std::slice::from_ref(&to_transfer_dst));
+
+        self.device
+            .cmd_pipeline_barrier2(self.transfer.command_buffer, &dependency_info);
+        unsafe {
+            self.device.raw.cmd_copy_buffer_to_image(
+                self.transfer.command_buffer,
+                staging_buffer,
+                dst_image.raw,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                regions,
+            );
+        }
+
+        self.image_barriers.push(
+            vk::ImageMemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(dst_image.raw)
+                .subresource_range(range),
+        );
+        dst_image.set_current_access(AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer);
+
+        Ok(())
+    }
+
+    // submits everything recorded so far and blocks until it has completed
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.recording {
+            return Ok(());
+        }
+
+        let wait_value = self.submit()?;
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(std::slice::from_ref(&self.timeline_semaphore))
+            .values(std::slice::from_ref(&wait_value));
+        unsafe { self.device.raw.wait_semaphores(&wait_info, u64::MAX)? };
+
+        self.transfer.reset(&self.device)?;
+        if let Some(graphics) = &self.graphics {
+            graphics.reset(&self.device)?;
+        }
+        self.staging_buffers.clear();
+
+        Ok(())
+    }
+
+    // submits everything recorded so far without waiting, the copies run on the
+    // transfer queue while the graphics queue keeps going until it waits on the token
+    pub fn flush_async(&mut self) -> Result<Option<UploadToken>> {
+        self.retire_completed()?;
+        if !self.recording {
+            return Ok(None);
+        }
+
+        let value = self.submit()?;
+
+        let transfer = match self.free_transfer.pop() {
+            Some(transfer) => transfer,
+            None => UploadCommands::new(&self.device, self.device.transfer_queue)?,
+        };
+        let graphics = match &self.graphics {
+            Some(_) => Some(match self.free_graphics.pop() {
+                Some(graphics) => graphics,
+                None => UploadCommands::new(&self.device, self.device.graphics_queue)?,
+            }),
+            None => None,
+        };
+
+        self.in_flight.push_back(InFlightUpload {
+            value,
+            transfer: std::mem::replace(&mut self.transfer, transfer),
+            graphics: std::mem::replace(&mut self.graphics, graphics),
+            _staging_buffers: std::mem::take(&mut self.staging_buffers),
+        });
+
+        Ok(Some(UploadToken {
+            semaphore: self.timeline_semaphore,
+            value,
+        }))
+    }
+
+    pub fn is_complete(&self, token: UploadToken) -> Result<bool> {
+        let value = unsafe {
+            self.device
+                .raw
+                .get_semaphore_counter_value(token.semaphore)?
+        };
+        Ok(value >= token.value)
+    }
+
+    // frees staging memory and command pools of finished async uploads
+    pub fn retire_completed(&mut self) -> Result<()> {
+        let completed = unsafe {
+            self.device
+                .raw
+                .get_semaphore_counter_value(self.timeline_semaphore)?
+        };
+
+        while let Some(upload) = self.in_flight.front()
+            && upload.value <= completed
+        {
+            let upload = self.in_flight.pop_front().unwrap();
+            upload.transfer.reset(&self.device)?;
+            self.free_transfer.push(upload.transfer);
+            if let Some(graphics) = upload.graphics {
+                graphics.reset(&self.device)?;
+                self.free_graphics.push(graphics);
+            }
+        }
+
+        Ok(())
+    }
+
+    // records the ownership barriers and submits, returns the timeline value
+    // that signals completion on the graphics queue
+    fn submit(&mut self) -> Result<u64> {
+        let device = &self.device;
+        let transfer_value = self.timeline_value + 1;
+        let transfer_signal = vk::SemaphoreSubmitInfo::default()
+            .semaphore(self.timeline_semaphore)
+            .value(transfer_value)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS);
+
+        let wait_value = if let Some(graphics) = &self.graphics {
+            // release on the transfer queue, then acquire on the graphics queue
+            // with identical barriers apart from the stages and accesses
+            let src_family = self.transfer.queue.family;
+            let dst_family = graphics.queue.family;
+
+            let release_buffers = self
+                .buffer_barriers
+                .iter()
+                .map(|barrier| {
+                    barrier
+                        .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+                        .dst_access_mask(vk::AccessFlags2::NONE)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                })
+                .collect::<Vec<_>>();
+            let release_images = self
+                .image_barriers
+                .iter()
+                .map(|barrier| {
+                    barrier
+                        .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+                        .dst_access_mask(vk::AccessFlags2::NONE)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                })
+                .collect::<Vec<_>>();
+            let acquire_buffers = self
+                .buffer_barriers
+                .iter()
+                .map(|barrier| {
+                    barrier
+                        .src_stage_mask(vk::PipelineStageFlags2::NONE)
+                        .src_access_mask(vk::AccessFlags2::NONE)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                })
+                .collect::<Vec<_>>();
+            let acquire_images = self
+                .image_barriers
+                .iter()
+                .map(|barrier| {
+                    barrier
+                        .src_stage_mask(vk::PipelineStageFlags2::NONE)
+                        .src_access_mask(vk::AccessFlags2::NONE)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                })
+                .collect::<Vec<_>>();
+
+            let release = vk::DependencyInfo::default()
+                .buffer_memory_barriers(&release_buffers)
+                .image_memory_barriers(&release_images);
+            device.cmd_pipeline_barrier2(self.transfer.command_buffer, &release);
+            self.transfer.submit(device, None, transfer_signal)?;
+
+            graphics.begin(device)?;
+            let acquire = vk::DependencyInfo::default()
+                .buffer_memory_barriers(&acquire_buffers)
+                .image_memory_barriers(&acquire_images);
+            device.cmd_pipeline_barrier2(graphics.command_buffer, &acquire);
+
+            let graphics_value = transfer_value + 1;
+            let wait = vk::SemaphoreSubmitInfo::default()
+                .semaphore(self.timeline_semaphore)
+                .value(transfer_value)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS);
+            let signal = vk::SemaphoreSubmitInfo::default()
+                .semaphore(self.timeline_semaphore)
+                .value(graphics_value)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS);
+            graphics.submit(device, Some(wait), signal)?;
+
+            graphics_value
+        } else {
+            let barriers = vk::DependencyInfo::default()
+                .buffer_memory_barriers(&self.buffer_barriers)
+                .image_memory_barriers(&self.image_barriers);
+            device.cmd_pipeline_barrier2(self.transfer.command_buffer, &barriers);
+            self.transfer.submit(device, None, transfer_signal)?;
+
+            transfer_value
+        };
+
+        self.timeline_value = wait_value;
+        self.buffer_barriers.clear();
+        self.image_barriers.clear();
+        self.recording = false;
+
+        Ok(wait_value)
+    }
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        if self.recording {
+            let _ = self.flush();
+        }
+        if self.timeline_value > 0 {
+            let wait_info = vk::SemaphoreWaitInfo::default()
+                .semaphores(std::slice::from_ref(&self.timeline_semaphore))
+                .values(std::slice::from_ref(&self.timeline_value));
+            let _ = unsafe { self.device.raw.wait_semaphores(&wait_info, u64::MAX) };
+        }
+        unsafe {
+            self.device
+                .raw
+                .destroy_semaphore(self.timeline_semaphore, None);
+        }
+
+        let in_flight = self
+            .in_flight
+            .drain(..)
+            .flat_map(|upload| std::iter::once(upload.transfer).chain(upload.graphics));
+        let free = self
+            .free_transfer
+            .drain(..)
+            .chain(self.free_graphics.drain(..));
+        for commands in in_flight.chain(free) {
+            commands.destroy(&self.device);
+        }
+        self.transfer.destroy(&self.device);
+        if let Some(graphics) = &self.graphics {
+            graphics.destroy(&self.device);
+        }
+    }
+}
+
+impl Device {
+    pub fn upload_buffer<T: bytemuck::Pod>(
+        self: &Arc<Self>,
+        data: &[T],
+        dst_buffer: &Buffer,
+    ) -> Result<()> {
+        let mut uploader = Uploader::new(self.clone())?;
+        uploader.upload_buffer(data, dst_buffer, 0)?;
+        uploader.flush()
+    }
+
+    pub fn upload_image(
+        self: &Arc<Self>,
+        data: &[u8],
+        dst_image: &mut Image,
+        extent: vk::Extent3D,
+    ) -> Result<()> {
+        let mut uploader = Uploader::new(self.clone())?;
+        uploader.upload_image(data, dst_image, extent)?;
+        uploader.flush()
+    }
+}